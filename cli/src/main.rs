@@ -1,10 +1,8 @@
 use std::io::stdin;
 
+use clap::Parser;
 use piosphere::{
-    socket::{
-        client::Client,
-        message::{Overview, ViewDeployment},
-    },
+    socket::{client::Client, message},
     PITERIA_SOCKET,
 };
 use signal_hook::{
@@ -14,6 +12,14 @@ use signal_hook::{
 
 #[tokio::main]
 async fn main() {
+    let args = CliArgs::parse();
+
+    if args.schema {
+        let schema = serde_json::to_string_pretty(&message::schema()).unwrap();
+        println!("{schema}");
+        return;
+    }
+
     println!("Starting client");
     let client = Client::new(PITERIA_SOCKET)
         .await
@@ -21,7 +27,7 @@ async fn main() {
 
     let mut buf = String::new();
     stdin().read_line(&mut buf).unwrap();
-    let res = client.request(Overview).await.expect("error in request");
+    let res = client.overview().await.expect("error in request");
     println!("Got response: {:?}", res);
 
     let mut signals = Signals::new([SIGTERM, SIGINT]).unwrap();
@@ -39,3 +45,10 @@ async fn main() {
 
     let _ = signals.await.expect("error while shutting down");
 }
+
+#[derive(Debug, Parser)]
+struct CliArgs {
+    /// Print the JSON schema of all messages and exit
+    #[arg(long)]
+    schema: bool,
+}
@@ -1,42 +1,455 @@
-use quote::quote;
-use syn::{ItemStruct, Token};
+use proc_macro2::{Ident, TokenStream};
+use proc_macro_error::{abort, abort_call_site};
+use quote::{quote, quote_spanned, ToTokens};
+use syn::{
+    parse::ParseStream, Fields, GenericArgument, Item, ItemStruct, PathArguments, Token, Type,
+};
 
-#[proc_macro_attribute]
+/// Declares the messages of the Piosphere protocol.
+///
+/// The first line names the type handling the messages, followed by the message structs.
+/// Each struct needs a `#[request(Response)]` attribute naming the type it is answered with,
+/// optionally followed by `admin` if only admins can send it:
+///
+/// ```ignore
+/// messages! {
+///     handler: crate::PiosphereService;
+///
+///     #[derive(Debug, Serialize, Deserialize)]
+///     #[request(bool, admin)]
+///     pub struct KillSession(pub usize);
+/// }
+/// ```
+///
+/// Generates the `Message` impls, the `PiosphereTag` enum with a variant for each message
+/// and `handle_request` on the handler, which passes requests through the `layers` of the
+/// handler to the `Handler` impl of their message. A message without a handler is a compile
+/// error pointing to the message.
+///
+/// For each message, a method named after it in snake case is generated on `Client`,
+/// `BlockingClient` and `Piosphere`, taking the message fields as arguments. `String` fields
+/// are taken as `&str` and `Option<String>` fields as `Option<&str>`.
+///
+/// Fields of tuple structs are named after their type, e.g. `CreateDeployment(Deployment)`
+/// becomes `create_deployment(deployment: Deployment)`. Fields of primitive types need to be
+/// named with `#[param(name)]`, e.g. `ViewDeployment(#[param(id)] String)` becomes
+/// `view_deployment(id: &str)`.
+///
+/// `schema` returns the JSON schemas of all messages and their responses, so messages
+/// and responses must implement `JsonSchema`.
+#[proc_macro]
 #[proc_macro_error::proc_macro_error]
-pub fn request(
-    attrs: proc_macro::TokenStream,
-    input: proc_macro::TokenStream,
-) -> proc_macro::TokenStream {
-    let strct: ItemStruct = syn::parse(input).expect("invalid input");
+pub fn messages(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let Messages { handler, messages } = match syn::parse(input) {
+        Ok(messages) => messages,
+        Err(e) => abort!(e.span(), "{}", e),
+    };
 
-    let id = &strct.ident;
-    let Request { response, tag } = syn::parse(attrs).expect("invalid input");
+    if messages.is_empty() {
+        abort_call_site!("at least one message must be declared");
+    }
+
+    let structs = messages.iter().map(|message| &message.item);
+    let idents: Vec<_> = messages.iter().map(|message| &message.item.ident).collect();
+
+    let impls = messages.iter().map(|Message { item, response, .. }| {
+        let ident = &item.ident;
+        quote!(
+            impl crate::socket::Message for #ident {
+                type Response = #response;
+
+                fn tag(&self) -> PiosphereTag {
+                    PiosphereTag::#ident
+                }
+            }
+        )
+    });
+
+    let admins: Vec<_> = messages
+        .iter()
+        .filter(|message| message.admin)
+        .map(|message| &message.item.ident)
+        .collect();
+
+    let admin_only = if admins.is_empty() {
+        quote!(false)
+    } else {
+        quote!(matches!(self, #(PiosphereTag::#admins)|*))
+    };
+
+    let arms = idents.iter().map(|ident| handle_arm(&handler, ident));
+
+    let methods: Vec<_> = idents
+        .iter()
+        .map(|ident| snake_case(&ident.to_string()))
+        .collect();
+
+    let client_methods: Vec<_> = messages
+        .iter()
+        .map(|message| client_method(message, false))
+        .collect();
+    let blocking_methods = messages.iter().map(|message| client_method(message, true));
+
+    let schemas = messages
+        .iter()
+        .zip(methods.iter())
+        .map(|(message, method)| {
+            let ident = &message.item.ident;
+            let admin = message.admin;
+            quote!(
+                MessageSchema {
+                    method: #method,
+                    admin: #admin,
+                    params: schemars::schema_for!(#ident),
+                    result: schemars::schema_for!(<#ident as crate::socket::Message>::Response),
+                }
+            )
+        });
 
     quote!(
-        #strct
+        #(#structs)*
+
+        #(#impls)*
+
+        /// Identifies the message type in a [PiosphereRequest][crate::socket::PiosphereRequest].
+        ///
+        /// In JSON-RPC sessions the snake cased tag is used as the method name, e.g. `view_deployment`.
+        #[derive(
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            serde::Serialize,
+            serde::Deserialize,
+            schemars::JsonSchema,
+        )]
+        #[serde(rename_all = "snake_case")]
+        pub enum PiosphereTag {
+            #(#idents),*
+        }
 
-        impl crate::socket::Message for #id {
-            type Response = #response;
+        impl PiosphereTag {
+            /// The JSON-RPC method name of the message.
+            pub fn method(&self) -> &'static str {
+                match self {
+                    #(PiosphereTag::#idents => #methods),*
+                }
+            }
 
-            fn tag(&self) -> crate::socket::PiosphereTag {
-                crate::socket::PiosphereTag::#tag
+            /// Whether the message can only be sent by an
+            /// [admin][crate::socket::session::Peer::is_admin].
+            pub fn admin_only(&self) -> bool {
+                #admin_only
             }
         }
+
+        impl #handler {
+            /// Decode the message, pass it through the layers to its handler
+            /// and encode the response.
+            pub(crate) async fn handle_request(
+                &self,
+                request: crate::socket::PiosphereRequest,
+                peer: Option<&crate::socket::session::Peer>,
+                encoding: crate::socket::Encoding,
+            ) -> crate::PiosphereResult<Vec<u8>> {
+                let crate::socket::PiosphereRequest { tag, message } = request;
+                let call = crate::layer::Call {
+                    tag,
+                    peer,
+                    started_at: std::time::Instant::now(),
+                };
+
+                match tag {
+                    #(#arms)*
+                }
+            }
+        }
+
+        impl crate::socket::client::Client {
+            #(#client_methods)*
+        }
+
+        impl crate::socket::client::BlockingClient {
+            #(#blocking_methods)*
+        }
+
+        impl crate::Piosphere {
+            #(#client_methods)*
+        }
+
+        /// JSON schemas of all messages, in the order they are declared.
+        pub fn schema() -> Vec<MessageSchema> {
+            vec![#(#schemas),*]
+        }
     )
     .into()
 }
 
-#[derive(Debug)]
-struct Request {
-    response: syn::Path,
-    tag: syn::Path,
+fn client_method(message: &Message, blocking: bool) -> TokenStream {
+    let ident = &message.item.ident;
+    let method = Ident::new(&snake_case(&ident.to_string()), ident.span());
+    let doc = format!("Send a [{ident}] and wait for the response.");
+
+    let mut params = vec![];
+    let mut values = vec![];
+
+    for (field, name) in message.item.fields.iter().zip(message.params.iter()) {
+        let (ty, value) = match Borrowed::of(&field.ty) {
+            Some(Borrowed::Str) => (quote!(&str), quote!(#name.to_string())),
+            Some(Borrowed::OptionStr) => (quote!(Option<&str>), quote!(#name.map(str::to_string))),
+            None => (field.ty.to_token_stream(), quote!(#name)),
+        };
+        params.push(quote!(#name: #ty));
+        values.push(value);
+    }
+
+    let construct = match &message.item.fields {
+        Fields::Unit => quote!(#ident),
+        Fields::Named(_) => {
+            let names = &message.params;
+            quote!(#ident { #(#names: #values),* })
+        }
+        Fields::Unnamed(_) => quote!(#ident(#(#values),*)),
+    };
+
+    let response = quote!(<#ident as crate::socket::Message>::Response);
+
+    if blocking {
+        quote!(
+            #[doc = #doc]
+            pub fn #method(&mut self, #(#params),*) -> crate::PiosphereResult<#response> {
+                self.request(#construct)
+            }
+        )
+    } else {
+        quote!(
+            #[doc = #doc]
+            pub async fn #method(&self, #(#params),*) -> crate::PiosphereResult<#response> {
+                self.request(#construct).await
+            }
+        )
+    }
+}
+
+/// Same as serde's `rename_all = "snake_case"` for variants.
+fn snake_case(ident: &str) -> String {
+    let mut snake = String::new();
+    for (i, ch) in ident.char_indices() {
+        if i > 0 && ch.is_uppercase() {
+            snake.push('_');
+        }
+        snake.push(ch.to_ascii_lowercase());
+    }
+    snake
+}
+
+/// Field types taken by reference in the generated methods.
+enum Borrowed {
+    /// `String`, taken as `&str`.
+    Str,
+
+    /// `Option<String>`, taken as `Option<&str>`.
+    OptionStr,
+}
+
+impl Borrowed {
+    fn of(ty: &Type) -> Option<Self> {
+        if is_ident(ty, "String") {
+            return Some(Self::Str);
+        }
+
+        let Type::Path(path) = ty else {
+            return None;
+        };
+        let segment = path.path.segments.last()?;
+        let PathArguments::AngleBracketed(ref args) = segment.arguments else {
+            return None;
+        };
+
+        match args.args.first() {
+            Some(GenericArgument::Type(inner))
+                if segment.ident == "Option" && is_ident(inner, "String") =>
+            {
+                Some(Self::OptionStr)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether `ty` is a path ending in `name`, without generic arguments.
+fn is_ident(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == name && segment.arguments.is_empty()),
+        _ => false,
+    }
+}
+
+/// Types which say nothing about what the value is, fields of these need a `#[param(name)]`.
+const PRIMITIVES: &[&str] = &[
+    "String", "str", "bool", "char", "usize", "isize", "u8", "u16", "u32", "u64", "u128", "i8",
+    "i16", "i32", "i64", "i128", "f32", "f64", "Option", "Vec", "PathBuf",
+];
+
+/// The parameter name of a tuple field, taken from `#[param(name)]` or its type,
+/// e.g. `crate::deployment::Deployment` -> `deployment`.
+fn param_name(field: &mut syn::Field) -> syn::Result<Ident> {
+    let mut name = None;
+
+    for (i, attr) in field.attrs.iter().enumerate() {
+        if !attr.path().is_ident("param") {
+            continue;
+        }
+        if name.is_some() {
+            abort!(attr, "duplicate `param` attribute");
+        }
+        name = Some((i, attr.parse_args::<Ident>()?));
+    }
+
+    if let Some((i, name)) = name {
+        field.attrs.remove(i);
+        return Ok(name);
+    }
+
+    let segment = match field.ty {
+        Type::Path(ref path) => path.path.segments.last(),
+        _ => None,
+    };
+
+    match segment {
+        Some(segment) if !PRIMITIVES.contains(&segment.ident.to_string().as_str()) => {
+            Ok(Ident::new(
+                &snake_case(&segment.ident.to_string()),
+                segment.ident.span(),
+            ))
+        }
+        _ => abort!(
+            field.ty,
+            "the parameter of this field cannot be named after its type";
+            help = "name it with `#[param(name)]`"
+        ),
+    }
+}
+
+/// The handler call is spanned to the message so a missing `Handler` impl is reported there.
+fn handle_arm(handler: &Type, ident: &Ident) -> TokenStream {
+    let handle = quote_spanned!(ident.span()=>
+        |message| <#handler as crate::Handler<#ident>>::handle(self, message)
+    );
+
+    quote!(
+        PiosphereTag::#ident => {
+            let message: #ident = encoding.decode(&message)?;
+            let response = self.layers.call(&call, message, #handle).await?;
+            encoding.encode(&response)
+        }
+    )
 }
 
-impl syn::parse::Parse for Request {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let response: syn::Path = input.parse()?;
+struct Messages {
+    handler: Type,
+    messages: Vec<Message>,
+}
+
+struct Message {
+    /// The struct without the `request` and `param` attributes.
+    item: ItemStruct,
+    response: Type,
+    admin: bool,
+
+    /// Names of the fields as parameters of the generated methods.
+    params: Vec<Ident>,
+}
+
+impl syn::parse::Parse for Messages {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let kw: Ident = input.parse()?;
+        if kw != "handler" {
+            return Err(syn::Error::new(kw.span(), "expected `handler: <type>;`"));
+        }
+        input.parse::<Token![:]>()?;
+        let handler: Type = input.parse()?;
+        input.parse::<Token![;]>()?;
+
+        let mut messages = vec![];
+
+        while !input.is_empty() {
+            let item = match input.parse()? {
+                Item::Struct(item) => item,
+                item => abort!(item, "only structs can be declared as messages"),
+            };
+            messages.push(Message::from_struct(item)?);
+        }
+
+        Ok(Self { handler, messages })
+    }
+}
+
+impl Message {
+    fn from_struct(mut item: ItemStruct) -> syn::Result<Self> {
+        let mut request = None;
+
+        for (i, attr) in item.attrs.iter().enumerate() {
+            if !attr.path().is_ident("request") {
+                continue;
+            }
+            if request.is_some() {
+                abort!(attr, "duplicate `request` attribute");
+            }
+            request = Some((i, attr.parse_args_with(Self::parse_request)?));
+        }
+
+        let Some((i, (response, admin))) = request else {
+            abort!(
+                item.ident,
+                "message `{}` is missing a `#[request(Response)]` attribute", item.ident;
+                help = "add `#[request(Self)]` if the message is answered with itself"
+            );
+        };
+
+        item.attrs.remove(i);
+
+        let params = match item.fields {
+            Fields::Unit => vec![],
+            Fields::Named(ref fields) => fields
+                .named
+                .iter()
+                .map(|field| field.ident.clone().expect("named field"))
+                .collect(),
+            Fields::Unnamed(ref mut fields) => fields
+                .unnamed
+                .iter_mut()
+                .map(param_name)
+                .collect::<syn::Result<_>>()?,
+        };
+
+        Ok(Self {
+            item,
+            response,
+            admin,
+            params,
+        })
+    }
+
+    /// `Response` or `Response, admin`
+    fn parse_request(input: ParseStream) -> syn::Result<(Type, bool)> {
+        let response: Type = input.parse()?;
+
+        if input.is_empty() {
+            return Ok((response, false));
+        }
+
         input.parse::<Token![,]>()?;
-        let tag: syn::Path = input.parse()?;
-        Ok(Self { response, tag })
+        let flag: Ident = input.parse()?;
+        if flag != "admin" {
+            return Err(syn::Error::new(flag.span(), "expected `admin`"));
+        }
+
+        Ok((response, true))
     }
 }
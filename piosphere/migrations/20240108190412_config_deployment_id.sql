@@ -0,0 +1,27 @@
+-- Deployment IDs are UUIDs, the config tables referenced them as INT.
+
+ALTER TABLE nginx_configs RENAME TO nginx_configs_old;
+
+CREATE TABLE nginx_configs (
+    id TEXT NOT NULL PRIMARY KEY,
+    deployment_id TEXT NOT NULL REFERENCES deployments(id) ON DELETE CASCADE,
+    file_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO nginx_configs SELECT * FROM nginx_configs_old;
+
+DROP TABLE nginx_configs_old;
+
+ALTER TABLE sysd_configs RENAME TO sysd_configs_old;
+
+CREATE TABLE sysd_configs (
+    id TEXT NOT NULL PRIMARY KEY,
+    deployment_id TEXT NOT NULL REFERENCES deployments(id) ON DELETE CASCADE,
+    file_path TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO sysd_configs SELECT * FROM sysd_configs_old;
+
+DROP TABLE sysd_configs_old;
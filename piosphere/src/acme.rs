@@ -0,0 +1,112 @@
+//! Certificates obtained from an ACME CA, e.g. Let's Encrypt, for the servers with
+//! [Issuer::Acme][crate::deployment::nginx::Issuer::Acme].
+//!
+//! The server names are proven with HTTP-01 challenges: piosphere writes the challenges below
+//! its [data directory][crate::PiosphereBuilder::data_dir] and adds a location serving them
+//! to the servers plain HTTP requests for the names go to. The certificates are stored and
+//! renewed like the others, see [certificates][crate::certificates].
+//!
+//! Any CA implementing RFC 8555 can be used, e.g. a local [pebble] for tests:
+//!
+//! ```ignore
+//! let piosphere = Piosphere::builder()
+//!     .acme(AcmeConfig {
+//!         root_certificate: Some("pebble.minica.pem".into()),
+//!         ..AcmeConfig::new("https://localhost:14000/dir")
+//!     })
+//!     .build()
+//!     .await?;
+//! ```
+//!
+//! [pebble]: https://github.com/letsencrypt/pebble
+
+use std::path::PathBuf;
+
+use crate::{
+    certificates::write_private, error::PiosphereError, PiosphereResult, PiosphereService,
+};
+
+use self::jws::AccountKey;
+
+pub(crate) use self::client::AcmeClient;
+
+mod client;
+mod jws;
+
+/// Directory of the production CA of Let's Encrypt.
+pub const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Directory of the staging CA of Let's Encrypt, issuing untrusted certificates with
+/// higher rate limits.
+pub const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// The CA certificates are obtained from, see [PiosphereBuilder::acme][crate::PiosphereBuilder::acme].
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    /// URL of the directory of the CA, e.g. [LETS_ENCRYPT].
+    pub directory: String,
+
+    /// Contact URLs of the account, e.g. `mailto:admin@example.org`.
+    pub contact: Vec<String>,
+
+    /// A PEM certificate to trust in addition to the system roots, for CAs with
+    /// self-signed certificates such as pebble.
+    pub root_certificate: Option<PathBuf>,
+
+    /// How long before it expires a certificate is renewed.
+    pub renew_before: chrono::Duration,
+
+    /// How long to wait between checks of a pending challenge or order.
+    pub poll_interval: std::time::Duration,
+
+    /// How often the server checks for certificates to renew.
+    pub check_interval: std::time::Duration,
+}
+
+impl AcmeConfig {
+    pub fn new(directory: &str) -> Self {
+        Self {
+            directory: directory.to_string(),
+            contact: vec![],
+            root_certificate: None,
+            renew_before: chrono::Duration::days(crate::certificates::RENEW_BEFORE_DAYS),
+            poll_interval: std::time::Duration::from_secs(2),
+            check_interval: crate::certificates::CHECK_INTERVAL,
+        }
+    }
+}
+
+impl PiosphereService {
+    /// A client of the configured CA, to obtain a certificate for `names`.
+    pub(crate) fn acme_client(&self, names: &[String]) -> PiosphereResult<AcmeClient<'_>> {
+        let Some(ref acme) = self.acme else {
+            let names: Vec<_> = names.iter().map(|name| format!("`{name}`")).collect();
+            return Err(PiosphereError::Certificate(format!(
+                "Cannot obtain a certificate for {}, no ACME directory is configured",
+                names.join(", ")
+            )));
+        };
+
+        AcmeClient::new(acme, self.account_key()?)
+    }
+
+    /// The key of the ACME account, generated on first use.
+    fn account_key(&self) -> PiosphereResult<AccountKey> {
+        let path = self.resolve(&self.acme_location("account.pem"));
+
+        match std::fs::read_to_string(&path) {
+            Ok(pem) => AccountKey::from_pem(&pem),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let (key, pem) = AccountKey::generate()?;
+                write_private(&path, &pem)?;
+                Ok(key)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Location of `name` in the directory of the ACME files.
+    pub(crate) fn acme_location(&self, name: &str) -> String {
+        format!("{}/acme/{name}", self.data_dir.trim_end_matches('/'))
+    }
+}
@@ -0,0 +1,430 @@
+//! The requests of RFC 8555 needed to order a certificate with HTTP-01 challenges.
+
+use std::path::Path;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rcgen::{CertificateParams, KeyPair};
+use reqwest::{header, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use super::{jws::AccountKey, AcmeConfig};
+use crate::{certificates::Issued, error::PiosphereError, PiosphereResult};
+
+/// How many times the status of an authorization or order is checked before giving up.
+const POLL_ATTEMPTS: usize = 30;
+
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+/// Talks to the ACME CA of an [AcmeConfig] on behalf of one account.
+#[derive(Debug)]
+pub(crate) struct AcmeClient<'a> {
+    http: reqwest::Client,
+    config: &'a AcmeConfig,
+    key: AccountKey,
+    directory: Option<Directory>,
+    nonce: Option<String>,
+
+    /// URL of the account, used instead of the key once known.
+    account: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    identifier: Identifier,
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Problem>,
+}
+
+/// An error reported by the CA, see RFC 7807.
+#[derive(Debug, Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.detail, self.kind)
+    }
+}
+
+impl<'a> AcmeClient<'a> {
+    pub(crate) fn new(config: &'a AcmeConfig, key: AccountKey) -> PiosphereResult<Self> {
+        let mut http =
+            reqwest::Client::builder().user_agent(concat!("piosphere/", env!("CARGO_PKG_VERSION")));
+
+        if let Some(ref path) = config.root_certificate {
+            let pem = std::fs::read(path)?;
+            let certificate = reqwest::Certificate::from_pem(&pem).map_err(|e| {
+                PiosphereError::Certificate(format!(
+                    "Invalid root certificate {}: {e}",
+                    path.display()
+                ))
+            })?;
+            http = http.add_root_certificate(certificate);
+        }
+
+        let http = http.build().map_err(|e| {
+            PiosphereError::Certificate(format!("Could not create HTTP client: {e}"))
+        })?;
+
+        Ok(Self {
+            http,
+            config,
+            key,
+            directory: None,
+            nonce: None,
+            account: None,
+        })
+    }
+
+    /// Order a certificate for `names`, writing the challenge files to the directory
+    /// `challenges` nginx serves them from.
+    pub(crate) async fn issue(
+        &mut self,
+        names: &[String],
+        challenges: &Path,
+    ) -> PiosphereResult<Issued> {
+        self.register().await?;
+
+        let identifiers: Vec<_> = names
+            .iter()
+            .map(|name| json!({ "type": "dns", "value": name }))
+            .collect();
+        let new_order = self.directory()?.new_order.clone();
+        let response = self
+            .post(&new_order, Some(json!({ "identifiers": identifiers })))
+            .await?;
+        let url = location(&response)?;
+        let mut order: Order = parse(response).await?;
+
+        for authorization in std::mem::take(&mut order.authorizations) {
+            self.authorize(&authorization, challenges).await?;
+        }
+
+        order = self
+            .poll(&url, order, |order| order.status != "pending")
+            .await?;
+        if order.status != "ready" {
+            return Err(order_failed(&order));
+        }
+
+        let key = KeyPair::generate().map_err(key_error)?;
+        let csr = CertificateParams::new(names.to_vec())
+            .and_then(|params| params.serialize_request(&key))
+            .map_err(key_error)?;
+        let response = self
+            .post(
+                &order.finalize,
+                Some(json!({ "csr": URL_SAFE_NO_PAD.encode(csr.der()) })),
+            )
+            .await?;
+        order = parse(response).await?;
+
+        order = self
+            .poll(&url, order, |order| order.status != "processing")
+            .await?;
+        let Some(certificate) = order
+            .certificate
+            .as_ref()
+            .filter(|_| order.status == "valid")
+        else {
+            return Err(order_failed(&order));
+        };
+
+        let chain = self
+            .post(certificate, None)
+            .await?
+            .text()
+            .await
+            .map_err(request_error)?;
+
+        Ok(Issued {
+            chain,
+            key: key.serialize_pem(),
+        })
+    }
+
+    /// Fetch the directory and find or create the account of the key.
+    async fn register(&mut self) -> PiosphereResult<()> {
+        if self.account.is_some() {
+            return Ok(());
+        }
+
+        let response = self
+            .http
+            .get(&self.config.directory)
+            .send()
+            .await
+            .map_err(request_error)?;
+        self.directory = Some(parse(response).await?);
+
+        let new_account = self.directory()?.new_account.clone();
+        let response = self
+            .post(
+                &new_account,
+                Some(json!({
+                    "termsOfServiceAgreed": true,
+                    "contact": self.config.contact,
+                })),
+            )
+            .await?;
+        self.account = Some(location(&response)?);
+
+        Ok(())
+    }
+
+    /// Prove control of the identifier of the authorization at `url` with its HTTP-01
+    /// challenge, unless it is already valid.
+    async fn authorize(&mut self, url: &str, challenges: &Path) -> PiosphereResult<()> {
+        let authorization: Authorization = parse(self.post(url, None).await?).await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+
+        let name = authorization.identifier.value.clone();
+        let Some(challenge) = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.kind == "http-01")
+        else {
+            return Err(PiosphereError::Certificate(format!(
+                "The CA offers no HTTP-01 challenge for `{name}`"
+            )));
+        };
+
+        // The token is chosen by the CA, it must not escape the directory
+        if challenge.token.is_empty()
+            || !challenge
+                .token
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(PiosphereError::Certificate(format!(
+                "Invalid challenge token `{}` for `{name}`",
+                challenge.token
+            )));
+        }
+
+        let file = challenges.join(&challenge.token);
+        let key_authorization = format!("{}.{}", challenge.token, self.key.thumbprint());
+        std::fs::create_dir_all(challenges)?;
+        std::fs::write(&file, key_authorization)?;
+
+        let challenge_url = challenge.url.clone();
+        let result = async {
+            self.post(&challenge_url, Some(json!({}))).await?;
+            self.poll(url, authorization, |authorization| {
+                authorization.status != "pending"
+            })
+            .await
+        }
+        .await;
+
+        if let Err(e) = std::fs::remove_file(&file) {
+            println!("Could not remove challenge {}: {e}", file.display());
+        }
+
+        let authorization = result?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+
+        let problem = authorization
+            .challenges
+            .iter()
+            .find_map(|challenge| challenge.error.as_ref())
+            .map(|problem| format!(": {problem}"))
+            .unwrap_or_default();
+        Err(PiosphereError::Certificate(format!(
+            "Could not validate `{name}`, the authorization is {}{problem}",
+            authorization.status
+        )))
+    }
+
+    /// Fetch the resource at `url` until `done` is true for it, starting with `current`.
+    async fn poll<T: DeserializeOwned>(
+        &mut self,
+        url: &str,
+        current: T,
+        done: impl Fn(&T) -> bool,
+    ) -> PiosphereResult<T> {
+        if done(&current) {
+            return Ok(current);
+        }
+
+        for _ in 0..POLL_ATTEMPTS {
+            tokio::time::sleep(self.config.poll_interval).await;
+
+            let resource = parse(self.post(url, None).await?).await?;
+            if done(&resource) {
+                return Ok(resource);
+            }
+        }
+
+        Err(PiosphereError::Certificate(format!(
+            "Gave up waiting for {url} after {POLL_ATTEMPTS} attempts"
+        )))
+    }
+
+    /// Send `payload` signed by the account key to `url`, `None` making a POST-as-GET request.
+    /// Requests with a nonce the CA rejected are sent again with the fresh one.
+    async fn post(&mut self, url: &str, payload: Option<Value>) -> PiosphereResult<Response> {
+        let payload = payload
+            .map(|payload| payload.to_string())
+            .unwrap_or_default();
+
+        let mut retried = false;
+        loop {
+            let mut protected = json!({
+                "alg": "ES256",
+                "nonce": self.nonce().await?,
+                "url": url,
+            });
+            match self.account {
+                Some(ref account) => protected["kid"] = json!(account),
+                None => protected["jwk"] = self.key.jwk(),
+            }
+
+            let response = self
+                .http
+                .post(url)
+                .header(header::CONTENT_TYPE, "application/jose+json")
+                .json(&self.key.sign(&protected, &payload)?)
+                .send()
+                .await
+                .map_err(request_error)?;
+            self.nonce = replay_nonce(&response);
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let status = response.status();
+            let problem: Problem = response.json().await.unwrap_or(Problem {
+                kind: String::new(),
+                detail: status.to_string(),
+            });
+
+            if problem.kind == BAD_NONCE && !retried {
+                retried = true;
+                continue;
+            }
+
+            return Err(PiosphereError::Certificate(format!(
+                "Request to {url} failed: {problem}"
+            )));
+        }
+    }
+
+    /// A nonce returned by the previous request, or a new one.
+    async fn nonce(&mut self) -> PiosphereResult<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+
+        let new_nonce = &self.directory()?.new_nonce;
+        let response = self
+            .http
+            .head(new_nonce)
+            .send()
+            .await
+            .map_err(request_error)?;
+
+        match response.status() {
+            StatusCode::OK | StatusCode::NO_CONTENT => replay_nonce(&response).ok_or_else(|| {
+                PiosphereError::Certificate(format!("{new_nonce} returned no nonce"))
+            }),
+            status => Err(PiosphereError::Certificate(format!(
+                "Could not get a nonce from {new_nonce}: {status}"
+            ))),
+        }
+    }
+
+    fn directory(&self) -> PiosphereResult<&Directory> {
+        self.directory
+            .as_ref()
+            .ok_or_else(|| PiosphereError::Certificate("The directory was not fetched".to_string()))
+    }
+}
+
+async fn parse<T: DeserializeOwned>(response: Response) -> PiosphereResult<T> {
+    let url = response.url().to_string();
+    response
+        .json()
+        .await
+        .map_err(|e| PiosphereError::Certificate(format!("Invalid response from {url}: {e}")))
+}
+
+/// The URL of the resource a request created.
+fn location(response: &Response) -> PiosphereResult<String> {
+    response
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            PiosphereError::Certificate(format!("{} returned no Location", response.url()))
+        })
+}
+
+fn replay_nonce(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get("Replay-Nonce")
+        .and_then(|nonce| nonce.to_str().ok())
+        .map(str::to_string)
+}
+
+fn order_failed(order: &Order) -> PiosphereError {
+    let problem = order
+        .error
+        .as_ref()
+        .map(|problem| format!(": {problem}"))
+        .unwrap_or_default();
+    PiosphereError::Certificate(format!("The order is {}{problem}", order.status))
+}
+
+fn request_error(e: reqwest::Error) -> PiosphereError {
+    PiosphereError::Certificate(format!("Request to the CA failed: {e}"))
+}
+
+fn key_error(e: rcgen::Error) -> PiosphereError {
+    PiosphereError::Certificate(format!("Could not create the certificate request: {e}"))
+}
@@ -0,0 +1,87 @@
+//! Signing of ACME requests as JSON Web Signatures, see RFC 7515 and RFC 8555 section 6.2.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256};
+use ring::{
+    digest::{digest, SHA256},
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde_json::{json, Value};
+
+use crate::{error::PiosphereError, PiosphereResult};
+
+/// The P-256 key of an ACME account.
+pub(crate) struct AccountKey {
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for AccountKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccountKey").finish_non_exhaustive()
+    }
+}
+
+impl AccountKey {
+    /// Generate a new key, returning it with its PEM encoding to store.
+    pub(crate) fn generate() -> PiosphereResult<(Self, String)> {
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).map_err(key_error)?;
+        Ok((Self::from_pkcs8(&key.serialize_der())?, key.serialize_pem()))
+    }
+
+    pub(crate) fn from_pem(pem: &str) -> PiosphereResult<Self> {
+        let key = KeyPair::from_pem(pem).map_err(key_error)?;
+        Self::from_pkcs8(&key.serialize_der())
+    }
+
+    fn from_pkcs8(der: &[u8]) -> PiosphereResult<Self> {
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der, &rng)
+            .map_err(|e| PiosphereError::Certificate(format!("Invalid account key: {e}")))?;
+        Ok(Self { key, rng })
+    }
+
+    /// The public key as a JSON Web Key, with its members in lexicographic order.
+    pub(crate) fn jwk(&self) -> Value {
+        // Uncompressed point: 0x04 followed by the coordinates
+        let point = self.key.public_key().as_ref();
+        let (x, y) = point[1..].split_at(32);
+
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(x),
+            "y": URL_SAFE_NO_PAD.encode(y),
+        })
+    }
+
+    /// The JWK thumbprint of RFC 7638, which key authorizations end with.
+    pub(crate) fn thumbprint(&self) -> String {
+        // serde_json orders the members of objects, and the thumbprint needs no whitespace
+        let jwk = self.jwk().to_string();
+        URL_SAFE_NO_PAD.encode(digest(&SHA256, jwk.as_bytes()))
+    }
+
+    /// The flattened JSON serialization of `payload` signed with the `protected` header.
+    /// An empty `payload` makes a POST-as-GET request.
+    pub(crate) fn sign(&self, protected: &Value, payload: &str) -> PiosphereResult<Value> {
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = URL_SAFE_NO_PAD.encode(payload);
+
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|e| PiosphereError::Certificate(format!("Could not sign request: {e}")))?;
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        }))
+    }
+}
+
+fn key_error(e: rcgen::Error) -> PiosphereError {
+    PiosphereError::Certificate(format!("Invalid account key: {e}"))
+}
@@ -0,0 +1,128 @@
+//! The programs notified when the config files of a deployment change.
+
+use std::{
+    fmt::{Debug, Display},
+    process::Command,
+    sync::Arc,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{PiosphereError, PiosphereResult};
+
+/// Manages the systemd units of deployments.
+pub trait ServiceManager: Debug + Send + Sync {
+    /// Pick up changed unit files.
+    fn reload(&self) -> PiosphereResult<()>;
+
+    /// Apply `action` to the unit named `unit`, e.g. `app.service`.
+    fn control(&self, action: ServiceAction, unit: &str) -> PiosphereResult<()>;
+}
+
+/// What to do with the units of a deployment, see
+/// [ControlDeployment][crate::socket::message::ControlDeployment].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+}
+
+impl Display for ServiceAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Start => "start",
+            Self::Stop => "stop",
+            Self::Restart => "restart",
+        })
+    }
+}
+
+/// Runs the nginx serving the vhosts of deployments.
+pub trait NginxRunner: Debug + Send + Sync {
+    /// Check the configuration and pick up changed vhosts.
+    fn reload(&self) -> PiosphereResult<()>;
+}
+
+/// Uses `systemctl` of the system manager.
+#[derive(Debug)]
+pub struct Systemctl;
+
+impl ServiceManager for Systemctl {
+    fn reload(&self) -> PiosphereResult<()> {
+        run(Command::new("systemctl").arg("daemon-reload"))
+    }
+
+    fn control(&self, action: ServiceAction, unit: &str) -> PiosphereResult<()> {
+        run(Command::new("systemctl").arg(action.to_string()).arg(unit))
+    }
+}
+
+/// Uses the `nginx` binary to signal the running master process.
+#[derive(Debug)]
+pub struct Nginx;
+
+impl NginxRunner for Nginx {
+    fn reload(&self) -> PiosphereResult<()> {
+        run(Command::new("nginx").arg("-t"))?;
+        run(Command::new("nginx").args(["-s", "reload"]))
+    }
+}
+
+/// Only writes the config files, for development or when something else applies them.
+#[derive(Debug)]
+pub struct DryRun;
+
+impl ServiceManager for DryRun {
+    fn reload(&self) -> PiosphereResult<()> {
+        println!("Dry run, not reloading systemd");
+        Ok(())
+    }
+
+    fn control(&self, action: ServiceAction, unit: &str) -> PiosphereResult<()> {
+        println!("Dry run, not running systemctl {action} {unit}");
+        Ok(())
+    }
+}
+
+impl NginxRunner for DryRun {
+    fn reload(&self) -> PiosphereResult<()> {
+        println!("Dry run, not reloading nginx");
+        Ok(())
+    }
+}
+
+/// Run `call` with the backend on the blocking thread pool, backends wait for the programs
+/// they run and would hold up the other requests.
+pub(crate) async fn blocking<B, T>(
+    backend: &Arc<B>,
+    call: impl FnOnce(&B) -> PiosphereResult<T> + Send + 'static,
+) -> PiosphereResult<T>
+where
+    B: ?Sized + Send + Sync + 'static,
+    T: Send + 'static,
+{
+    let backend = backend.clone();
+    tokio::task::spawn_blocking(move || call(&backend))
+        .await
+        .map_err(|e| PiosphereError::Backend(format!("Backend call did not finish: {e}")))?
+}
+
+/// Run the command, failing with its output if it does not exit successfully.
+fn run(command: &mut Command) -> PiosphereResult<()> {
+    let output = command
+        .output()
+        .map_err(|e| PiosphereError::Backend(format!("Could not run {command:?}: {e}")))?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    Err(PiosphereError::Backend(format!(
+        "{command:?} failed with {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
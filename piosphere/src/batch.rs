@@ -0,0 +1,190 @@
+//! Execution of multiple requests in a single [Batch][crate::socket::message::Batch].
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    backend::{self, NginxRunner, ServiceManager},
+    PiosphereError, PiosphereResult,
+};
+
+/// Response to a [Batch][crate::socket::message::Batch].
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BatchResponse {
+    /// Set if the batch was atomic and one of its requests failed,
+    /// in which case all changes made by it were reverted.
+    pub rolled_back: bool,
+
+    /// The outcome of each request, in the order they were sent.
+    pub results: Vec<BatchResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchResult {
+    /// The response to the request in the encoding of the session.
+    Ok(
+        #[serde(with = "crate::socket::payload")]
+        #[schemars(with = "serde_json::Value")]
+        Vec<u8>,
+    ),
+
+    Err(String),
+
+    /// Not executed because an earlier request of an atomic batch failed.
+    Skipped,
+}
+
+/// Records the changes made outside of the database during an atomic batch, or a single
+/// request changing deployments, so they can be reverted if it fails.
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    entries: Mutex<Option<Vec<Undo>>>,
+}
+
+impl Journal {
+    /// Start recording changes.
+    pub(crate) fn begin(&self) {
+        *self.lock() = Some(vec![]);
+    }
+
+    /// Stop recording and return the recorded changes.
+    pub(crate) fn finish(&self) -> Vec<Undo> {
+        self.lock().take().unwrap_or_default()
+    }
+
+    /// Whether changes are being recorded, by an atomic batch or request.
+    pub(crate) fn is_recording(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// Write `contents` to the file at `path`, recording its previous state if recording.
+    pub(crate) fn write_file(
+        &self,
+        path: &Path,
+        contents: impl AsRef<[u8]>,
+    ) -> PiosphereResult<()> {
+        if let Some(ref mut entries) = *self.lock() {
+            entries.push(Undo::file(path)?);
+        }
+
+        std::fs::write(path, contents).map_err(PiosphereError::from)
+    }
+
+    /// Remove the file at `path` if it exists, recording its contents if recording.
+    pub(crate) fn remove_file(&self, path: &Path) -> PiosphereResult<()> {
+        if let Some(ref mut entries) = *self.lock() {
+            entries.push(Undo::file(path)?);
+        }
+
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that `reloaded` picked up the changed files, so it does again once they are
+    /// reverted.
+    pub(crate) fn reload(&self, reloaded: Reloaded) {
+        if let Some(ref mut entries) = *self.lock() {
+            entries.push(Undo::Reload(reloaded));
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Vec<Undo>>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Undo {
+    /// Restore the file to its previous contents, or remove it if it did not exist.
+    File {
+        path: PathBuf,
+        previous: Option<Vec<u8>>,
+    },
+
+    /// Reload again after the files were restored.
+    Reload(Reloaded),
+}
+
+/// What picked up changed config files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reloaded {
+    Systemd,
+    Nginx,
+}
+
+impl Undo {
+    /// Record the current contents of the file at `path`, to restore them later.
+    pub(crate) fn file(path: &Path) -> PiosphereResult<Undo> {
+        let previous = match std::fs::read(path) {
+            Ok(previous) => Some(previous),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Undo::File {
+            path: path.to_path_buf(),
+            previous,
+        })
+    }
+
+    /// Revert the changes in reverse order, attempting all of them even if some fail.
+    /// What reloaded during the batch reloads once more after all files were restored.
+    pub(crate) async fn revert_all(
+        entries: Vec<Undo>,
+        service_manager: &Arc<dyn ServiceManager>,
+        nginx: &Arc<dyn NginxRunner>,
+    ) {
+        let mut reloads = vec![];
+
+        for entry in entries.into_iter().rev() {
+            match entry {
+                Undo::Reload(reloaded) => {
+                    if !reloads.contains(&reloaded) {
+                        reloads.push(reloaded);
+                    }
+                }
+                entry => {
+                    if let Err(e) = entry.revert() {
+                        println!("Error while reverting batch: {e}");
+                    }
+                }
+            }
+        }
+
+        for reloaded in reloads {
+            let res = match reloaded {
+                Reloaded::Systemd => {
+                    backend::blocking(service_manager, |manager| manager.reload()).await
+                }
+                Reloaded::Nginx => backend::blocking(nginx, |nginx| nginx.reload()).await,
+            };
+            if let Err(e) = res {
+                println!("Error while reloading {reloaded:?} after reverting batch: {e}");
+            }
+        }
+    }
+
+    fn revert(self) -> std::io::Result<()> {
+        match self {
+            Undo::File {
+                path,
+                previous: Some(previous),
+            } => std::fs::write(path, previous),
+            Undo::File {
+                path,
+                previous: None,
+            } => match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            Undo::Reload(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! A certificate authority managed by piosphere, issuing certificates for the servers with
+//! [Issuer::Local][crate::deployment::nginx::Issuer::Local], e.g. on staging boxes or for local testing.
+//!
+//! The CA is created on first use below the [data directory][crate::PiosphereBuilder::data_dir].
+//! Its certificate is exported with [ExportCaCertificate][crate::socket::message::ExportCaCertificate]
+//! so team machines can trust it, e.g. on Debian:
+//!
+//! ```sh
+//! cp piosphere-ca.pem /usr/local/share/ca-certificates/piosphere-ca.crt
+//! update-ca-certificates
+//! ```
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use rcgen::{
+    date_time_ymd, BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use ring::digest::{digest, SHA256};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    certificates::{write_private, Issued},
+    deployment::nginx::CertificateInfo,
+    error::PiosphereError,
+    PiosphereResult, PiosphereService,
+};
+
+/// How long the CA certificate is valid, in years.
+const CA_VALIDITY_YEARS: i32 = 10;
+
+/// How long issued certificates are valid, below the 398 days browsers accept.
+const VALIDITY_DAYS: i64 = 365;
+
+/// The certificate of the local CA, to install on the machines which should trust the
+/// certificates it issues.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CaCertificate {
+    /// Location of the certificate on the server.
+    pub location: String,
+
+    /// The PEM encoded certificate.
+    pub pem: String,
+
+    /// SHA-256 fingerprint of the certificate, as colon separated hex bytes, to compare
+    /// with the installed one.
+    pub fingerprint: String,
+
+    pub not_after: NaiveDateTime,
+}
+
+/// The certificate and key of the local CA.
+pub(crate) struct LocalCa {
+    certificate: Certificate,
+    key: KeyPair,
+    pem: String,
+}
+
+impl std::fmt::Debug for LocalCa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalCa").finish_non_exhaustive()
+    }
+}
+
+impl LocalCa {
+    /// Create a new CA, valid from `today`.
+    pub(crate) fn generate(today: NaiveDate) -> PiosphereResult<Self> {
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Piosphere local CA");
+        params
+            .distinguished_name
+            .push(DnType::OrganizationName, "Piosphere");
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        params.not_before = date_time_ymd(today.year(), today.month() as u8, today.day() as u8);
+        params.not_after = date_time_ymd(
+            today.year() + CA_VALIDITY_YEARS,
+            today.month() as u8,
+            today.day().min(28) as u8,
+        );
+
+        let key = KeyPair::generate().map_err(ca_error)?;
+        let certificate = params.self_signed(&key).map_err(ca_error)?;
+        let pem = certificate.pem();
+
+        Ok(Self {
+            certificate,
+            key,
+            pem,
+        })
+    }
+
+    pub(crate) fn from_pem(certificate: &str, key: &str) -> PiosphereResult<Self> {
+        let key = KeyPair::from_pem(key).map_err(ca_error)?;
+
+        // Signing only needs the name and key of the CA, the re-signed certificate is
+        // equivalent to the stored one
+        let params = CertificateParams::from_ca_cert_pem(certificate).map_err(ca_error)?;
+        let signer = params.self_signed(&key).map_err(ca_error)?;
+
+        Ok(Self {
+            certificate: signer,
+            key,
+            pem: certificate.to_string(),
+        })
+    }
+
+    /// Issue a certificate for `names`, valid from `today`.
+    pub(crate) fn issue(&self, names: &[String], today: NaiveDate) -> PiosphereResult<Issued> {
+        let mut params = CertificateParams::new(names.to_vec()).map_err(ca_error)?;
+        params.distinguished_name = DistinguishedName::new();
+        if let Some(name) = names.first() {
+            params
+                .distinguished_name
+                .push(DnType::CommonName, name.as_str());
+        }
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        let until = today + chrono::Duration::days(VALIDITY_DAYS);
+        params.not_before = date_time_ymd(today.year(), today.month() as u8, today.day() as u8);
+        params.not_after = date_time_ymd(until.year(), until.month() as u8, until.day() as u8);
+
+        let key = KeyPair::generate().map_err(ca_error)?;
+        let certificate = params
+            .signed_by(&key, &self.certificate, &self.key)
+            .map_err(ca_error)?;
+
+        Ok(Issued {
+            chain: format!("{}{}", certificate.pem(), self.pem),
+            key: key.serialize_pem(),
+        })
+    }
+}
+
+impl PiosphereService {
+    /// The local CA, created on first use.
+    pub(crate) fn local_ca(&self) -> PiosphereResult<LocalCa> {
+        let certificate = self.resolve(&self.ca_location("ca.pem"));
+        let key = self.resolve(&self.ca_location("ca.key"));
+
+        match std::fs::read_to_string(&certificate) {
+            Ok(pem) => LocalCa::from_pem(&pem, &std::fs::read_to_string(key)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("Creating the local CA at {}", certificate.display());
+
+                let ca = LocalCa::generate(chrono::Utc::now().date_naive())?;
+                write_private(&key, &ca.key.serialize_pem())?;
+                std::fs::write(&certificate, &ca.pem)?;
+                Ok(ca)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The certificate of the local CA, see [CaCertificate].
+    pub(crate) fn ca_certificate(&self) -> PiosphereResult<CaCertificate> {
+        let ca = self.local_ca()?;
+        let info = CertificateInfo::from_pem(ca.pem.as_bytes())
+            .map_err(|e| PiosphereError::Certificate(format!("The local CA {e}")))?;
+
+        let (_, pem) = x509_parser::pem::parse_x509_pem(ca.pem.as_bytes())
+            .map_err(|e| PiosphereError::Certificate(format!("The local CA is invalid: {e}")))?;
+        let fingerprint = digest(&SHA256, &pem.contents)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        Ok(CaCertificate {
+            location: self.ca_location("ca.pem"),
+            pem: ca.pem,
+            fingerprint,
+            not_after: info.not_after,
+        })
+    }
+
+    /// Location of `name` in the directory of the local CA.
+    pub(crate) fn ca_location(&self, name: &str) -> String {
+        format!("{}/ca/{name}", self.data_dir.trim_end_matches('/'))
+    }
+}
+
+fn ca_error(e: rcgen::Error) -> PiosphereError {
+    PiosphereError::Certificate(format!("The local CA failed: {e}"))
+}
@@ -0,0 +1,401 @@
+//! The certificates piosphere obtains for servers with an [issuer][crate::deployment::nginx::Tls::issuer], either
+//! from an [ACME CA][crate::acme] or from its [local CA][crate::ca].
+//!
+//! They are stored below the [data directory][crate::PiosphereBuilder::data_dir], obtained
+//! when a deployment is created or updated, and renewed by the
+//! [server][crate::socket::server::Server] before they expire, or on request with
+//! [RenewCertificates][crate::socket::message::RenewCertificates].
+
+use std::{io::Write, os::unix::fs::OpenOptionsExt, path::Path, sync::Arc};
+
+use chrono::NaiveDateTime;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::RwLockReadGuard, task::JoinHandle};
+
+use crate::{
+    acme::AcmeClient,
+    backend,
+    batch::{Reloaded, Undo},
+    deployment::{
+        nginx::{CertificateInfo, Issuer, NginxConfig},
+        Deployment,
+    },
+    error::PiosphereError,
+    PiosphereResult, PiosphereService,
+};
+
+/// How many days before it expires a certificate is renewed by default.
+pub const RENEW_BEFORE_DAYS: i64 = 30;
+
+/// How often the server checks for certificates to renew by default.
+pub const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(12 * 60 * 60);
+
+/// A certificate piosphere obtained for a server.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct IssuedCertificate {
+    pub deployment_id: String,
+
+    pub issuer: Issuer,
+
+    pub names: Vec<String>,
+
+    /// Location of the certificate chain.
+    pub certificate: String,
+
+    pub not_after: NaiveDateTime,
+}
+
+/// A certificate chain and its private key, both PEM encoded.
+#[derive(Debug)]
+pub(crate) struct Issued {
+    pub(crate) chain: String,
+    pub(crate) key: String,
+}
+
+/// A certificate to obtain, for the server names it has to cover.
+#[derive(Debug)]
+struct Due {
+    issuer: Issuer,
+    names: Vec<String>,
+    certificate: String,
+    certificate_key: String,
+}
+
+impl PiosphereService {
+    /// Point the servers with an issuer to their certificate below the data directory,
+    /// and serve the ACME challenges for their names.
+    pub(crate) fn prepare_certificates(&self, config: &mut NginxConfig) {
+        for server in config.servers.iter_mut() {
+            let Some(name) = server.request_names().next().map(|name| name.to_string()) else {
+                continue;
+            };
+            let Some(tls) = server.tls.as_mut() else {
+                continue;
+            };
+            let Some(issuer) = tls.issuer else {
+                continue;
+            };
+
+            let dir = format!("{}/{name}", self.certificates_location(issuer));
+            tls.certificate = format!("{dir}/fullchain.pem");
+            tls.certificate_key = format!("{dir}/privkey.pem");
+        }
+
+        config.serve_acme_challenges(&self.acme_location("challenges"));
+    }
+
+    /// Set the issuer of the certificates piosphere obtained, as it is not part of the vhost.
+    pub(crate) fn find_issuers(&self, config: &mut NginxConfig) {
+        for tls in config
+            .servers
+            .iter_mut()
+            .filter_map(|server| server.tls.as_mut())
+        {
+            tls.issuer = [Issuer::Acme, Issuer::Local].into_iter().find(|issuer| {
+                let dir = format!("{}/", self.certificates_location(*issuer));
+                tls.certificate.starts_with(&dir)
+            });
+        }
+    }
+
+    /// Obtain the missing certificates of the deployment, and those to renew. Until a
+    /// certificate is obtained nginx cannot load the server using it, so ACME challenges
+    /// are served by vhosts without those servers, which are reverted if issuing fails.
+    pub(crate) async fn issue_certificates(
+        &self,
+        deployment: &Deployment,
+    ) -> PiosphereResult<Vec<IssuedCertificate>> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut due = vec![];
+        let mut undo = vec![];
+
+        for nginx_cfg in deployment.nginx_cfgs.iter() {
+            let vhost_due = self.due_certificates(nginx_cfg, now);
+
+            if vhost_due.iter().any(|due| due.issuer == Issuer::Acme) {
+                let mut bootstrap = nginx_cfg.clone();
+                bootstrap.servers.retain(|server| {
+                    server
+                        .tls
+                        .as_ref()
+                        .is_none_or(|tls| self.resolve(&tls.certificate).exists())
+                });
+
+                let path = self.resolve(&bootstrap.file_location);
+                undo.push(Undo::file(&path)?);
+                self.journal.write_file(&path, bootstrap.to_string())?;
+            }
+
+            due.extend(vhost_due);
+        }
+
+        if undo.is_empty() {
+            return self.obtain_certificates(&deployment.id, due).await;
+        }
+
+        undo.push(Undo::Reload(Reloaded::Nginx));
+
+        let result = match self.reload(false, true).await {
+            Ok(_) => self.obtain_certificates(&deployment.id, due).await,
+            Err(e) => Err(e),
+        };
+
+        if result.is_err() {
+            println!("Reverting the vhosts of deployment {}", deployment.id);
+            self.revert(undo).await;
+        }
+
+        result
+    }
+
+    /// Renew the certificates of all deployments which expire soon, and reload nginx if
+    /// any was renewed. A failing deployment does not keep the others from being renewed.
+    ///
+    /// With `lock`, the batch lock is held to find the due certificates and to store them,
+    /// but not while they are obtained. Without it, the caller holds the lock already.
+    pub(crate) async fn renew_certificates(
+        &self,
+        lock: bool,
+    ) -> PiosphereResult<Vec<IssuedCertificate>> {
+        let due = {
+            let _lock = self.read_batch_lock(lock).await;
+            self.due_renewals().await?
+        };
+
+        let mut obtained = vec![];
+        let mut failed = vec![];
+
+        for (deployment_id, due) in due {
+            match self.fetch_certificates(due).await {
+                Ok(certificates) => obtained.push((deployment_id, certificates)),
+                Err(e) => failed.push(format!("deployment {deployment_id}: {e}")),
+            }
+        }
+
+        let mut renewed = vec![];
+
+        if !obtained.is_empty() {
+            let _lock = self.read_batch_lock(lock).await;
+
+            for (deployment_id, certificates) in obtained {
+                match self.store_certificates(&deployment_id, certificates) {
+                    Ok(stored) => renewed.extend(stored),
+                    Err(e) => failed.push(format!("deployment {deployment_id}: {e}")),
+                }
+            }
+
+            if !renewed.is_empty() {
+                backend::blocking(&self.nginx, |nginx| nginx.reload()).await?;
+            }
+        }
+
+        for certificate in renewed.iter() {
+            println!(
+                "Renewed the certificate of {} until {}",
+                certificate.names.join(", "),
+                certificate.not_after
+            );
+        }
+
+        if !failed.is_empty() {
+            return Err(PiosphereError::Certificate(format!(
+                "Could not renew the certificates of {}",
+                failed.join("; ")
+            )));
+        }
+
+        Ok(renewed)
+    }
+
+    /// Hold the batch lock for reading if `lock` is set, see
+    /// [renew_certificates][Self::renew_certificates].
+    async fn read_batch_lock(&self, lock: bool) -> Option<RwLockReadGuard<'_, ()>> {
+        if !lock {
+            return None;
+        }
+        Some(self.batch_lock.read().await)
+    }
+
+    /// The certificates which expire soon, by the ID of their deployment.
+    async fn due_renewals(&self) -> PiosphereResult<Vec<(String, Vec<Due>)>> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut renewals = vec![];
+
+        for deployment in self.db.list_deployments().await? {
+            let (_, nginx_cfgs, _) = self.db.get_deployment(&deployment.id).await?;
+            let mut due = vec![];
+
+            for nginx_cfg in nginx_cfgs.iter() {
+                match self.read_nginx_config(&nginx_cfg.file_path) {
+                    Ok(config) => due.extend(self.due_certificates(&config, now)),
+                    Err(e) => println!(
+                        "Not renewing the certificates of {} of deployment {}, it could not be read: {e}",
+                        nginx_cfg.file_path, deployment.id
+                    ),
+                }
+            }
+
+            if !due.is_empty() {
+                renewals.push((deployment.id, due));
+            }
+        }
+
+        Ok(renewals)
+    }
+
+    /// The certificates of servers with an issuer which are missing, expire soon or do not
+    /// cover the names of the server.
+    fn due_certificates(&self, config: &NginxConfig, now: NaiveDateTime) -> Vec<Due> {
+        let mut due = vec![];
+
+        for server in config.servers.iter() {
+            let Some((tls, issuer)) = server.tls.as_ref().and_then(|tls| Some((tls, tls.issuer?)))
+            else {
+                continue;
+            };
+
+            let names: Vec<_> = server
+                .request_names()
+                .map(|name| name.as_str().to_ascii_lowercase())
+                .collect();
+
+            let renew = match CertificateInfo::read(&self.resolve(&tls.certificate)) {
+                Ok(info) => {
+                    info.not_after - self.renew_before(issuer) <= now
+                        || names.iter().any(|name| !info.names.contains(name))
+                }
+                Err(_) => true,
+            };
+
+            if renew {
+                due.push(Due {
+                    issuer,
+                    names,
+                    certificate: tls.certificate.clone(),
+                    certificate_key: tls.certificate_key.clone(),
+                });
+            }
+        }
+
+        due
+    }
+
+    /// Obtain and store the `due` certificates of the deployment with the ID `deployment_id`.
+    async fn obtain_certificates(
+        &self,
+        deployment_id: &str,
+        due: Vec<Due>,
+    ) -> PiosphereResult<Vec<IssuedCertificate>> {
+        let certificates = self.fetch_certificates(due).await?;
+        self.store_certificates(deployment_id, certificates)
+    }
+
+    /// Obtain the `due` certificates from their issuer, without storing them.
+    async fn fetch_certificates(&self, due: Vec<Due>) -> PiosphereResult<Vec<(Due, Issued)>> {
+        let mut acme: Option<AcmeClient> = None;
+        let mut certificates = vec![];
+
+        for due in due {
+            println!("Obtaining a certificate for {}", due.names.join(", "));
+
+            let certificate = match due.issuer {
+                Issuer::Acme => {
+                    let client = match acme {
+                        Some(ref mut client) => client,
+                        None => acme.insert(self.acme_client(&due.names)?),
+                    };
+                    let challenges = self.resolve(&self.acme_location("challenges"));
+                    client.issue(&due.names, &challenges).await?
+                }
+                Issuer::Local => self
+                    .local_ca()?
+                    .issue(&due.names, chrono::Utc::now().date_naive())?,
+            };
+
+            certificates.push((due, certificate));
+        }
+
+        Ok(certificates)
+    }
+
+    /// Write the obtained certificates of the deployment with the ID `deployment_id`.
+    fn store_certificates(
+        &self,
+        deployment_id: &str,
+        certificates: Vec<(Due, Issued)>,
+    ) -> PiosphereResult<Vec<IssuedCertificate>> {
+        let mut issued = vec![];
+
+        for (due, certificate) in certificates {
+            let info = CertificateInfo::from_pem(certificate.chain.as_bytes())
+                .map_err(|e| PiosphereError::Certificate(format!("The issued certificate {e}")))?;
+
+            // Not recorded by the journal, a valid certificate can be kept even if the
+            // batch creating the deployment fails
+            write_private(&self.resolve(&due.certificate_key), &certificate.key)?;
+            std::fs::write(self.resolve(&due.certificate), &certificate.chain)?;
+
+            issued.push(IssuedCertificate {
+                deployment_id: deployment_id.to_string(),
+                issuer: due.issuer,
+                names: due.names,
+                certificate: due.certificate,
+                not_after: info.not_after,
+            });
+        }
+
+        Ok(issued)
+    }
+
+    fn renew_before(&self, issuer: Issuer) -> chrono::Duration {
+        match (issuer, &self.acme) {
+            (Issuer::Acme, Some(acme)) => acme.renew_before,
+            _ => chrono::Duration::days(RENEW_BEFORE_DAYS),
+        }
+    }
+
+    /// Location of the directory with the certificates of `issuer`, one directory per server.
+    fn certificates_location(&self, issuer: Issuer) -> String {
+        match issuer {
+            Issuer::Acme => self.acme_location("certificates"),
+            Issuer::Local => self.ca_location("certificates"),
+        }
+    }
+}
+
+/// Periodically renew the certificates of the service, every
+/// [AcmeConfig::check_interval][crate::acme::AcmeConfig::check_interval] if it has one.
+pub(crate) fn spawn_renewal(service: Arc<PiosphereService>) -> JoinHandle<()> {
+    let interval = service
+        .acme
+        .as_ref()
+        .map_or(CHECK_INTERVAL, |acme| acme.check_interval);
+
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = service.renew_certificates(true).await {
+                println!("Error while renewing certificates: {e}");
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Write a file only the owner can read, creating its directory if needed.
+pub(crate) fn write_private(path: &Path, contents: &str) -> PiosphereResult<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
@@ -1,8 +1,18 @@
 use chrono::NaiveDateTime;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
+use sqlx::{
+    pool::PoolConnection,
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePoolOptions},
+    Connection, Sqlite, SqlitePool, Transaction,
+};
+use std::{
+    ops::{Deref, DerefMut},
+    str::FromStr,
+};
+use tokio::sync::{Mutex, MutexGuard};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct Deployment {
     pub id: String,
     pub name: String,
@@ -10,64 +20,179 @@ pub struct Deployment {
     pub created_at: NaiveDateTime,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 pub struct Config {
     pub id: String,
-    pub deployment_id: i64,
+    pub deployment_id: String,
     pub file_path: String,
     pub created_at: NaiveDateTime,
 }
 
+/// Row counts of the piosphere tables.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+pub struct DatabaseStats {
+    pub deployments: i64,
+    pub nginx_configs: i64,
+    pub sysd_configs: i64,
+}
+
+/// Name of the in-memory database.
+pub const MEMORY: &str = ":memory:";
+
 #[derive(Debug)]
 pub struct PiosphereDatabase {
     client: SqlitePool,
+
+    /// Path to the sqlite file.
+    file: String,
+
+    /// Transaction of the running atomic batch, if any. While set, all queries go through it.
+    batch_tx: Mutex<Option<Transaction<'static, Sqlite>>>,
+}
+
+/// A connection used to execute a single query.
+enum Conn<'a> {
+    Pool(PoolConnection<Sqlite>),
+    Batch(MutexGuard<'a, Option<Transaction<'static, Sqlite>>>),
+}
+
+impl Deref for Conn<'_> {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Conn::Pool(conn) => conn,
+            Conn::Batch(tx) => tx.as_ref().expect("batch transaction checked in conn"),
+        }
+    }
+}
+
+impl DerefMut for Conn<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Conn::Pool(conn) => conn,
+            Conn::Batch(tx) => tx.as_mut().expect("batch transaction checked in conn"),
+        }
+    }
 }
 
 impl PiosphereDatabase {
     /// Establish a connection pool at the specified sqlite file
+    ///
+    /// `:memory:` opens a database that only lives as long as the pool.
     pub async fn new(file: &str) -> Result<Self, sqlx::Error> {
-        let options = SqliteConnectOptions::new()
-            .filename(file)
-            .create_if_missing(true);
+        let pool = if file == MEMORY {
+            // Every connection would get its own database, so keep a single one open forever
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None)
+                .connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?)
+                .await?
+        } else {
+            let options = SqliteConnectOptions::new()
+                .filename(file)
+                .create_if_missing(true);
+
+            SqlitePool::connect_with(options).await?
+        };
+
+        Ok(Self {
+            client: pool,
+            file: file.to_string(),
+            batch_tx: Mutex::new(None),
+        })
+    }
 
-        let pool = SqlitePool::connect_with(options).await?;
-        Ok(Self { client: pool })
+    pub fn file(&self) -> &str {
+        &self.file
     }
 
     pub async fn migrate(&self) -> Result<(), sqlx::migrate::MigrateError> {
         sqlx::migrate!().run(&self.client).await
     }
 
-    /// (Deploymeny, NginxConfig, SysdConfig)
-    pub async fn get_deployment(&self, id: &str) -> sqlx::Result<(Deployment, Config, Config)> {
+    /// Start a transaction all subsequent queries will run in, until either
+    /// [commit_batch][Self::commit_batch] or [rollback_batch][Self::rollback_batch] is called.
+    ///
+    /// The caller must ensure no other requests are executed in the meantime.
+    pub async fn begin_batch(&self) -> sqlx::Result<()> {
+        let tx = self.client.begin().await?;
+        *self.batch_tx.lock().await = Some(tx);
+        Ok(())
+    }
+
+    pub async fn commit_batch(&self) -> sqlx::Result<()> {
+        match self.batch_tx.lock().await.take() {
+            Some(tx) => tx.commit().await,
+            None => Ok(()),
+        }
+    }
+
+    pub async fn rollback_batch(&self) -> sqlx::Result<()> {
+        match self.batch_tx.lock().await.take() {
+            Some(tx) => tx.rollback().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn conn(&self) -> sqlx::Result<Conn<'_>> {
+        let tx = self.batch_tx.lock().await;
+        if tx.is_some() {
+            return Ok(Conn::Batch(tx));
+        }
+        Ok(Conn::Pool(self.client.acquire().await?))
+    }
+
+    pub async fn stats(&self) -> sqlx::Result<DatabaseStats> {
+        sqlx::query_as!(
+            DatabaseStats,
+            r#"SELECT
+                (SELECT COUNT(*) FROM deployments) AS "deployments!: i64",
+                (SELECT COUNT(*) FROM nginx_configs) AS "nginx_configs!: i64",
+                (SELECT COUNT(*) FROM sysd_configs) AS "sysd_configs!: i64""#
+        )
+        .fetch_one(&mut *self.conn().await?)
+        .await
+    }
+
+    /// (Deploymeny, NginxConfigs, SysdConfigs), the configs in the order they were inserted.
+    pub async fn get_deployment(
+        &self,
+        id: &str,
+    ) -> sqlx::Result<(Deployment, Vec<Config>, Vec<Config>)> {
+        let mut conn = self.conn().await?;
+
         let deployment = sqlx::query_as!(Deployment, "SELECT * FROM deployments WHERE id=?", id)
-            .fetch_one(&self.client)
+            .fetch_one(&mut *conn)
             .await?;
 
-        let nginx_cfg = sqlx::query_as!(
+        let nginx_cfgs = sqlx::query_as!(
             Config,
-            "SELECT * FROM nginx_configs WHERE deployment_id=?",
+            "SELECT * FROM nginx_configs WHERE deployment_id=? ORDER BY rowid",
             deployment.id,
         )
-        .fetch_one(&self.client)
+        .fetch_all(&mut *conn)
         .await?;
 
-        let sysd_cfg = sqlx::query_as!(
+        let sysd_cfgs = sqlx::query_as!(
             Config,
-            "SELECT * FROM sysd_configs WHERE deployment_id=?",
+            "SELECT * FROM sysd_configs WHERE deployment_id=? ORDER BY rowid",
             deployment.id,
         )
-        .fetch_one(&self.client)
+        .fetch_all(&mut *conn)
         .await?;
 
-        Ok((deployment, nginx_cfg, sysd_cfg))
+        Ok((deployment, nginx_cfgs, sysd_cfgs))
     }
 
+    #[allow(clippy::blocks_in_conditions)]
     pub async fn insert_deployment(
         &self,
         deployment: &crate::deployment::Deployment,
     ) -> sqlx::Result<Deployment> {
-        let mut tx = self.client.begin().await?;
+        let mut conn = self.conn().await?;
+        let mut tx = conn.begin().await?;
 
         match {
             let deployment_new = sqlx::query_as!(
@@ -80,22 +205,56 @@ impl PiosphereDatabase {
             .fetch_one(&mut *tx)
             .await?;
 
+            insert_configs(&mut tx, deployment).await?;
+
+            Result::<Deployment, sqlx::Error>::Ok(deployment_new)
+        } {
+            Ok(dep) => {
+                tx.commit().await?;
+                Ok(dep)
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+
+    #[allow(clippy::blocks_in_conditions)]
+    pub async fn update_deployment(
+        &self,
+        deployment: &crate::deployment::Deployment,
+    ) -> sqlx::Result<Deployment> {
+        let mut conn = self.conn().await?;
+        let mut tx = conn.begin().await?;
+
+        match {
+            let deployment_new = sqlx::query_as!(
+                Deployment,
+                "UPDATE deployments SET name=?, description=? WHERE id=? RETURNING *",
+                deployment.name,
+                deployment.description,
+                deployment.id,
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            // The rows are replaced, so that added and removed configs keep the order
             sqlx::query!(
-                "INSERT INTO nginx_configs (deployment_id, file_path) VALUES (?, ?)",
+                "DELETE FROM nginx_configs WHERE deployment_id=?",
                 deployment_new.id,
-                deployment.nginx_cfg.file_location
             )
             .execute(&mut *tx)
             .await?;
-
             sqlx::query!(
-                "INSERT INTO sysd_configs (deployment_id, file_path) VALUES (?, ?)",
+                "DELETE FROM sysd_configs WHERE deployment_id=?",
                 deployment_new.id,
-                deployment.service_cfg.file_location
             )
             .execute(&mut *tx)
             .await?;
 
+            insert_configs(&mut tx, deployment).await?;
+
             Result::<Deployment, sqlx::Error>::Ok(deployment_new)
         } {
             Ok(dep) => {
@@ -111,15 +270,47 @@ impl PiosphereDatabase {
 
     pub async fn list_deployments(&self) -> sqlx::Result<Vec<Deployment>> {
         sqlx::query_as!(Deployment, "SELECT * FROM deployments")
-            .fetch_all(&self.client)
+            .fetch_all(&mut *self.conn().await?)
             .await
     }
 
-    pub async fn delete_deployment(&self, id: i64) -> sqlx::Result<u64> {
+    pub async fn delete_deployment(&self, id: &str) -> sqlx::Result<u64> {
         let result = sqlx::query!("DELETE FROM deployments WHERE id=?", id)
-            .execute(&self.client)
+            .execute(&mut *self.conn().await?)
             .await;
 
         result.map(|res| res.rows_affected())
     }
 }
+
+/// Insert a row for each config of the deployment, in order.
+async fn insert_configs(
+    conn: &mut SqliteConnection,
+    deployment: &crate::deployment::Deployment,
+) -> sqlx::Result<()> {
+    for nginx_cfg in deployment.nginx_cfgs.iter() {
+        let nginx_id = uuid::Uuid::new_v4().to_string();
+        sqlx::query!(
+            "INSERT INTO nginx_configs (id, deployment_id, file_path) VALUES (?, ?, ?)",
+            nginx_id,
+            deployment.id,
+            nginx_cfg.file_location
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for service_cfg in deployment.service_cfgs.iter() {
+        let sysd_id = uuid::Uuid::new_v4().to_string();
+        sqlx::query!(
+            "INSERT INTO sysd_configs (id, deployment_id, file_path) VALUES (?, ?, ?)",
+            sysd_id,
+            deployment.id,
+            service_cfg.file_location
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
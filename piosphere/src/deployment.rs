@@ -1,13 +1,22 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use schemars::JsonSchema;
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 
 use crate::PiosphereResult;
 
-use self::{nginx::NginxConfig, systemd::SystemdConfig};
+use self::{
+    nginx::{lint::Diagnostic, NginxConfig},
+    systemd::SystemdConfig,
+    validate::{UnitName, ValidationErrors},
+};
 
 pub mod nginx;
+pub mod parse;
 pub mod systemd;
+pub mod validate;
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema)]
 pub struct Deployment {
     pub id: String,
 
@@ -18,11 +27,46 @@ pub struct Deployment {
     /// If a systemd description is not defined, this one is used for it.
     pub description: String,
 
-    /// The systemd service file.
-    pub service_cfg: SystemdConfig,
+    /// The systemd service files, none for deployments without a process, e.g. static sites.
+    #[serde(default)]
+    pub service_cfgs: Vec<SystemdConfig>,
+
+    /// The nginx vhost files, none for deployments nothing is proxied to, e.g. workers.
+    #[serde(default)]
+    pub nginx_cfgs: Vec<NginxConfig>,
+}
+
+/// Self-describing formats like JSON leave out the kinds of configs the deployment does not
+/// have, bincode needs every field.
+impl Serialize for Deployment {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let hide = serializer.is_human_readable();
+        let mut deployment = serializer.serialize_struct("Deployment", 5)?;
+
+        deployment.serialize_field("id", &self.id)?;
+        deployment.serialize_field("name", &self.name)?;
+        deployment.serialize_field("description", &self.description)?;
+
+        if hide && self.service_cfgs.is_empty() {
+            deployment.skip_field("service_cfgs")?;
+        } else {
+            deployment.serialize_field("service_cfgs", &self.service_cfgs)?;
+        }
+        if hide && self.nginx_cfgs.is_empty() {
+            deployment.skip_field("nginx_cfgs")?;
+        } else {
+            deployment.serialize_field("nginx_cfgs", &self.nginx_cfgs)?;
+        }
+
+        deployment.end()
+    }
+}
 
-    /// The nginx vhost file.
-    pub nginx_cfg: NginxConfig,
+/// A unit or vhost of a deployment, see [Deployment::component].
+#[derive(Debug, Clone, Copy)]
+pub enum Component<'a> {
+    Service(&'a SystemdConfig),
+    Vhost(&'a NginxConfig),
 }
 
 impl Deployment {
@@ -31,13 +75,118 @@ impl Deployment {
             id: uuid::Uuid::new_v4().to_string(),
             name: name.to_string(),
             description: desc.to_string(),
-            service_cfg: sysd,
-            nginx_cfg: nginx,
+            service_cfgs: vec![sysd],
+            nginx_cfgs: vec![nginx],
+        }
+    }
+
+    /// A deployment only running a service, e.g. a worker or a queue consumer.
+    pub fn service(name: &str, desc: &str, sysd: SystemdConfig) -> Self {
+        Self {
+            nginx_cfgs: vec![],
+            ..Self::new(name, desc, NginxConfig::default(), sysd)
+        }
+    }
+
+    /// A deployment only serving files, e.g. a static site, see [StaticFiles][nginx::StaticFiles].
+    pub fn site(name: &str, desc: &str, nginx: NginxConfig) -> Self {
+        Self {
+            service_cfgs: vec![],
+            ..Self::new(name, desc, nginx, SystemdConfig::default())
+        }
+    }
+
+    /// The unit or vhost named `name`, components are named after their file, e.g.
+    /// `api.service` or `api.example.org`.
+    pub fn component(&self, name: &str) -> Option<Component<'_>> {
+        let service = self
+            .service_cfgs
+            .iter()
+            .find(|config| config.unit_name() == name)
+            .map(Component::Service);
+
+        service.or_else(|| {
+            self.nginx_cfgs
+                .iter()
+                .find(|config| config.name() == name)
+                .map(Component::Vhost)
+        })
+    }
+
+    /// Check everything the types of the fields cannot, reporting all rejected fields at once.
+    pub fn validate(&self) -> PiosphereResult<()> {
+        let mut errors = ValidationErrors::default();
+
+        if self.name.trim().is_empty() {
+            errors.push("name", "name cannot be empty");
+        }
+
+        if self.nginx_cfgs.is_empty() && self.service_cfgs.is_empty() {
+            errors.push("nginx_cfgs", "a vhost or a service is required");
+        }
+
+        let mut components = HashSet::new();
+
+        for (i, nginx_cfg) in self.nginx_cfgs.iter().enumerate() {
+            let field = format!("nginx_cfgs[{i}]");
+            nginx_cfg.validate(&field, &mut errors);
+
+            if !components.insert(nginx_cfg.name()) {
+                errors.push(
+                    format!("{field}.file_location"),
+                    format!("duplicate component `{}`", nginx_cfg.name()),
+                );
+            }
         }
+        for (i, service_cfg) in self.service_cfgs.iter().enumerate() {
+            let field = format!("service_cfgs[{i}]");
+            service_cfg.validate(&field, &mut errors);
+
+            if let Err(e) = service_cfg.unit_name().parse::<UnitName>() {
+                errors.push(format!("{field}.file_location"), e);
+            } else if !components.insert(service_cfg.unit_name()) {
+                errors.push(
+                    format!("{field}.file_location"),
+                    format!("duplicate component `{}`", service_cfg.unit_name()),
+                );
+            }
+        }
+
+        Ok(errors.into_result()?)
     }
+
+    /// Lint the vhosts, checking the server names against each other and the vhosts of the
+    /// `others` deployments, named by their deployment.
+    pub fn lint(&self, others: &[(&str, &NginxConfig)]) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+
+        for (i, nginx_cfg) in self.nginx_cfgs.iter().enumerate() {
+            let siblings = self
+                .nginx_cfgs
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, sibling)| (self.name.as_str(), sibling));
+            let others: Vec<_> = others.iter().copied().chain(siblings).collect();
+
+            diagnostics.extend(nginx_cfg.lint(&format!("nginx_cfgs[{i}]"), &others));
+        }
+
+        diagnostics
+    }
+
     pub fn write_config(&self) -> PiosphereResult<()> {
-        self.nginx_cfg.write_to_file()?;
-        self.service_cfg.write_to_file()?;
+        for nginx_cfg in self.nginx_cfgs.iter() {
+            nginx_cfg.write_to_file()?;
+        }
+        for service_cfg in self.service_cfgs.iter() {
+            service_cfg.write_to_file()?;
+        }
         Ok(())
     }
 }
+
+/// The file name of a config file location.
+pub(crate) fn file_name(location: &str) -> &str {
+    location.rsplit('/').next().unwrap_or(location)
+}
@@ -1,243 +1,280 @@
-use nom::{
-    self,
-    bytes::complete::{is_not, tag},
-    character::complete::char,
-    sequence::delimited,
-    IResult,
-};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
+use self::ast::{Directive, Document};
+use super::{
+    parse::ParseError,
+    validate::{validate_param, ValidationErrors},
+};
 use crate::{PiosphereError, PiosphereResult, NGINX_FILE_PATH};
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod ast;
+mod files;
+pub mod lint;
+mod listen;
+mod location;
+pub mod route;
+mod server;
+mod tls;
+mod upstream;
+
+pub use files::{GzipStatic, StaticFiles};
+pub use listen::{Listen, ListenAddress};
+pub use location::{LocationLayout, Modifier, NginxLocation};
+pub use server::{NginxServer, ServerLayout};
+pub use tls::{CertificateInfo, Hsts, Issuer, Tls, TlsProtocol, ACME_CHALLENGE_PATH};
+pub use upstream::{Balancing, NginxUpstream, UpstreamLayout, UpstreamServer};
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct NginxConfig {
     /// Absolute path to the nginx config file.
     ///
     /// By default this should be in /etc/nginx/sites-enabled
     pub file_location: String,
 
-    /// Sets the `listen` directive in the `server` to this value.
-    ///
-    /// 80 is the default.
-    pub listen: usize,
+    /// The `server` blocks of the file, e.g. a redirect from `www` next to the site itself.
+    pub servers: Vec<NginxServer>,
 
-    /// The public facing domain of the server. Used by Nginx
-    /// for pattern matching and forwarding requests.
-    ///
-    /// Example: `mysite.org`
-    pub server_name: String,
+    /// The `upstream` blocks locations can proxy to.
+    #[serde(default)]
+    pub upstreams: Vec<NginxUpstream>,
 
-    /// Location of the application's access log
-    pub access_log: Option<String>,
+    /// Directives of the file outside of the `server` and `upstream` blocks, e.g. `map`.
+    #[serde(default)]
+    pub global: Vec<Directive>,
 
-    /// Used by Nginx to determine where to forward the request, based on the url.
-    /// For example, if the location path is set to `/location/` (note the trailing slash),
-    /// all requests matching `mysite.org/location` will be forwarded to `proxy_pass`.
-    pub location: Vec<NginxLocation>,
+    /// How the file the config was parsed from is laid out, empty for new configs.
+    #[serde(default)]
+    pub layout: Layout,
 }
 
-impl NginxConfig {
-    pub fn parse(input: &str) -> Result<NginxConfig, PiosphereError> {
-        let lines = input.lines();
+/// Order and formatting of a parsed file, so that rendering the config reproduces it
+/// and only the parts which were changed differ.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Layout {
+    /// The top level directives in the order of the file.
+    items: Vec<Item>,
 
-        let mut config = NginxConfig::default();
-        let mut location = NginxLocation::default();
+    /// Whitespace and comments after the last directive.
+    trailing: Option<String>,
+}
 
-        let mut in_server = false;
-        let mut in_location = false;
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+enum Item {
+    /// The next of the [servers][NginxConfig::servers].
+    Server,
 
-        for line in lines {
-            let line = line.trim();
+    /// The next of the [upstreams][NginxConfig::upstreams].
+    Upstream,
 
-            if line.is_empty() {
-                continue;
-            }
+    /// The next of the [global][NginxConfig::global] directives.
+    Global,
+}
 
-            if line.starts_with("server") && line.ends_with('{') {
-                in_server = true;
-                continue;
-            }
+impl NginxConfig {
+    pub fn parse(input: &str) -> Result<NginxConfig, ParseError> {
+        let document = Document::parse(input)?;
 
-            if in_location && line == "}" {
-                in_location = false;
-                config.location.push(location);
-                location = NginxLocation::default();
-                continue;
-            }
+        let mut config = NginxConfig::default();
+        config.layout.trailing = document.trailing;
 
-            if in_server && line == "}" {
-                in_server = false;
-                continue;
-            }
+        let locate = |e: ParseError| e.locate(input);
 
-            if in_location {
-                if line.starts_with("proxy_pass ") {
-                    let pass: IResult<&str, &str> =
-                        delimited(tag("proxy_pass "), is_not(";"), char(';'))(line);
-                    match pass {
-                        Ok((_, pass)) => location.proxy_pass = pass.to_string(),
-                        Err(_) => {
-                            return Err(PiosphereError::NginxParse(format!(
-                                "Invalid proxy_pass at: {line}"
-                            )))
-                        }
-                    }
-                } else {
-                    let Some((key, value)) = line.split_once(' ') else {
-                        return Err(PiosphereError::NginxParse(format!(
-                            "Invalid location directive at: {line}"
-                        )));
-                    };
-                    location
-                        .directives
-                        .push((key.to_string(), value.to_string()))
+        for directive in document.directives {
+            let item = match directive.block {
+                Some(_) if directive.name == "server" => {
+                    let server = NginxServer::from_directive(directive).map_err(locate)?;
+                    config.servers.push(server);
+                    Item::Server
                 }
-                continue;
-            }
-
-            if in_server {
-                if line.starts_with("location") {
-                    in_location = true;
-                    let loc = line.split(' ');
-                    for loc in loc.skip(1).take_while(|el| *el != "{") {
-                        location.paths.push(loc.to_string());
-                    }
-                    continue;
+                Some(_) if directive.name == "upstream" => {
+                    let upstream = NginxUpstream::from_directive(directive).map_err(locate)?;
+                    config.upstreams.push(upstream);
+                    Item::Upstream
                 }
-
-                if line.ends_with(';') {
-                    let Some((key, value)) = line.split_once(' ') else {
-                        continue;
-                    };
-
-                    if value.is_empty() {
-                        continue;
-                    }
-
-                    let value = &value[..value.len() - 1];
-
-                    match key {
-                        "listen" => {
-                            config.listen = value.parse().map_err(|_| {
-                                PiosphereError::NginxParse(format!(
-                                    "Invalid `listen` port value: {value}"
-                                ))
-                            })?
-                        }
-                        "access_log" => config.access_log = Some(value.to_string()),
-                        "server_name" => config.server_name = value.to_string(),
-                        _ => {}
-                    }
+                _ => {
+                    config.global.push(directive);
+                    Item::Global
                 }
-            }
+            };
+            config.layout.items.push(item);
+        }
+
+        if config.servers.is_empty() {
+            let e = ParseError::new(input, input.len(), "No `server` block found");
+            return Err(e.expected("a `server` block"));
         }
 
+        config.find_https_redirects();
+
         Ok(config)
     }
 
+    /// The upstream block named `name`, e.g. the [host][super::validate::UpstreamUrl::host] of a `proxy_pass`.
+    pub fn upstream(&self, name: &str) -> Option<&NginxUpstream> {
+        self.upstreams
+            .iter()
+            .find(|upstream| upstream.name.as_str() == name)
+    }
+
+    /// The name of the vhost, the file name of its location, e.g. `app.example.org`.
+    pub fn name(&self) -> &str {
+        super::file_name(&self.file_location)
+    }
+
     pub fn write_to_file(&self) -> PiosphereResult<()> {
         let path = &self.file_location;
         std::fs::write(path, self.to_string()).map_err(PiosphereError::from)
     }
-}
 
-impl Default for NginxConfig {
-    fn default() -> Self {
-        Self {
-            file_location: NGINX_FILE_PATH.to_string(),
-            listen: 80,
-            server_name: Default::default(),
-            access_log: None,
-            location: vec![],
+    /// Check the values the types of the fields cannot, prefixing the fields with `field`.
+    pub(crate) fn validate(&self, field: &str, errors: &mut ValidationErrors) {
+        if self.servers.is_empty() {
+            errors.push(
+                format!("{field}.servers"),
+                "at least one server is required",
+            );
         }
+
+        for (i, server) in self.servers.iter().enumerate() {
+            server.validate(&format!("{field}.servers[{i}]"), errors);
+        }
+
+        for (i, upstream) in self.upstreams.iter().enumerate() {
+            let field = format!("{field}.upstreams[{i}]");
+
+            if self.upstreams[..i]
+                .iter()
+                .any(|other| other.name == upstream.name)
+            {
+                errors.push(
+                    format!("{field}.name"),
+                    format!("duplicate upstream `{}`", upstream.name),
+                );
+            }
+
+            upstream.validate(&field, errors);
+        }
+
+        validate_directives(&format!("{field}.global"), &self.global, errors);
     }
 }
 
-impl Display for NginxConfig {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let NginxConfig {
-            server_name,
-            listen,
-            location,
-            ..
-        } = self;
-        writeln!(f, "server {{\n  listen {listen};")?;
-        writeln!(f, "  server_name {server_name};")?;
-        for location in location {
-            writeln!(f, "  {location}")?;
-        }
-        writeln!(f, "}}")
+/// Write the `original` directive if its arguments are still `args`, otherwise a new one
+/// in its format.
+fn write_managed(
+    f: &mut std::fmt::Formatter<'_>,
+    depth: usize,
+    original: Option<&Directive>,
+    name: &str,
+    args: Vec<String>,
+) -> std::fmt::Result {
+    managed(original, name, args).write(f, depth, false)
+}
+
+/// The `original` directive if its arguments are still `args`, otherwise a new one in its format.
+fn managed(original: Option<&Directive>, name: &str, args: Vec<String>) -> Directive {
+    match original {
+        Some(original) if original.args == args => original.clone(),
+        _ => Directive {
+            format: original.map(|o| o.format.clone()).unwrap_or_default(),
+            args,
+            ..Directive::new(name, &[])
+        },
     }
 }
 
-/// Key value pairs for an Nginx location.
-#[derive(Debug, Default, Serialize, Deserialize)]
-pub struct NginxLocation {
-    /// Determines which paths will get forwarded to `proxy_pass`
-    pub paths: Vec<String>,
+/// Values cannot end the directive or open and close blocks.
+fn validate_directive_value(value: &str) -> Result<(), String> {
+    validate_param(value)?;
+    match value.chars().find(|ch| matches!(ch, ';' | '{' | '}')) {
+        Some(ch) => Err(format!("{ch:?} is not allowed")),
+        None => Ok(()),
+    }
+}
 
-    /// A list of Nginx directives inside a `location` block.
-    pub directives: Vec<(String, String)>,
+/// Names must be plain words and arguments single, possibly quoted, words.
+fn validate_directives(field: &str, directives: &[Directive], errors: &mut ValidationErrors) {
+    for (i, directive) in directives.iter().enumerate() {
+        let field = format!("{field}[{i}]");
+        let Directive {
+            name, args, block, ..
+        } = directive;
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        {
+            errors.push(&field, format!("invalid directive name `{name}`"));
+        }
 
-    /// The address where the app will be listening on.
-    pub proxy_pass: String,
+        for (j, arg) in args.iter().enumerate() {
+            if !ast::is_word(arg) {
+                errors.push(
+                    format!("{field}.args[{j}]"),
+                    format!("`{arg}` must be quoted"),
+                );
+            }
+        }
+
+        if let Some(block) = block {
+            validate_directives(&format!("{field}.block"), block, errors);
+        }
+    }
 }
 
-impl NginxLocation {
-    pub fn new() -> Self {
+impl Default for NginxConfig {
+    fn default() -> Self {
         Self {
-            paths: vec!["/".to_string()],
-            directives: vec![
-                ("proxy_set_header".to_string(), "Host $host".to_string()),
-                (
-                    "proxy_set_header".to_string(),
-                    "Upgrade $http_upgrade".to_string(),
-                ),
-                (
-                    "proxy_set_header".to_string(),
-                    "Connection \"upgrade\"".to_string(),
-                ),
-                (
-                    "proxy_set_header".to_string(),
-                    "X-Real-Ip $remote_addr".to_string(),
-                ),
-                (
-                    "proxy_set_header".to_string(),
-                    "X-Forwarded-For $proxy_add_x_forwarded_for".to_string(),
-                ),
-                (
-                    "proxy_set_header".to_string(),
-                    "X-Scheme $scheme".to_string(),
-                ),
-            ],
-
-            proxy_pass: "http://localhost:42069/".to_string(),
+            file_location: NGINX_FILE_PATH.to_string(),
+            servers: vec![],
+            upstreams: vec![],
+            global: vec![],
+            layout: Layout::default(),
         }
     }
 }
 
-impl Display for NginxLocation {
+impl Display for NginxConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let NginxLocation {
-            paths,
-            directives,
-            proxy_pass,
-        } = self;
-        write!(f, "location ")?;
-
-        for path in paths {
-            write!(f, "{path} ")?;
+        let mut servers = self.servers.iter();
+        let mut upstreams = self.upstreams.iter();
+        let mut global = self.global.iter();
+        let mut first = true;
+
+        for item in self.layout.items.iter() {
+            match item {
+                Item::Server => match servers.next() {
+                    Some(server) => server.write(f, first)?,
+                    None => continue,
+                },
+                Item::Upstream => match upstreams.next() {
+                    Some(upstream) => upstream.write(f, first)?,
+                    None => continue,
+                },
+                Item::Global => match global.next() {
+                    Some(directive) => directive.write(f, 0, first)?,
+                    None => continue,
+                },
+            }
+            first = false;
         }
 
-        writeln!(f, "{{")?;
-
-        writeln!(f, "    proxy_pass {proxy_pass};")?;
-
-        for (directive, value) in directives {
-            writeln!(f, "    {directive} {value};")?;
+        for upstream in upstreams {
+            upstream.write(f, first)?;
+            first = false;
+        }
+        for directive in global {
+            directive.write(f, 0, first)?;
+            first = false;
+        }
+        for server in servers {
+            server.write(f, first)?;
+            first = false;
         }
 
-        write!(f, "  }}")
+        Document::write_trailing(self.layout.trailing.as_deref(), f)
     }
 }
@@ -0,0 +1,480 @@
+//! Generic representation of nginx configuration files.
+//!
+//! A file is a list of [Directive]s, each with a name, its arguments and an optional
+//! block of nested directives, e.g. `server`, `location` or `if`.
+//!
+//! The whitespace and comments around the tokens are kept in the [Format] of each directive,
+//! so a parsed file renders back byte for byte.
+
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag},
+    character::complete::{anychar, char, multispace1, not_line_ending, one_of},
+    combinator::{map, not, recognize},
+    multi::{many0, many1},
+    sequence::{delimited, pair, preceded},
+    IResult,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+use crate::deployment::parse::ParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Directive {
+    pub name: String,
+
+    /// The arguments as written in the file, including quotes.
+    pub args: Vec<String>,
+
+    /// Nested directives if the directive is followed by a block instead of `;`.
+    #[serde(default)]
+    pub block: Option<Vec<Directive>>,
+
+    #[serde(default)]
+    pub format: Format,
+
+    /// Byte offset of the name in the input the directive was parsed from, used to point
+    /// at it in errors. Not serialized.
+    #[serde(skip)]
+    pub offset: Option<usize>,
+}
+
+/// Whitespace and comments around the tokens of a directive, as written in the file it was
+/// parsed from. Parts which are not set are rendered with the default indentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Format {
+    /// Before the name, including the line break and any comments above it.
+    #[serde(default)]
+    pub leading: Option<String>,
+
+    /// Before each argument, followed by the one before the `;` or `{`.
+    /// Ignored once the number of arguments changes.
+    #[serde(default)]
+    pub separators: Vec<String>,
+
+    /// Before the `}` closing the block.
+    #[serde(default)]
+    pub closing: Option<String>,
+}
+
+impl Format {
+    /// Write the leading trivia, the name and the arguments up to the `;` or `{`.
+    ///
+    /// `leading` is used when the format has none.
+    pub(crate) fn write_head<S: AsRef<str>>(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        leading: &str,
+        name: &str,
+        args: &[S],
+        block: bool,
+    ) -> std::fmt::Result {
+        f.write_str(self.leading.as_deref().unwrap_or(leading))?;
+        f.write_str(name)?;
+
+        let separators = Some(&self.separators).filter(|s| s.len() == args.len() + 1);
+
+        for (i, arg) in args.iter().enumerate() {
+            f.write_str(separators.map_or(" ", |s| &s[i]))?;
+            f.write_str(arg.as_ref())?;
+        }
+
+        match separators {
+            Some(separators) => f.write_str(&separators[args.len()]),
+            None if block => f.write_str(" "),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the trivia before the `}` of a block at `depth` and the `}` itself.
+    pub(crate) fn write_closing(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        depth: usize,
+    ) -> std::fmt::Result {
+        match self.closing {
+            Some(ref closing) => f.write_str(closing)?,
+            None => f.write_str(&leading(depth, false))?,
+        }
+        f.write_str("}")
+    }
+}
+
+/// Directives of a whole file along with the trivia after the last one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Document {
+    pub directives: Vec<Directive>,
+
+    /// Whitespace and comments at the end of the file, a line break if not set.
+    pub trailing: Option<String>,
+}
+
+impl Document {
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let parse = || {
+            let (tokens, trailing) = tokenize(input)?;
+            let mut parser = Parser {
+                tokens: tokens.into_iter().peekable(),
+                end: 0,
+            };
+
+            let directives = parser.block()?;
+
+            match parser.tokens.next() {
+                None => Ok(Self {
+                    directives,
+                    trailing: Some(trailing.to_string()),
+                }),
+                Some((offset, _, _)) => {
+                    Err(ParseError::at(offset, "unexpected `}`").expected("a directive"))
+                }
+            }
+        };
+
+        parse().map_err(|e| e.locate(input))
+    }
+
+    pub(crate) fn write_trailing(
+        trailing: Option<&str>,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.write_str(trailing.unwrap_or("\n"))
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, directive) in self.directives.iter().enumerate() {
+            directive.write(f, 0, i == 0)?;
+        }
+        Self::write_trailing(self.trailing.as_deref(), f)
+    }
+}
+
+impl Directive {
+    pub fn new(name: &str, args: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            block: None,
+            format: Format::default(),
+            offset: None,
+        }
+    }
+
+    pub fn block(name: &str, args: &[&str], block: Vec<Directive>) -> Self {
+        Self {
+            block: Some(block),
+            ..Self::new(name, args)
+        }
+    }
+
+    /// The first argument without quotes.
+    pub fn value(&self) -> Option<String> {
+        self.args.first().map(|arg| unquote(arg))
+    }
+
+    /// An error pointing at the first argument, or at the name if there is none.
+    pub(crate) fn invalid(&self, message: impl Into<String>) -> ParseError {
+        self.invalid_arg(0, message)
+    }
+
+    /// An error pointing at the argument `i`, or at the name if the directive has no such
+    /// argument or was not parsed.
+    pub(crate) fn invalid_arg(&self, i: usize, message: impl Into<String>) -> ParseError {
+        let offset = self.offset.unwrap_or_default();
+        let separators = &self.format.separators;
+
+        let offset = match separators.len() == self.args.len() + 1 && i < self.args.len() {
+            true => {
+                let before: usize = (0..i)
+                    .map(|k| separators[k].len() + self.args[k].len())
+                    .sum();
+                offset + self.name.len() + before + separators[i].len()
+            }
+            false => offset,
+        };
+
+        ParseError::at(offset, message)
+    }
+
+    /// Write the directive nested `depth` blocks deep, `first` if nothing precedes it in the file.
+    pub(crate) fn write(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        depth: usize,
+        first: bool,
+    ) -> std::fmt::Result {
+        let leading = leading(depth, first);
+        self.format
+            .write_head(f, &leading, &self.name, &self.args, self.block.is_some())?;
+
+        let Some(ref block) = self.block else {
+            return f.write_str(";");
+        };
+
+        f.write_str("{")?;
+        for directive in block {
+            directive.write(f, depth + 1, false)?;
+        }
+        self.format.write_closing(f, depth)
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, 0, true)
+    }
+}
+
+/// The default trivia before a directive: a line break and the indentation of its depth.
+pub(crate) fn leading(depth: usize, first: bool) -> String {
+    match first {
+        true => String::new(),
+        false => format!("\n{}", INDENT.repeat(depth)),
+    }
+}
+
+/// Remove the quotes around an argument and resolve its escapes like nginx does.
+///
+/// Only `\"`, `\'`, `\\`, `\t`, `\r` and `\n` are escapes, other backslashes are kept,
+/// e.g. in regexes.
+pub fn unquote(arg: &str) -> String {
+    let quoted = arg.len() >= 2
+        && (arg.starts_with('"') && arg.ends_with('"')
+            || arg.starts_with('\'') && arg.ends_with('\''));
+
+    let arg = match quoted {
+        true => &arg[1..arg.len() - 1],
+        false => arg,
+    };
+
+    let mut value = String::new();
+    let mut chars = arg.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let escaped = match (ch, chars.peek()) {
+            ('\\', Some(ch @ ('"' | '\'' | '\\'))) => *ch,
+            ('\\', Some('t')) => '\t',
+            ('\\', Some('r')) => '\r',
+            ('\\', Some('n')) => '\n',
+            (ch, _) => {
+                value.push(ch);
+                continue;
+            }
+        };
+        chars.next();
+        value.push(escaped);
+    }
+    value
+}
+
+/// Write `value` as a single argument, quoting it if necessary.
+pub fn quote(value: &str) -> String {
+    if is_word(value) && unquote(value) == value {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    for ch in value.chars() {
+        match ch {
+            '"' | '\\' => quoted.extend(['\\', ch]),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\n' => quoted.push_str("\\n"),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Whether `arg` is written as a single argument, i.e. anything that could end
+/// the directive or start a block is quoted.
+pub fn is_word(arg: &str) -> bool {
+    matches!(token(arg), Ok(("", Token::Word(_))))
+}
+
+pub(crate) const INDENT: &str = "  ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    Open,
+    Close,
+    End,
+}
+
+/// A token with the byte offset it starts at and the whitespace and comments before it.
+type Spanned<'a> = (usize, &'a str, Token<'a>);
+
+impl Token<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Token::Word(word) => word.len(),
+            _ => 1,
+        }
+    }
+}
+
+/// Split the input into tokens, returning the trivia after the last one separately.
+fn tokenize(input: &str) -> Result<(Vec<Spanned<'_>>, &str), ParseError> {
+    let mut tokens = vec![];
+    let mut rest = input;
+
+    loop {
+        let (next, _) = trivia(rest).expect("trivia cannot fail");
+        let before = &rest[..rest.len() - next.len()];
+        rest = next;
+
+        if rest.is_empty() {
+            return Ok((tokens, before));
+        }
+
+        let offset = input.len() - rest.len();
+
+        match token(rest) {
+            Ok((next, token)) => {
+                tokens.push((offset, before, token));
+                rest = next;
+            }
+            Err(_) => {
+                let quote = &rest[..1];
+                return Err(ParseError::at(offset, "unterminated quoted string")
+                    .expected(format!("a closing `{quote}`")));
+            }
+        }
+    }
+}
+
+/// Whitespace and comments.
+fn trivia(input: &str) -> IResult<&str, Vec<&str>> {
+    many0(alt((
+        multispace1,
+        recognize(pair(char('#'), not_line_ending)),
+    )))(input)
+}
+
+fn token(input: &str) -> IResult<&str, Token<'_>> {
+    alt((
+        map(char('{'), |_| Token::Open),
+        map(char('}'), |_| Token::Close),
+        map(char(';'), |_| Token::End),
+        map(quoted('"'), Token::Word),
+        map(quoted('\''), Token::Word),
+        // An opening quote without its closing one is not a word
+        map(preceded(not(one_of("\"'")), word), Token::Word),
+    ))(input)
+}
+
+/// A quoted string including the quotes, with `\` escaping the next character.
+fn quoted(quote: char) -> impl FnMut(&str) -> IResult<&str, &str> {
+    move |input| {
+        let delimiters = if quote == '"' { "\\\"" } else { "\\'" };
+        recognize(delimited(
+            char(quote),
+            many0(alt((
+                is_not(delimiters),
+                recognize(pair(char('\\'), anychar)),
+            ))),
+            char(quote),
+        ))(input)
+    }
+}
+
+/// An unquoted word. Braces only end it when not part of a `${variable}`.
+fn word(input: &str) -> IResult<&str, &str> {
+    recognize(many1(alt((
+        is_not(" \t\r\n;{}$"),
+        recognize(delimited(tag("${"), is_not("}"), char('}'))),
+        recognize(char('$')),
+    ))))(input)
+}
+
+struct Parser<I: Iterator> {
+    tokens: std::iter::Peekable<I>,
+
+    /// Byte offset after the last consumed token.
+    end: usize,
+}
+
+impl<'a, I: Iterator<Item = Spanned<'a>>> Parser<I> {
+    fn next(&mut self) -> Option<Spanned<'a>> {
+        let next = self.tokens.next();
+        if let Some((offset, _, token)) = next {
+            self.end = offset + token.len();
+        }
+        next
+    }
+
+    /// Directives until the end of the input or a `}`, which is not consumed.
+    fn block(&mut self) -> Result<Vec<Directive>, ParseError> {
+        let mut directives = vec![];
+
+        while let Some(&(offset, leading, token)) = self.tokens.peek() {
+            let name = match token {
+                Token::Word(name) => name,
+                Token::Close => break,
+                Token::Open => {
+                    return Err(ParseError::at(offset, "unexpected `{`").expected("a directive"))
+                }
+                Token::End => {
+                    return Err(ParseError::at(offset, "unexpected `;`").expected("a directive"))
+                }
+            };
+            self.next();
+
+            let mut args = vec![];
+            let mut format = Format {
+                leading: Some(leading.to_string()),
+                ..Default::default()
+            };
+
+            let block = loop {
+                let missing_end = |offset| {
+                    ParseError::at(offset, format!("`{name}` is missing its terminating `;`"))
+                        .expected("`;`")
+                };
+
+                let end = self.end;
+                let Some((token_offset, separator, token)) = self.next() else {
+                    return Err(missing_end(end));
+                };
+
+                format.separators.push(separator.to_string());
+
+                match token {
+                    Token::Word(arg) => args.push(arg.to_string()),
+                    Token::End => break None,
+                    Token::Open => {
+                        let block = self.block()?;
+                        match self.next() {
+                            Some((_, closing, Token::Close)) => {
+                                format.closing = Some(closing.to_string())
+                            }
+                            _ => {
+                                return Err(ParseError::at(
+                                    token_offset,
+                                    format!("`{name}` block is missing its closing `}}`"),
+                                ))
+                            }
+                        }
+                        break Some(block);
+                    }
+                    Token::Close => return Err(missing_end(token_offset)),
+                }
+            };
+
+            directives.push(Directive {
+                name: name.to_string(),
+                args,
+                block,
+                format,
+                offset: Some(offset),
+            });
+        }
+
+        Ok(directives)
+    }
+}
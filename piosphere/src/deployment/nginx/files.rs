@@ -0,0 +1,273 @@
+//! Files served by a location from disk, e.g. the build of a static site.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+use super::{
+    ast::{self, Directive},
+    validate_directive_value,
+};
+use crate::deployment::{parse::ParseError, validate::ValidationErrors};
+
+/// How a location serves files, written as its `root`, `alias`, `index`, `try_files`,
+/// `expires` and `gzip_static` directives.
+///
+/// A location with neither `root` nor `alias` serves the files of the `root` of its server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct StaticFiles {
+    /// Directory the request URI is appended to, e.g. `/var/www/site`.
+    #[serde(default)]
+    pub root: Option<String>,
+
+    /// Directory replacing the path of the location in the request URI,
+    /// e.g. `/var/www/assets/` for `location /static/`.
+    #[serde(default)]
+    pub alias: Option<String>,
+
+    /// Files served for URIs ending with `/`, `index.html` if empty.
+    #[serde(default)]
+    pub index: Vec<String>,
+
+    /// Files tried in order, the last one being the URI or `=code` used if none exists,
+    /// e.g. `$uri`, `$uri/`, `/index.html`.
+    #[serde(default)]
+    pub try_files: Vec<String>,
+
+    /// How long clients may cache the files, e.g. `30d`, `max` or `off`.
+    #[serde(default)]
+    pub expires: Option<String>,
+
+    /// Serve the precompressed `.gz` file next to the requested one.
+    #[serde(default)]
+    pub gzip_static: Option<GzipStatic>,
+}
+
+/// When the precompressed `.gz` file of a request is served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GzipStatic {
+    /// To clients accepting gzip, `gzip_static on`.
+    On,
+
+    /// To all clients, `gzip_static always`.
+    Always,
+}
+
+/// The directives of the location [StaticFiles] is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub(super) enum FilesDirective {
+    Root,
+    Alias,
+    Index,
+    TryFiles,
+    Expires,
+    GzipStatic,
+}
+
+impl StaticFiles {
+    /// Serve the site at `dir`, with `index.html` for directories and a 404 for missing files.
+    pub fn root(dir: &str) -> Self {
+        Self {
+            root: Some(dir.to_string()),
+            index: vec!["index.html".to_string()],
+            try_files: vec!["$uri".to_string(), "$uri/".to_string(), "=404".to_string()],
+            ..Default::default()
+        }
+    }
+
+    /// The directory the files are served from, None if it is the one of the server.
+    pub fn dir(&self) -> Option<&str> {
+        self.root.as_deref().or(self.alias.as_deref())
+    }
+
+    /// Whether requests are answered from a directory or by `try_files`, rather than from
+    /// the html directory of nginx.
+    pub fn serves(&self) -> bool {
+        self.dir().is_some() || !self.try_files.is_empty()
+    }
+
+    /// Set the setting of `directive`, which is of `kind`.
+    pub(super) fn apply(
+        &mut self,
+        kind: FilesDirective,
+        directive: &Directive,
+    ) -> Result<(), ParseError> {
+        let name = &directive.name;
+        let single = || match directive.args.len() {
+            1 => Ok(directive.value().unwrap_or_default()),
+            n => Err(directive.invalid(format!(
+                "Invalid `{name}`: expected a single value, found {n} arguments"
+            ))),
+        };
+        let list = || match directive.args.is_empty() {
+            true => Err(directive.invalid(format!("Invalid `{name}`: expected a value"))),
+            false => Ok(directive.args.iter().map(|arg| ast::unquote(arg)).collect()),
+        };
+
+        match kind {
+            FilesDirective::Root => self.root = Some(single()?),
+            FilesDirective::Alias => self.alias = Some(single()?),
+            FilesDirective::Index => self.index = list()?,
+            FilesDirective::TryFiles => self.try_files = list()?,
+            FilesDirective::Expires => {
+                self.expires = Some(list()?.join(" "));
+            }
+            FilesDirective::GzipStatic => {
+                self.gzip_static = match single()?.as_str() {
+                    "off" => None,
+                    value => Some(
+                        value
+                            .parse()
+                            .map_err(|e| directive.invalid(format!("Invalid `{name}`: {e}")))?,
+                    ),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The arguments of the directive of `kind`, None if it is not written. Those of the
+    /// `original` directive are kept as long as it says the same.
+    pub(super) fn to_args(
+        &self,
+        kind: FilesDirective,
+        original: Option<&Directive>,
+    ) -> Option<Vec<String>> {
+        if let Some(original) = original {
+            let mut parsed = self.clone();
+            if parsed.apply(kind, original).is_ok() && parsed == *self {
+                return Some(original.args.clone());
+            }
+        }
+
+        let quote_all = |values: &[String]| values.iter().map(|value| ast::quote(value)).collect();
+
+        let args = match kind {
+            FilesDirective::Root => vec![ast::quote(self.root.as_ref()?)],
+            FilesDirective::Alias => vec![ast::quote(self.alias.as_ref()?)],
+            FilesDirective::Index if self.index.is_empty() => return None,
+            FilesDirective::Index => quote_all(&self.index),
+            FilesDirective::TryFiles if self.try_files.is_empty() => return None,
+            FilesDirective::TryFiles => quote_all(&self.try_files),
+            FilesDirective::Expires => {
+                let expires = self.expires.as_ref()?;
+                expires
+                    .split_whitespace()
+                    .map(ToString::to_string)
+                    .collect()
+            }
+            FilesDirective::GzipStatic => vec![self.gzip_static?.to_string()],
+        };
+        Some(args)
+    }
+
+    /// Check the values the types of the fields cannot, prefixing the fields with `field`.
+    pub(super) fn validate(&self, field: &str, errors: &mut ValidationErrors) {
+        if self.root.is_some() && self.alias.is_some() {
+            errors.push(
+                format!("{field}.alias"),
+                "`root` and `alias` cannot be combined",
+            );
+        }
+
+        for (name, value) in [("root", &self.root), ("alias", &self.alias)] {
+            match value.as_deref() {
+                Some("") => {
+                    errors.push(format!("{field}.{name}"), format!("{name} cannot be empty"))
+                }
+                Some(dir) => {
+                    if let Err(e) = validate_directive_value(dir) {
+                        errors.push(format!("{field}.{name}"), e);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        for (name, values) in [("index", &self.index), ("try_files", &self.try_files)] {
+            for (i, value) in values.iter().enumerate() {
+                if value.is_empty() {
+                    errors.push(format!("{field}.{name}[{i}]"), "value cannot be empty");
+                } else if let Err(e) = validate_directive_value(value) {
+                    errors.push(format!("{field}.{name}[{i}]"), e);
+                }
+            }
+        }
+
+        // nginx needs a file and the fallback
+        if self.try_files.len() == 1 {
+            errors.push(
+                format!("{field}.try_files"),
+                "at least a file and the fallback are required",
+            );
+        }
+
+        if let Some(ref expires) = self.expires {
+            if expires.trim().is_empty() {
+                errors.push(format!("{field}.expires"), "expires cannot be empty");
+            } else if let Err(e) = validate_directive_value(expires) {
+                errors.push(format!("{field}.expires"), e);
+            }
+        }
+    }
+}
+
+impl FilesDirective {
+    /// In the order new directives are written.
+    pub(super) const ALL: [Self; 6] = [
+        Self::Root,
+        Self::Alias,
+        Self::Index,
+        Self::TryFiles,
+        Self::Expires,
+        Self::GzipStatic,
+    ];
+
+    /// The kind of `directive`, if it is one [StaticFiles] is written as.
+    pub(super) fn of(directive: &Directive) -> Option<Self> {
+        let kind = match directive.name.as_str() {
+            "root" => Self::Root,
+            "alias" => Self::Alias,
+            "index" => Self::Index,
+            "try_files" => Self::TryFiles,
+            "expires" => Self::Expires,
+            "gzip_static" => Self::GzipStatic,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            Self::Root => "root",
+            Self::Alias => "alias",
+            Self::Index => "index",
+            Self::TryFiles => "try_files",
+            Self::Expires => "expires",
+            Self::GzipStatic => "gzip_static",
+        }
+    }
+}
+
+impl FromStr for GzipStatic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "on" => Ok(Self::On),
+            "always" => Ok(Self::Always),
+            _ => Err(format!("expected `on`, `off` or `always`, found `{s}`")),
+        }
+    }
+}
+
+impl Display for GzipStatic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::On => "on",
+            Self::Always => "always",
+        })
+    }
+}
@@ -0,0 +1,461 @@
+//! Finds mistakes in vhosts which nginx refuses to load, or loads while routing requests
+//! differently than intended.
+//!
+//! Deployments are linted before their files are written, [errors][Severity::Error] reject
+//! the request like validation errors do, warnings are only reported by `LintDeployment`.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+use super::{
+    route::match_location, Listen, ListenAddress, Modifier, NginxConfig, NginxLocation,
+    NginxServer, StaticFiles,
+};
+use crate::deployment::validate::{Port, ValidationErrors};
+
+/// A problem found in a config, located by the field it was found in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Diagnostic {
+    pub lint: Lint,
+
+    pub severity: Severity,
+
+    /// The field of the deployment, e.g. `nginx_cfgs[0].servers[0].location[1]`.
+    pub field: String,
+
+    pub message: String,
+
+    /// How to fix the problem.
+    pub help: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// nginx refuses to load the config, or ignores a part of it.
+    Error,
+
+    /// The config loads, but likely does not do what was meant.
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lint {
+    /// Two locations of a server with the same path and kind of match.
+    DuplicateLocation,
+
+    /// A location without `proxy_pass` or any other directive producing a response.
+    MissingProxyPass,
+
+    /// A `server_name` served on the same port by another server, of this or another deployment.
+    ConflictingServerName,
+
+    /// A location no request is routed to.
+    UnreachableLocation,
+
+    /// The location and the URI of its `proxy_pass` disagree on the trailing slash.
+    TrailingSlash,
+
+    /// A `proxy_pass` with a URI in a regex or named location.
+    ProxyPassUri,
+}
+
+/// Directives producing a response without `proxy_pass`.
+const HANDLERS: &[&str] = &[
+    "fastcgi_pass",
+    "uwsgi_pass",
+    "scgi_pass",
+    "grpc_pass",
+    "memcached_pass",
+    "return",
+    "rewrite",
+    "root",
+    "alias",
+    "try_files",
+    "deny",
+    "stub_status",
+    "location",
+    "if",
+];
+
+impl Lint {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::DuplicateLocation | Self::ConflictingServerName | Self::ProxyPassUri => {
+                Severity::Error
+            }
+            Self::MissingProxyPass | Self::UnreachableLocation | Self::TrailingSlash => {
+                Severity::Warning
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DuplicateLocation => "duplicate-location",
+            Self::MissingProxyPass => "missing-proxy-pass",
+            Self::ConflictingServerName => "conflicting-server-name",
+            Self::UnreachableLocation => "unreachable-location",
+            Self::TrailingSlash => "trailing-slash",
+            Self::ProxyPassUri => "proxy-pass-uri",
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{severity}[{}] {}: {}",
+            self.lint.as_str(),
+            self.field,
+            self.message
+        )?;
+        match self.help {
+            Some(ref help) => write!(f, "\n  help: {help}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reject the [errors][Severity::Error] among `diagnostics`, warnings pass.
+pub fn deny_errors(diagnostics: &[Diagnostic]) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    for diagnostic in diagnostics {
+        if diagnostic.severity == Severity::Error {
+            let message = match diagnostic.help {
+                Some(ref help) => format!("{}, {help}", diagnostic.message),
+                None => diagnostic.message.clone(),
+            };
+            errors.push(&diagnostic.field, message);
+        }
+    }
+
+    errors.into_result()
+}
+
+/// Collects the diagnostics of a config.
+struct Linter<'a> {
+    field: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn push(&mut self, lint: Lint, field: String, message: String, help: Option<String>) {
+        self.diagnostics.push(Diagnostic {
+            lint,
+            severity: lint.severity(),
+            field: format!("{}.{field}", self.field),
+            message,
+            help,
+        });
+    }
+}
+
+impl NginxConfig {
+    /// Lint the config, prefixing the fields with `field`. The server names are checked
+    /// against the configs of the other deployments in `others`, named by their deployment.
+    pub fn lint(&self, field: &str, others: &[(&str, &NginxConfig)]) -> Vec<Diagnostic> {
+        let mut linter = Linter {
+            field,
+            diagnostics: vec![],
+        };
+
+        for (i, server) in self.servers.iter().enumerate() {
+            lint_server_names(&mut linter, i, &self.servers, others);
+            lint_server(&mut linter, &format!("servers[{i}]"), server);
+        }
+
+        linter.diagnostics
+    }
+}
+
+/// nginx warns about a name served by several servers on the same address and ignores it
+/// for all of them but the first.
+fn lint_server_names(
+    linter: &mut Linter,
+    i: usize,
+    servers: &[NginxServer],
+    others: &[(&str, &NginxConfig)],
+) {
+    let server = &servers[i];
+    let addresses = addresses_of(server);
+
+    // The address both servers listen on, if `other` serves `name`
+    let serves = |other: &NginxServer, name: &str| {
+        let named = other
+            .server_name
+            .iter()
+            .any(|other| other.as_str().eq_ignore_ascii_case(name));
+        let theirs = addresses_of(other);
+
+        addresses
+            .iter()
+            .find(|address| named && theirs.contains(address))
+            .cloned()
+    };
+
+    for (k, name) in server.server_name.iter().enumerate() {
+        let field = format!("servers[{i}].server_name[{k}]");
+        let name = name.as_str();
+
+        let earlier = servers[..i]
+            .iter()
+            .enumerate()
+            .find_map(|(j, other)| serves(other, name).map(|address| (j, address)));
+
+        if let Some((j, address)) = earlier {
+            linter.push(
+                Lint::ConflictingServerName,
+                field,
+                format!("`{name}` on `{address}` is already served by servers[{j}]"),
+                Some("nginx only uses the first of them, merge the two servers".to_string()),
+            );
+            continue;
+        }
+
+        let deployment = others.iter().find_map(|(deployment, config)| {
+            config
+                .servers
+                .iter()
+                .find_map(|other| serves(other, name))
+                .map(|address| (deployment, address))
+        });
+        if let Some((deployment, address)) = deployment {
+            linter.push(
+                Lint::ConflictingServerName,
+                field,
+                format!("`{name}` on `{address}` is already served by deployment `{deployment}`"),
+                Some(
+                    "use another name or port, or remove it from the other deployment".to_string(),
+                ),
+            );
+        }
+    }
+}
+
+/// The addresses the server listens on, port 80 if it has no `listen`.
+fn addresses_of(server: &NginxServer) -> Vec<ListenAddress> {
+    match server.listen.is_empty() {
+        true => vec![Listen::new(Port::HTTP).address],
+        false => server
+            .listen
+            .iter()
+            .map(|listen| listen.address.clone())
+            .collect(),
+    }
+}
+
+fn lint_server(linter: &mut Linter, field: &str, server: &NginxServer) {
+    let has_root = server.directives.iter().any(|d| d.name == "root");
+
+    // `return` is executed before a location is searched for
+    if let Some(d) = server.directives.iter().position(|d| d.name == "return") {
+        if !server.location.is_empty() {
+            linter.push(
+                Lint::UnreachableLocation,
+                format!("{field}.directives[{d}]"),
+                format!(
+                    "`return` answers every request, the {} locations of the server are never used",
+                    server.location.len()
+                ),
+                Some("move the `return` into a `location /` block".to_string()),
+            );
+        }
+    }
+
+    for (j, location) in server.location.iter().enumerate() {
+        let field = format!("{field}.location[{j}]");
+        let same = server.location[..j]
+            .iter()
+            .position(|other| key(other) == key(location));
+
+        match same {
+            Some(l) if location.modifier.is_regex() => linter.push(
+                Lint::UnreachableLocation,
+                field.clone(),
+                format!(
+                    "{} is never used, the same regex of location[{l}] is tried first",
+                    describe(location)
+                ),
+                Some("remove one of them".to_string()),
+            ),
+            Some(l) => linter.push(
+                Lint::DuplicateLocation,
+                field.clone(),
+                format!(
+                    "duplicate {}, already defined by location[{l}]",
+                    describe(location)
+                ),
+                Some("nginx refuses to load the file, merge the two blocks".to_string()),
+            ),
+            None => lint_reachable(linter, &field, server, location),
+        }
+
+        if location.proxy_pass.is_none()
+            && !has_root
+            && !location.files.as_ref().is_some_and(StaticFiles::serves)
+            && !location
+                .directives
+                .iter()
+                .any(|d| HANDLERS.contains(&d.name.as_str()))
+        {
+            linter.push(
+                Lint::MissingProxyPass,
+                field.clone(),
+                format!("{} has no `proxy_pass`", describe(location)),
+                Some(
+                    "without it or a `root`, files are served from the html directory of nginx"
+                        .to_string(),
+                ),
+            );
+        }
+
+        lint_proxy_pass(linter, &field, location);
+    }
+}
+
+/// Warn about locations the requests they are meant for never reach.
+fn lint_reachable(
+    linter: &mut Linter,
+    field: &str,
+    server: &NginxServer,
+    location: &NginxLocation,
+) {
+    if location.modifier.is_regex() || location.is_named() {
+        return;
+    }
+
+    if !location.path.starts_with('/') {
+        linter.push(
+            Lint::UnreachableLocation,
+            field.to_string(),
+            format!(
+                "{} never matches, request URIs start with `/`",
+                describe(location)
+            ),
+            Some(format!("use `/{}`", location.path)),
+        );
+        return;
+    }
+
+    if location.modifier == Modifier::Exact {
+        return;
+    }
+
+    // A prefix is only unreachable if the URIs right below it are all taken by regexes
+    let path = &location.path;
+    let below = path.trim_end_matches('/');
+    let probes = [
+        path.clone(),
+        format!("{below}/a"),
+        format!("{below}/a/b.html"),
+    ];
+
+    let mut taken_by = None;
+    for probe in probes.iter() {
+        match match_location(server, probe, &mut vec![]) {
+            Some(other) if std::ptr::eq(other, location) => return,
+            other => taken_by = taken_by.or(other),
+        }
+    }
+
+    let Some(other) = taken_by else {
+        return;
+    };
+    let l = server
+        .location
+        .iter()
+        .position(|l| std::ptr::eq(l, other))
+        .unwrap_or_default();
+
+    let help = match location.modifier {
+        Modifier::Prefix if other.modifier.is_regex() => {
+            format!("use `location ^~ {path}` to take precedence over regexes")
+        }
+        _ => "remove it or change the path".to_string(),
+    };
+
+    linter.push(
+        Lint::UnreachableLocation,
+        field.to_string(),
+        format!(
+            "{} is never used, requests below `{path}` go to {} of location[{l}]",
+            describe(location),
+            describe(other)
+        ),
+        Some(help),
+    );
+}
+
+fn lint_proxy_pass(linter: &mut Linter, field: &str, location: &NginxLocation) {
+    let Some(ref proxy_pass) = location.proxy_pass else {
+        return;
+    };
+    let field = format!("{field}.proxy_pass");
+
+    // With variables, the url is used as it is
+    let Some(uri) = proxy_pass
+        .uri()
+        .filter(|_| !proxy_pass.as_str().contains('$'))
+    else {
+        return;
+    };
+
+    if location.modifier.is_regex() || location.is_named() {
+        let kind = match location.is_named() {
+            true => "a named location",
+            false => "a location given by a regex",
+        };
+        linter.push(
+            Lint::ProxyPassUri,
+            field,
+            format!("`proxy_pass` cannot have a URI part in {kind}, found `{uri}`"),
+            Some("remove the URI, the request URI is passed as it is".to_string()),
+        );
+        return;
+    }
+
+    let path = &location.path;
+    if location.modifier != Modifier::Exact && path.ends_with('/') != uri.ends_with('/') {
+        let below = path.trim_end_matches('/');
+        let example = format!("{below}/users");
+        let passed = format!("{uri}{}", &example[path.len().min(example.len())..]);
+
+        linter.push(
+            Lint::TrailingSlash,
+            field,
+            format!(
+                "{} replaces `{path}` with `{uri}`, `{example}` is passed as `{passed}`",
+                describe(location)
+            ),
+            Some("end both the location and the `proxy_pass` URI with `/`, or neither".to_string()),
+        );
+    }
+}
+
+/// Locations nginx considers the same, prefixes with and without `^~` conflict.
+fn key(location: &NginxLocation) -> (u8, &str) {
+    let kind = match location.modifier {
+        Modifier::Prefix | Modifier::PreferPrefix => 0,
+        Modifier::Exact => 1,
+        Modifier::Regex => 2,
+        Modifier::RegexCaseless => 3,
+    };
+    (kind, &location.path)
+}
+
+/// The location as written in the file, e.g. location `= /`.
+fn describe(location: &NginxLocation) -> String {
+    match location.modifier {
+        Modifier::Prefix => format!("location `{}`", location.path),
+        modifier => format!("location `{modifier} {}`", location.path),
+    }
+}
@@ -0,0 +1,285 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+use super::ast;
+use crate::deployment::validate::{validate_authority, validate_param, Port, ValidationErrors};
+
+/// A `listen` directive of a server, e.g. `listen [::]:443 ssl http2 default_server;`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Listen {
+    pub address: ListenAddress,
+
+    /// Accept TLS connections, the server needs a certificate.
+    #[serde(default)]
+    pub ssl: bool,
+
+    /// Accept HTTP/2 connections. Deprecated since nginx 1.25.1 in favor of `http2 on;`.
+    #[serde(default)]
+    pub http2: bool,
+
+    /// Expect the PROXY protocol header of a load balancer in front of nginx.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// Serve the requests of the address no other server name matches. Also written as `default`.
+    #[serde(default)]
+    pub default_server: bool,
+
+    /// Open a socket per worker process.
+    #[serde(default)]
+    pub reuseport: bool,
+
+    /// The other parameters as written, e.g. `backlog=511` or `ipv6only=on`.
+    #[serde(default)]
+    pub params: Vec<String>,
+}
+
+/// Where a server listens, serialized as written in the directive, e.g. `[::]:443`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    /// A port on all addresses if `host` is not set, e.g. `80`, otherwise on the address or
+    /// hostname, e.g. `127.0.0.1:8080` or `[::]:443`. IPv6 addresses keep their brackets.
+    Inet { host: Option<String>, port: Port },
+
+    /// The path of a unix socket, e.g. `/run/nginx/app.sock` for `unix:/run/nginx/app.sock`.
+    Unix(String),
+}
+
+/// The flags of [Listen] in the order they are written.
+const FLAGS: [&str; 5] = [
+    "default_server",
+    "ssl",
+    "http2",
+    "proxy_protocol",
+    "reuseport",
+];
+
+impl Listen {
+    pub fn new(port: Port) -> Self {
+        Self {
+            address: ListenAddress::Inet { host: None, port },
+            ssl: false,
+            http2: false,
+            proxy_protocol: false,
+            default_server: false,
+            reuseport: false,
+            params: vec![],
+        }
+    }
+
+    /// The port, None for unix sockets.
+    pub fn port(&self) -> Option<Port> {
+        match self.address {
+            ListenAddress::Inet { port, .. } => Some(port),
+            ListenAddress::Unix(_) => None,
+        }
+    }
+
+    /// Parse the arguments of a `listen` directive, failing with the index of the rejected one.
+    pub(super) fn from_args(args: &[String]) -> Result<Self, (usize, String)> {
+        let Some(address) = args.first() else {
+            return Err((0, "expected an address or a port".to_string()));
+        };
+
+        let address = ast::unquote(address).parse().map_err(|e| (0, e))?;
+        let mut listen = Self {
+            address,
+            ..Self::new(Port::HTTP)
+        };
+
+        for arg in args[1..].iter() {
+            match listen.flag(arg) {
+                Some(flag) => *flag = true,
+                None => listen.params.push(arg.clone()),
+            }
+        }
+
+        Ok(listen)
+    }
+
+    /// The arguments of the directive. Those of the `original` directive are kept in their
+    /// order as long as they still apply, new ones are appended.
+    pub(super) fn to_args(&self, original: Option<&[String]>) -> Vec<String> {
+        let original = original.unwrap_or_default();
+        let mut args = vec![];
+
+        match original.first() {
+            Some(address) if ast::unquote(address).parse().as_ref() == Ok(&self.address) => {
+                args.push(address.clone())
+            }
+            _ => args.push(self.address.to_string()),
+        }
+
+        let mut flags = FLAGS.map(|name| (name, false));
+        let mut params = vec![];
+
+        for arg in original.iter().skip(1) {
+            match FLAGS.iter().position(|name| *name == canonical(arg)) {
+                Some(i) if self.is_set(i) && !flags[i].1 => {
+                    flags[i].1 = true;
+                    args.push(arg.clone());
+                }
+                Some(_) => {}
+                None if self.params.contains(arg) && !params.contains(&arg) => {
+                    params.push(arg);
+                    args.push(arg.clone());
+                }
+                None => {}
+            }
+        }
+
+        for (i, (name, written)) in flags.into_iter().enumerate() {
+            if self.is_set(i) && !written {
+                args.push(name.to_string());
+            }
+        }
+        for param in self.params.iter() {
+            if !params.contains(&param) {
+                args.push(param.clone());
+            }
+        }
+
+        args
+    }
+
+    /// Check the values the types of the fields cannot, prefixing the fields with `field`.
+    pub(super) fn validate(&self, field: &str, errors: &mut ValidationErrors) {
+        if let Err(e) = self.address.validate() {
+            errors.push(format!("{field}.address"), e);
+        }
+
+        for (i, param) in self.params.iter().enumerate() {
+            let field = format!("{field}.params[{i}]");
+
+            if FLAGS.contains(&canonical(param)) {
+                errors.push(&field, format!("`{param}` has a field of its own"));
+            } else if param.is_empty() || !ast::is_word(param) || param.starts_with(['"', '\'']) {
+                errors.push(&field, format!("invalid parameter `{param}`"));
+            }
+        }
+    }
+
+    fn flag(&mut self, arg: &str) -> Option<&mut bool> {
+        match canonical(arg) {
+            "default_server" => Some(&mut self.default_server),
+            "ssl" => Some(&mut self.ssl),
+            "http2" => Some(&mut self.http2),
+            "proxy_protocol" => Some(&mut self.proxy_protocol),
+            "reuseport" => Some(&mut self.reuseport),
+            _ => None,
+        }
+    }
+
+    /// Whether the flag at index `i` of [FLAGS] is set.
+    fn is_set(&self, i: usize) -> bool {
+        [
+            self.default_server,
+            self.ssl,
+            self.http2,
+            self.proxy_protocol,
+            self.reuseport,
+        ][i]
+    }
+}
+
+/// `default` is the old name of `default_server`.
+fn canonical(arg: &str) -> &str {
+    match arg {
+        "default" => "default_server",
+        arg => arg,
+    }
+}
+
+impl ListenAddress {
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Inet {
+                host: Some(host),
+                port,
+            } => validate_authority(&format!("{host}:{port}"))
+                .map_err(|e| format!("invalid listen address `{host}`: {e}")),
+            Self::Inet { host: None, .. } => Ok(()),
+            Self::Unix(path) if !path.starts_with('/') => {
+                Err(format!("unix socket path `{path}` must be absolute"))
+            }
+            Self::Unix(path) => match path.chars().find(|ch| ch.is_whitespace()) {
+                Some(ch) => Err(format!("invalid character {ch:?} in unix socket path")),
+                None => validate_param(path),
+            },
+        }
+    }
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    /// Parse `80`, `127.0.0.1`, `*:80`, `[::1]:8080`, `localhost:8080` or `unix:/path`.
+    /// Without a port, nginx listens on 80.
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            let address = Self::Unix(path.to_string());
+            address.validate()?;
+            return Ok(address);
+        }
+
+        let (host, port) = match address.rsplit_once(':') {
+            // The colons of an IPv6 address without a port
+            Some(_) if address.ends_with(']') => (Some(address), Port::HTTP),
+            Some((host, port)) => (Some(host), port.parse()?),
+            None if address.bytes().all(|b| b.is_ascii_digit()) => (None, address.parse()?),
+            None => (Some(address), Port::HTTP),
+        };
+
+        let address = Self::Inet {
+            host: host.filter(|host| *host != "*").map(ToString::to_string),
+            port,
+        };
+        address.validate()?;
+        Ok(address)
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inet { host: None, port } => write!(f, "{port}"),
+            Self::Inet {
+                host: Some(host),
+                port,
+            } => write!(f, "{host}:{port}"),
+            Self::Unix(path) => write!(f, "unix:{path}"),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(address: ListenAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl JsonSchema for ListenAddress {
+    fn schema_name() -> String {
+        "ListenAddress".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl Display for Listen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "listen {};", self.to_args(None).join(" "))
+    }
+}
@@ -0,0 +1,358 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+use super::{
+    ast::{self, Directive, Format},
+    files::FilesDirective,
+    managed, validate_directives, NginxUpstream, StaticFiles,
+};
+use crate::deployment::{
+    parse::ParseError,
+    validate::{validate_param, UpstreamUrl, ValidationErrors},
+};
+
+/// Key value pairs for an Nginx location.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct NginxLocation {
+    /// How `path` is matched against the request.
+    #[serde(default)]
+    pub modifier: Modifier,
+
+    /// The URI prefix, the exact URI or the regex the request is matched against,
+    /// or the name of a named location starting with `@`.
+    pub path: String,
+
+    /// A list of Nginx directives inside a `location` block, including nested blocks.
+    pub directives: Vec<Directive>,
+
+    /// The address where the app will be listening on.
+    pub proxy_pass: Option<UpstreamUrl>,
+
+    /// Serve files from disk instead, e.g. the build of a static site.
+    #[serde(default)]
+    pub files: Option<StaticFiles>,
+
+    /// How the parsed block is laid out, empty for new locations.
+    #[serde(default)]
+    pub layout: LocationLayout,
+}
+
+/// The modifier of a `location`, determining how its path is matched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Modifier {
+    /// No modifier: the longest matching prefix is used, unless a regex matches.
+    #[default]
+    Prefix,
+
+    /// `=`: the URI has to be equal to the path.
+    Exact,
+
+    /// `^~`: a prefix which takes precedence over regexes when it is the longest match.
+    PreferPrefix,
+
+    /// `~`: a case sensitive regex.
+    Regex,
+
+    /// `~*`: a case insensitive regex.
+    RegexCaseless,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct LocationLayout {
+    /// Format of the `location` block.
+    format: Format,
+
+    /// The arguments of the block as parsed, written as they were if the match did not change.
+    args: Vec<String>,
+
+    /// The directives of the block in the order of the file.
+    items: Vec<Item>,
+}
+
+/// A directive of the `location` block, managed ones keep the directive as parsed.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+enum Item {
+    ProxyPass(Directive),
+
+    /// The first directive of each kind [StaticFiles] is written as.
+    Files(FilesDirective, Directive),
+
+    /// The next of the unmanaged [directives][NginxLocation::directives].
+    Directive,
+}
+
+impl Modifier {
+    /// Modifiers as written in the file, longest first so `~*` is not read as `~`.
+    const ALL: [(&'static str, Modifier); 4] = [
+        ("^~", Modifier::PreferPrefix),
+        ("~*", Modifier::RegexCaseless),
+        ("=", Modifier::Exact),
+        ("~", Modifier::Regex),
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Prefix => "",
+            Self::Exact => "=",
+            Self::PreferPrefix => "^~",
+            Self::Regex => "~",
+            Self::RegexCaseless => "~*",
+        }
+    }
+
+    pub fn is_regex(&self) -> bool {
+        matches!(self, Self::Regex | Self::RegexCaseless)
+    }
+
+    /// The modifier and path of `location` arguments. Like nginx, the modifier may be
+    /// separated from the path or not, e.g. `= /` and `=/`.
+    fn parse(args: &[String]) -> Result<(Self, String), String> {
+        let args: Vec<_> = args.iter().map(|arg| ast::unquote(arg)).collect();
+
+        match &args[..] {
+            [modifier, path] => match Self::ALL.iter().find(|(m, _)| m == modifier) {
+                Some((_, modifier)) => Ok((*modifier, path.clone())),
+                None => Err(format!("unknown modifier `{modifier}`")),
+            },
+            [path] => Ok(Self::ALL
+                .iter()
+                .find_map(|(m, modifier)| {
+                    path.strip_prefix(m)
+                        .filter(|path| !path.is_empty())
+                        .map(|path| (*modifier, path.to_string()))
+                })
+                .unwrap_or((Self::Prefix, path.clone()))),
+            _ => Err(format!("expected a path, found {} arguments", args.len())),
+        }
+    }
+}
+
+impl Display for Modifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl NginxLocation {
+    pub fn new() -> Self {
+        Self {
+            modifier: Modifier::Prefix,
+            path: "/".to_string(),
+            directives: vec![
+                Directive::new("proxy_set_header", &["Host", "$host"]),
+                Directive::new("proxy_set_header", &["Upgrade", "$http_upgrade"]),
+                Directive::new("proxy_set_header", &["Connection", "\"upgrade\""]),
+                Directive::new("proxy_set_header", &["X-Real-Ip", "$remote_addr"]),
+                Directive::new(
+                    "proxy_set_header",
+                    &["X-Forwarded-For", "$proxy_add_x_forwarded_for"],
+                ),
+                Directive::new("proxy_set_header", &["X-Scheme", "$scheme"]),
+            ],
+
+            proxy_pass: Some("http://localhost:42069/".parse().unwrap()),
+            files: None,
+            layout: LocationLayout::default(),
+        }
+    }
+
+    /// A location serving `files` for the requests below `path`.
+    pub fn serve(path: &str, files: StaticFiles) -> Self {
+        Self {
+            path: path.to_string(),
+            files: Some(files),
+            ..Default::default()
+        }
+    }
+
+    /// Proxy the requests of the location to the servers of `upstream`.
+    pub fn proxy_to(&mut self, upstream: &NginxUpstream) {
+        self.proxy_pass = Some(UpstreamUrl::upstream(&upstream.name));
+    }
+
+    /// Named locations, e.g. `@fallback`, are only used by internal redirects.
+    pub fn is_named(&self) -> bool {
+        self.modifier == Modifier::Prefix && self.path.starts_with('@')
+    }
+
+    /// Map a `location` block, keeping the directives piosphere does not manage as they are.
+    pub(super) fn from_directive(directive: Directive) -> Result<Self, ParseError> {
+        let (modifier, path) = Modifier::parse(&directive.args)
+            .map_err(|e| directive.invalid(format!("Invalid `location`: {e}")))?;
+
+        let mut location = Self {
+            modifier,
+            path,
+            ..Default::default()
+        };
+        location.layout.format = directive.format;
+        location.layout.args = directive.args;
+
+        for directive in directive.block.unwrap_or_default() {
+            if directive.name == "proxy_pass" && location.proxy_pass.is_none() {
+                let value = directive.value().unwrap_or_default();
+                location.proxy_pass = Some(
+                    value
+                        .parse()
+                        .map_err(|e| directive.invalid(format!("Invalid `proxy_pass`: {e}")))?,
+                );
+                location.layout.items.push(Item::ProxyPass(directive));
+                continue;
+            }
+
+            let files = FilesDirective::of(&directive).filter(|kind| {
+                !location
+                    .layout
+                    .items
+                    .iter()
+                    .any(|item| matches!(item, Item::Files(other, _) if other == kind))
+            });
+            if let Some(kind) = files {
+                location
+                    .files
+                    .get_or_insert_with(StaticFiles::default)
+                    .apply(kind, &directive)?;
+                location.layout.items.push(Item::Files(kind, directive));
+                continue;
+            }
+
+            location.directives.push(directive);
+            location.layout.items.push(Item::Directive);
+        }
+
+        Ok(location)
+    }
+
+    /// Check the values the types of the fields cannot, prefixing the fields with `field`.
+    pub(super) fn validate(&self, field: &str, errors: &mut ValidationErrors) {
+        if self.path.is_empty() {
+            errors.push(format!("{field}.path"), "path cannot be empty");
+        }
+
+        if let Err(e) = validate_param(&self.path) {
+            errors.push(format!("{field}.path"), e);
+        }
+
+        // nginx reads these as the modifier, there is no way to escape them
+        if self.modifier == Modifier::Prefix
+            && Modifier::parse(std::slice::from_ref(&self.path))
+                .is_ok_and(|(m, _)| m != Modifier::Prefix)
+        {
+            errors.push(
+                format!("{field}.path"),
+                format!("prefix `{}` cannot start with a modifier", self.path),
+            );
+        }
+
+        if let Some(ref files) = self.files {
+            files.validate(&format!("{field}.files"), errors);
+        }
+
+        validate_directives(&format!("{field}.directives"), &self.directives, errors);
+    }
+
+    pub(super) fn to_directive(&self) -> Directive {
+        let NginxLocation {
+            proxy_pass,
+            files,
+            layout,
+            ..
+        } = self;
+        let mut block = vec![];
+
+        // Managed directives the block did not have come first
+        let has_proxy_pass = layout
+            .items
+            .iter()
+            .any(|item| matches!(item, Item::ProxyPass(_)));
+        if let (false, Some(proxy_pass)) = (has_proxy_pass, proxy_pass) {
+            block.push(Directive::new("proxy_pass", &[proxy_pass.as_str()]));
+        }
+
+        // Files directives the block did not have follow the last one it had
+        let last_files = layout
+            .items
+            .iter()
+            .rposition(|item| matches!(item, Item::Files(..)));
+        let push_files = |block: &mut Vec<Directive>| {
+            let Some(files) = files else {
+                return;
+            };
+            for kind in FilesDirective::ALL {
+                let written = layout
+                    .items
+                    .iter()
+                    .any(|item| matches!(item, Item::Files(other, _) if *other == kind));
+                if let (false, Some(args)) = (written, files.to_args(kind, None)) {
+                    block.push(managed(None, kind.name(), args));
+                }
+            }
+        };
+        if last_files.is_none() {
+            push_files(&mut block);
+        }
+
+        let mut directives = self.directives.iter();
+
+        for (i, item) in layout.items.iter().enumerate() {
+            match item {
+                Item::ProxyPass(original) => {
+                    let Some(proxy_pass) = proxy_pass else {
+                        continue;
+                    };
+                    let args = match original.value().as_deref() == Some(proxy_pass.as_str()) {
+                        true => original.args.clone(),
+                        false => vec![proxy_pass.to_string()],
+                    };
+                    block.push(managed(Some(original), "proxy_pass", args));
+                }
+                Item::Files(kind, original) => {
+                    let args = files
+                        .as_ref()
+                        .and_then(|files| files.to_args(*kind, Some(original)));
+                    if let Some(args) = args {
+                        block.push(managed(Some(original), kind.name(), args));
+                    }
+                    if Some(i) == last_files {
+                        push_files(&mut block);
+                    }
+                }
+                Item::Directive => {
+                    if let Some(directive) = directives.next() {
+                        block.push(directive.clone());
+                    }
+                }
+            }
+        }
+
+        block.extend(directives.cloned());
+
+        let unchanged = Modifier::parse(&self.layout.args)
+            .is_ok_and(|(modifier, path)| modifier == self.modifier && path == self.path);
+
+        let args = match unchanged {
+            true => self.layout.args.clone(),
+            false => {
+                let path = ast::quote(&self.path);
+                match self.modifier {
+                    Modifier::Prefix => vec![path],
+                    modifier => vec![modifier.to_string(), path],
+                }
+            }
+        };
+
+        Directive {
+            args,
+            format: self.layout.format.clone(),
+            ..Directive::block("location", &[], block)
+        }
+    }
+}
+
+impl Display for NginxLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_directive().write(f, 0, true)
+    }
+}
@@ -0,0 +1,298 @@
+//! Simulates which `server` block and `location` nginx picks for a request, to answer
+//! where a request goes before the config is deployed.
+//!
+//! The server is picked among the ones listening on the port of the request by its
+//! `server_name`: an exact name, then the longest wildcard starting with `*`, then the
+//! longest wildcard ending with `*`, then the first matching regex, and finally the
+//! `default_server`, or the first one if there is none.
+//!
+//! Within the server, an exact `=` location wins, then the longest prefix if it is marked
+//! with `^~`, then the first matching regex in the order of the file and finally the longest
+//! prefix. Nested locations are not considered.
+//!
+//! Regexes are evaluated with the [regex] crate instead of PCRE, the few features it lacks,
+//! e.g. lookarounds, are reported in [Route::unsupported].
+
+use regex::RegexBuilder;
+use std::str::FromStr;
+
+use super::{Modifier, NginxConfig, NginxLocation, NginxServer};
+use crate::deployment::validate::Port;
+
+/// A request as nginx sees it: the `Host` header, the port it arrived at and the
+/// normalized URI without the query string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub host: String,
+    pub port: Port,
+    pub uri: String,
+}
+
+/// Where nginx sends a [Request].
+#[derive(Debug, Clone)]
+pub struct Route<'a> {
+    pub server: &'a NginxServer,
+
+    /// How the server was picked.
+    pub server_match: ServerMatch,
+
+    /// None if no location matches, nginx then serves the request from the `server` block.
+    pub location: Option<&'a NginxLocation>,
+
+    /// Regex server names and locations the simulator could not evaluate, the request may
+    /// be routed differently if they match.
+    pub unsupported: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerMatch {
+    Exact,
+
+    /// A name like `*.example.org`.
+    LeadingWildcard,
+
+    /// A name like `example.*`.
+    TrailingWildcard,
+
+    Regex,
+
+    /// No name matched, the `default_server` or the first server of the port is used.
+    Default,
+}
+
+impl Request {
+    pub fn new(host: &str, port: Port, uri: &str) -> Self {
+        Self {
+            host: host.trim_end_matches('.').to_ascii_lowercase(),
+            port,
+            uri: normalize(uri),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    /// Parse a url like `https://example.org:8443/path?query`. Without a scheme, http is assumed.
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let (port, rest) = match url.split_once("://") {
+            Some(("http", rest)) => (Port::HTTP, rest),
+            Some(("https", rest)) => (Port::HTTPS, rest),
+            Some((scheme, _)) => return Err(format!("unsupported scheme `{scheme}`")),
+            None => (Port::HTTP, url),
+        };
+
+        let (authority, uri) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port.parse()?),
+            _ => (authority, port),
+        };
+
+        if host.is_empty() {
+            return Err(format!("missing host in `{url}`"));
+        }
+
+        Ok(Self::new(host, port, uri))
+    }
+}
+
+impl NginxConfig {
+    /// Where nginx would send `request` if this was its only config file.
+    pub fn route(&self, request: &Request) -> Option<Route<'_>> {
+        route(&self.servers, request)
+    }
+}
+
+/// Where nginx would send `request` given all of its `servers`, e.g. of several vhost files.
+pub fn route<'a>(
+    servers: impl IntoIterator<Item = &'a NginxServer>,
+    request: &Request,
+) -> Option<Route<'a>> {
+    let servers: Vec<_> = servers
+        .into_iter()
+        .filter(|server| server.listens_on(request.port))
+        .collect();
+
+    let mut unsupported = vec![];
+
+    let (server, server_match) =
+        match_server(&servers, &request.host, &mut unsupported).or_else(|| {
+            let default = servers
+                .iter()
+                .find(|server| server.is_default(request.port));
+            default
+                .or(servers.first())
+                .map(|server| (*server, ServerMatch::Default))
+        })?;
+
+    let location = match_location(server, &request.uri, &mut unsupported);
+
+    Some(Route {
+        server,
+        server_match,
+        location,
+        unsupported,
+    })
+}
+
+fn match_server<'a>(
+    servers: &[&'a NginxServer],
+    host: &str,
+    unsupported: &mut Vec<String>,
+) -> Option<(&'a NginxServer, ServerMatch)> {
+    let names = || {
+        servers.iter().flat_map(|server| {
+            server
+                .server_name
+                .iter()
+                .map(move |name| (*server, name.as_str().to_ascii_lowercase()))
+        })
+    };
+
+    if let Some((server, _)) = names().find(|(_, name)| name == host) {
+        return Some((server, ServerMatch::Exact));
+    }
+
+    // The longest wildcard wins, the first one on ties
+    let longest = |matches: &dyn Fn(&str) -> bool| {
+        let mut longest: Option<(&'a NginxServer, String)> = None;
+        for (server, name) in names().filter(|(_, name)| matches(name)) {
+            if longest
+                .as_ref()
+                .is_none_or(|(_, longest)| name.len() > longest.len())
+            {
+                longest = Some((server, name));
+            }
+        }
+        longest
+    };
+
+    let leading = longest(&|name| {
+        name.strip_prefix('*')
+            .is_some_and(|suffix| suffix.starts_with('.') && host.ends_with(suffix))
+    });
+    if let Some((server, _)) = leading {
+        return Some((server, ServerMatch::LeadingWildcard));
+    }
+
+    let trailing = longest(&|name| {
+        name.strip_suffix('*')
+            .is_some_and(|prefix| prefix.ends_with('.') && host.starts_with(prefix))
+    });
+    if let Some((server, _)) = trailing {
+        return Some((server, ServerMatch::TrailingWildcard));
+    }
+
+    for server in servers {
+        for name in server.server_name.iter() {
+            let Some(regex) = name.as_str().strip_prefix('~') else {
+                continue;
+            };
+
+            match RegexBuilder::new(regex).build() {
+                Ok(regex) if regex.is_match(host) => return Some((server, ServerMatch::Regex)),
+                Ok(_) => {}
+                Err(_) => unsupported.push(format!("server_name {name}")),
+            }
+        }
+    }
+
+    None
+}
+
+pub(super) fn match_location<'a>(
+    server: &'a NginxServer,
+    uri: &str,
+    unsupported: &mut Vec<String>,
+) -> Option<&'a NginxLocation> {
+    let locations = || {
+        server
+            .location
+            .iter()
+            .filter(|location| !location.is_named())
+    };
+
+    if let Some(exact) =
+        locations().find(|location| location.modifier == Modifier::Exact && location.path == uri)
+    {
+        return Some(exact);
+    }
+
+    // `rev` so the first of equally long prefixes is used
+    let prefix = locations()
+        .filter(|location| {
+            matches!(location.modifier, Modifier::Prefix | Modifier::PreferPrefix)
+                && uri.starts_with(&location.path)
+        })
+        .rev()
+        .max_by_key(|location| location.path.len());
+
+    if prefix.is_some_and(|location| location.modifier == Modifier::PreferPrefix) {
+        return prefix;
+    }
+
+    for location in locations().filter(|location| location.modifier.is_regex()) {
+        let regex = RegexBuilder::new(&location.path)
+            .case_insensitive(location.modifier == Modifier::RegexCaseless)
+            .build();
+
+        match regex {
+            Ok(regex) if regex.is_match(uri) => return Some(location),
+            Ok(_) => {}
+            Err(_) => unsupported.push(format!("location {} {}", location.modifier, location.path)),
+        }
+    }
+
+    prefix
+}
+
+/// Decode percent escapes, merge slashes and resolve `.` and `..` segments of the path
+/// and drop the query string, like nginx does before matching locations.
+fn normalize(uri: &str) -> String {
+    let path = uri.split(['?', '#']).next().unwrap_or_default();
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let [first, tail @ ..] = rest {
+        let decoded = match tail {
+            [high, low, ..] if *first == b'%' => std::str::from_utf8(&[*high, *low])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+
+        match decoded {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(*first);
+                rest = tail;
+            }
+        }
+    }
+    let path = String::from_utf8_lossy(&bytes);
+
+    let mut segments: Vec<&str> = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    let directory = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    if directory && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
@@ -0,0 +1,370 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+use super::{
+    ast::{self, leading, Directive, Format},
+    tls::TlsDirective,
+    validate_directive_value, validate_directives, write_managed, Listen, NginxLocation, Tls,
+};
+use crate::deployment::{
+    parse::ParseError,
+    validate::{DomainName, Port, ValidationErrors},
+};
+
+/// A `server` block of a vhost.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NginxServer {
+    /// The `listen` directives of the server. nginx listens on port 80 if there is none.
+    ///
+    /// New servers listen on port 80.
+    pub listen: Vec<Listen>,
+
+    /// The public facing domains of the server. Used by Nginx
+    /// for pattern matching and forwarding requests.
+    ///
+    /// Example: `mysite.org`
+    pub server_name: Vec<DomainName>,
+
+    /// Location of the application's access log
+    pub access_log: Option<String>,
+
+    /// Terminate TLS with a certificate, for the `listen` directives with `ssl`.
+    #[serde(default)]
+    pub tls: Option<Tls>,
+
+    /// Used by Nginx to determine where to forward the request, based on the url.
+    /// For example, if the location path is set to `/location/` (note the trailing slash),
+    /// all requests matching `mysite.org/location` will be forwarded to `proxy_pass`.
+    pub location: Vec<NginxLocation>,
+
+    /// Directives of the `server` block piosphere does not manage, kept as they are.
+    #[serde(default)]
+    pub directives: Vec<Directive>,
+
+    /// How the parsed block is laid out, empty for new servers.
+    #[serde(default)]
+    pub layout: ServerLayout,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ServerLayout {
+    /// Format of the `server` block.
+    format: Format,
+
+    /// The directives of the block in the order of the file.
+    items: Vec<Item>,
+}
+
+/// A directive of the `server` block. Managed ones keep the directive as parsed,
+/// which is written as it was as long as the value did not change.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+enum Item {
+    Listen(Directive),
+    ServerName(Directive),
+    AccessLog(Directive),
+
+    /// The first directive of each kind [Tls] is written as.
+    Tls(TlsDirective, Directive),
+
+    /// The next of the [locations][NginxServer::location].
+    Location,
+
+    /// The next of the unmanaged [directives][NginxServer::directives].
+    Directive,
+}
+
+impl Default for NginxServer {
+    fn default() -> Self {
+        Self {
+            listen: vec![Listen::new(Port::HTTP)],
+            server_name: vec![],
+            access_log: None,
+            tls: None,
+            location: vec![],
+            directives: vec![],
+            layout: ServerLayout::default(),
+        }
+    }
+}
+
+impl NginxServer {
+    /// Map a `server` block, keeping the directives piosphere does not manage as they are.
+    pub(super) fn from_directive(directive: Directive) -> Result<Self, ParseError> {
+        let mut server = Self {
+            listen: vec![],
+            ..Default::default()
+        };
+        server.layout.format = directive.format;
+
+        let block = directive.block.unwrap_or_default();
+
+        // Without a certificate, the TLS directives are left to the `http` block
+        let terminates = block.iter().any(|d| d.name == "ssl_certificate");
+
+        for directive in block {
+            let tls = TlsDirective::of(&directive).filter(|kind| {
+                terminates
+                    && !server
+                        .layout
+                        .items
+                        .iter()
+                        .any(|item| matches!(item, Item::Tls(other, _) if other == kind))
+            });
+            if let Some(kind) = tls {
+                server
+                    .tls
+                    .get_or_insert_with(|| Tls::new("", ""))
+                    .apply(kind, &directive)?;
+                server.layout.items.push(Item::Tls(kind, directive));
+                continue;
+            }
+
+            let item = match directive.name.as_str() {
+                "listen" => {
+                    let listen = Listen::from_args(&directive.args).map_err(|(i, e)| {
+                        directive.invalid_arg(i, format!("Invalid `listen`: {e}"))
+                    })?;
+                    server.listen.push(listen);
+                    Item::Listen(directive)
+                }
+                "server_name" => {
+                    for (i, name) in directive.args.iter().enumerate() {
+                        let name = ast::unquote(name).parse().map_err(|e| {
+                            directive.invalid_arg(i, format!("Invalid `server_name`: {e}"))
+                        })?;
+                        server.server_name.push(name);
+                    }
+                    Item::ServerName(directive)
+                }
+                "access_log" if server.access_log.is_none() => {
+                    server.access_log = Some(directive.args.join(" "));
+                    Item::AccessLog(directive)
+                }
+                "location" if directive.block.is_some() => {
+                    server
+                        .location
+                        .push(NginxLocation::from_directive(directive)?);
+                    Item::Location
+                }
+                _ => {
+                    server.directives.push(directive);
+                    Item::Directive
+                }
+            };
+            server.layout.items.push(item);
+        }
+
+        Ok(server)
+    }
+
+    /// Whether the server listens on `port`, on any address.
+    pub fn listens_on(&self, port: Port) -> bool {
+        match self.listen.is_empty() {
+            true => port == Port::HTTP,
+            false => self.listen.iter().any(|listen| listen.port() == Some(port)),
+        }
+    }
+
+    /// Whether a `listen` directive marks the server as the default one of `port`.
+    pub fn is_default(&self, port: Port) -> bool {
+        self.listen
+            .iter()
+            .any(|listen| listen.port() == Some(port) && listen.default_server)
+    }
+
+    /// Check the values the types of the fields cannot, prefixing the fields with `field`.
+    pub(super) fn validate(&self, field: &str, errors: &mut ValidationErrors) {
+        if self.server_name.is_empty() {
+            errors.push(
+                format!("{field}.server_name"),
+                "at least one server name is required",
+            );
+        }
+
+        for (i, listen) in self.listen.iter().enumerate() {
+            listen.validate(&format!("{field}.listen[{i}]"), errors);
+        }
+
+        if let Some(ref tls) = self.tls {
+            if !self.listen.iter().any(|listen| listen.ssl) {
+                errors.push(format!("{field}.tls"), "TLS needs a `listen` with `ssl`");
+            }
+            tls.validate(&format!("{field}.tls"), self, errors);
+        }
+
+        if let Some(ref access_log) = self.access_log {
+            if let Err(e) = validate_directive_value(access_log) {
+                errors.push(format!("{field}.access_log"), e);
+            }
+        }
+
+        for (i, location) in self.location.iter().enumerate() {
+            location.validate(&format!("{field}.location[{i}]"), errors);
+        }
+
+        validate_directives(&format!("{field}.directives"), &self.directives, errors);
+    }
+
+    /// Write the block, `first` if nothing precedes it in the file.
+    pub(super) fn write(&self, f: &mut std::fmt::Formatter<'_>, first: bool) -> std::fmt::Result {
+        let NginxServer {
+            server_name,
+            access_log,
+            tls,
+            location,
+            directives,
+            layout,
+            ..
+        } = self;
+
+        layout
+            .format
+            .write_head(f, &leading(0, first), "server", &[] as &[&str], true)?;
+        f.write_str("{")?;
+
+        // Managed directives the file did not have come first
+        let has = |matches: fn(&Item) -> bool| layout.items.iter().any(matches);
+
+        let mut listens = self.listen.iter();
+        if !has(|item| matches!(item, Item::Listen(_))) {
+            for listen in listens.by_ref() {
+                write_managed(f, 1, None, "listen", listen.to_args(None))?;
+            }
+        }
+        if !server_name.is_empty() && !has(|item| matches!(item, Item::ServerName(_))) {
+            let names = server_name.iter().map(ToString::to_string).collect();
+            write_managed(f, 1, None, "server_name", names)?;
+        }
+        if let Some(access_log) = access_log {
+            if !has(|item| matches!(item, Item::AccessLog(_))) {
+                write_managed(f, 1, None, "access_log", vec![access_log.clone()])?;
+            }
+        }
+
+        // TLS directives the file did not have follow the last one it had
+        let last_tls = layout
+            .items
+            .iter()
+            .rposition(|item| matches!(item, Item::Tls(..)));
+        let write_tls = |f: &mut std::fmt::Formatter<'_>| -> std::fmt::Result {
+            let Some(tls) = tls else {
+                return Ok(());
+            };
+            for kind in TlsDirective::ALL {
+                let written = layout
+                    .items
+                    .iter()
+                    .any(|item| matches!(item, Item::Tls(other, _) if *other == kind));
+                if let (false, Some(args)) = (written, tls.to_args(kind, None)) {
+                    write_managed(f, 1, None, kind.name(), args)?;
+                }
+            }
+            Ok(())
+        };
+        if last_tls.is_none() {
+            write_tls(f)?;
+        }
+
+        let last_listen = layout
+            .items
+            .iter()
+            .rposition(|item| matches!(item, Item::Listen(_)));
+        let last_server_name = layout
+            .items
+            .iter()
+            .rposition(|item| matches!(item, Item::ServerName(_)));
+
+        let mut names = &server_name[..];
+        let mut locations = location.iter();
+        let mut directives = directives.iter();
+
+        for (i, item) in layout.items.iter().enumerate() {
+            match item {
+                Item::Listen(original) => {
+                    if let Some(listen) = listens.next() {
+                        let args = listen.to_args(Some(&original.args));
+                        write_managed(f, 1, Some(original), "listen", args)?;
+                    }
+
+                    // Added listens follow the last parsed one
+                    if Some(i) == last_listen {
+                        for listen in listens.by_ref() {
+                            write_managed(f, 1, None, "listen", listen.to_args(None))?;
+                        }
+                    }
+                }
+                Item::ServerName(original) => {
+                    // Each directive keeps its names, the last one takes the rest
+                    let count = match Some(i) == last_server_name {
+                        true => names.len(),
+                        false => original.args.len().min(names.len()),
+                    };
+                    if count == 0 {
+                        continue;
+                    }
+
+                    let unchanged = original.args.len() == count
+                        && original
+                            .args
+                            .iter()
+                            .zip(names)
+                            .all(|(arg, name)| ast::unquote(arg) == name.as_str());
+
+                    let args = match unchanged {
+                        true => original.args.clone(),
+                        false => names[..count].iter().map(ToString::to_string).collect(),
+                    };
+                    write_managed(f, 1, Some(original), "server_name", args)?;
+                    names = &names[count..];
+                }
+                Item::AccessLog(original) => {
+                    let Some(access_log) = access_log else {
+                        continue;
+                    };
+                    let args = match original.args.join(" ") == *access_log {
+                        true => original.args.clone(),
+                        false => vec![access_log.clone()],
+                    };
+                    write_managed(f, 1, Some(original), "access_log", args)?;
+                }
+                Item::Tls(kind, original) => {
+                    let args = tls
+                        .as_ref()
+                        .and_then(|tls| tls.to_args(*kind, Some(original)));
+                    if let Some(args) = args {
+                        write_managed(f, 1, Some(original), kind.name(), args)?;
+                    }
+                    if Some(i) == last_tls {
+                        write_tls(f)?;
+                    }
+                }
+                Item::Location => {
+                    if let Some(location) = locations.next() {
+                        location.to_directive().write(f, 1, false)?;
+                    }
+                }
+                Item::Directive => {
+                    if let Some(directive) = directives.next() {
+                        directive.write(f, 1, false)?;
+                    }
+                }
+            }
+        }
+
+        for directive in directives {
+            directive.write(f, 1, false)?;
+        }
+        for location in locations {
+            location.to_directive().write(f, 1, false)?;
+        }
+
+        layout.format.write_closing(f, 0)
+    }
+}
+
+impl Display for NginxServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, true)
+    }
+}
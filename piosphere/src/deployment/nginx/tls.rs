@@ -0,0 +1,732 @@
+//! TLS termination of a server: its certificate, protocols and ciphers, OCSP stapling, HSTS
+//! and the redirect of plain HTTP requests to HTTPS.
+
+use chrono::{DateTime, NaiveDateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use x509_parser::{extensions::GeneralName, pem::parse_x509_pem};
+
+use super::{
+    ast::{self, Directive},
+    route::{Request, Route, ServerMatch},
+    validate_directive_value, Listen, ListenAddress, Modifier, NginxConfig, NginxLocation,
+    NginxServer, StaticFiles,
+};
+use crate::deployment::{
+    parse::ParseError,
+    validate::{DomainName, Port, ValidationErrors},
+};
+
+/// The TLS settings of a server, written as its `ssl_*` directives and
+/// `Strict-Transport-Security` header.
+///
+/// The server also needs a [listen][NginxServer::listen] with `ssl`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Tls {
+    /// Path of the PEM certificate chain, e.g. `/etc/letsencrypt/live/example.org/fullchain.pem`.
+    ///
+    /// Set by piosphere if the certificate has an [issuer][Tls::issuer].
+    pub certificate: String,
+
+    /// Path of the PEM private key of the certificate.
+    pub certificate_key: String,
+
+    /// Where piosphere obtains the certificate from, None if the files are provided.
+    #[serde(default)]
+    pub issuer: Option<Issuer>,
+
+    /// The accepted protocol versions, those of nginx if empty.
+    #[serde(default)]
+    pub protocols: Vec<TlsProtocol>,
+
+    /// The accepted ciphers in the format of OpenSSL, e.g. `HIGH:!aNULL:!MD5`, those of
+    /// nginx if not set.
+    #[serde(default)]
+    pub ciphers: Option<String>,
+
+    /// Staple the OCSP response of the certificate to the handshake, and verify it.
+    #[serde(default)]
+    pub stapling: bool,
+
+    /// Tell browsers to only connect to the server over HTTPS.
+    #[serde(default)]
+    pub hsts: Option<Hsts>,
+
+    /// Redirect plain HTTP requests for the server names to HTTPS,
+    /// see [NginxConfig::add_https_redirects].
+    #[serde(default)]
+    pub redirect: bool,
+}
+
+/// Where piosphere obtains the certificate of a server from, keeping it below its data
+/// directory and renewing it before it expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Issuer {
+    /// The ACME CA piosphere is configured with, e.g. Let's Encrypt. The server names are
+    /// proven over plain HTTP, so they are always [redirected][Tls::redirect].
+    Acme,
+
+    /// The [local CA][crate::ca] of piosphere, trusted only by the machines its certificate
+    /// is installed on.
+    Local,
+}
+
+/// A version of the TLS protocol, written as nginx does, e.g. `TLSv1.3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum TlsProtocol {
+    #[serde(rename = "TLSv1")]
+    Tls1,
+
+    #[serde(rename = "TLSv1.1")]
+    Tls1_1,
+
+    #[serde(rename = "TLSv1.2")]
+    Tls1_2,
+
+    #[serde(rename = "TLSv1.3")]
+    Tls1_3,
+}
+
+/// The `Strict-Transport-Security` header, e.g. `max-age=31536000; includeSubDomains`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Hsts {
+    /// How long browsers remember to use HTTPS, in seconds.
+    pub max_age: u64,
+
+    /// Also use HTTPS for the subdomains of the server names.
+    #[serde(default)]
+    pub include_subdomains: bool,
+
+    /// Consent to be included in the preload lists of browsers.
+    #[serde(default)]
+    pub preload: bool,
+}
+
+/// The dates and names of a certificate file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CertificateInfo {
+    pub not_before: NaiveDateTime,
+
+    pub not_after: NaiveDateTime,
+
+    /// The DNS names of the subject alternative names.
+    pub names: Vec<String>,
+}
+
+/// The directives of the server [Tls] is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub(super) enum TlsDirective {
+    Certificate,
+    CertificateKey,
+    Protocols,
+    Ciphers,
+    Stapling,
+    StaplingVerify,
+    Hsts,
+}
+
+/// The prefix of the URLs ACME CAs fetch the HTTP-01 challenges from.
+pub const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+const HSTS_HEADER: &str = "Strict-Transport-Security";
+
+/// Browsers only preload sites remembering HTTPS for at least a year.
+const PRELOAD_MAX_AGE: u64 = 31536000;
+
+impl Tls {
+    pub fn new(certificate: &str, certificate_key: &str) -> Self {
+        Self {
+            certificate: certificate.to_string(),
+            certificate_key: certificate_key.to_string(),
+            issuer: None,
+            protocols: vec![],
+            ciphers: None,
+            stapling: false,
+            hsts: None,
+            redirect: false,
+        }
+    }
+
+    /// Set the setting of `directive`, which is of `kind`.
+    pub(super) fn apply(
+        &mut self,
+        kind: TlsDirective,
+        directive: &Directive,
+    ) -> Result<(), ParseError> {
+        let name = &directive.name;
+        let single = || match directive.args.len() {
+            1 => Ok(directive.value().unwrap_or_default()),
+            n => Err(directive.invalid(format!(
+                "Invalid `{name}`: expected a single value, found {n} arguments"
+            ))),
+        };
+        let flag = || match single()?.as_str() {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            value => Err(directive.invalid(format!(
+                "Invalid `{name}`: expected `on` or `off`, found `{value}`"
+            ))),
+        };
+
+        match kind {
+            TlsDirective::Certificate => self.certificate = single()?,
+            TlsDirective::CertificateKey => self.certificate_key = single()?,
+            TlsDirective::Protocols => {
+                if directive.args.is_empty() {
+                    return Err(directive.invalid(format!("Invalid `{name}`: expected a protocol")));
+                }
+                self.protocols = directive
+                    .args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| {
+                        ast::unquote(arg)
+                            .parse()
+                            .map_err(|e| directive.invalid_arg(i, format!("Invalid `{name}`: {e}")))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            TlsDirective::Ciphers => self.ciphers = Some(single()?),
+            TlsDirective::Stapling => self.stapling = flag()?,
+            // Follows `ssl_stapling`
+            TlsDirective::StaplingVerify => {
+                flag()?;
+            }
+            TlsDirective::Hsts => self.hsts = Hsts::from_args(&directive.args),
+        }
+
+        Ok(())
+    }
+
+    /// The arguments of the directive of `kind`, None if it is not written. Those of the
+    /// `original` directive are kept as long as it says the same.
+    pub(super) fn to_args(
+        &self,
+        kind: TlsDirective,
+        original: Option<&Directive>,
+    ) -> Option<Vec<String>> {
+        if let Some(original) = original {
+            let mut parsed = self.clone();
+            if parsed.apply(kind, original).is_ok() && parsed == *self {
+                let verified = kind != TlsDirective::StaplingVerify || self.stapling;
+                return verified.then(|| original.args.clone());
+            }
+        }
+
+        let args = match kind {
+            TlsDirective::Certificate => vec![ast::quote(&self.certificate)],
+            TlsDirective::CertificateKey => vec![ast::quote(&self.certificate_key)],
+            TlsDirective::Protocols if self.protocols.is_empty() => return None,
+            TlsDirective::Protocols => self.protocols.iter().map(ToString::to_string).collect(),
+            TlsDirective::Ciphers => vec![ast::quote(self.ciphers.as_ref()?)],
+            TlsDirective::Stapling | TlsDirective::StaplingVerify if !self.stapling => return None,
+            TlsDirective::Stapling | TlsDirective::StaplingVerify => vec!["on".to_string()],
+            TlsDirective::Hsts => {
+                let hsts = self.hsts.as_ref()?;
+                vec![
+                    HSTS_HEADER.to_string(),
+                    ast::quote(&hsts.to_string()),
+                    "always".to_string(),
+                ]
+            }
+        };
+        Some(args)
+    }
+
+    /// Check the values the types of the fields cannot, prefixing the fields with `field`.
+    /// `server` is the server terminating TLS.
+    pub(super) fn validate(
+        &self,
+        field: &str,
+        server: &NginxServer,
+        errors: &mut ValidationErrors,
+    ) {
+        if self.issuer.is_some() && server.request_names().next().is_none() {
+            errors.push(
+                format!("{field}.issuer"),
+                "a server name is needed to issue the certificate for",
+            );
+        }
+
+        if self.issuer == Some(Issuer::Acme) {
+            for name in server.request_names() {
+                if name.as_str().starts_with('*') || name.as_str().ends_with('*') {
+                    errors.push(
+                        format!("{field}.issuer"),
+                        format!(
+                            "`{name}` cannot be proven over HTTP, wildcards need a DNS challenge"
+                        ),
+                    );
+                }
+            }
+        }
+
+        for (name, path) in [
+            ("certificate", &self.certificate),
+            ("certificate_key", &self.certificate_key),
+        ] {
+            if path.is_empty() {
+                errors.push(format!("{field}.{name}"), format!("{name} is required"));
+            } else if let Err(e) = validate_directive_value(path) {
+                errors.push(format!("{field}.{name}"), e);
+            }
+        }
+
+        match self.ciphers.as_deref() {
+            Some("") => errors.push(format!("{field}.ciphers"), "ciphers cannot be empty"),
+            Some(ciphers) => {
+                if let Err(e) = validate_directive_value(ciphers) {
+                    errors.push(format!("{field}.ciphers"), e);
+                }
+            }
+            None => {}
+        }
+
+        if let Some(Hsts {
+            max_age,
+            include_subdomains,
+            preload: true,
+        }) = self.hsts
+        {
+            if !include_subdomains {
+                errors.push(
+                    format!("{field}.hsts.include_subdomains"),
+                    "preload requires including the subdomains",
+                );
+            }
+            if max_age < PRELOAD_MAX_AGE {
+                errors.push(
+                    format!("{field}.hsts.max_age"),
+                    format!("preload requires a max age of at least {PRELOAD_MAX_AGE} seconds"),
+                );
+            }
+        }
+    }
+
+    /// Check the certificate and its key exist at the paths `resolve` maps them to, and that
+    /// the certificate is valid at `now`. Paths with variables are only known to nginx.
+    pub(super) fn check_files(
+        &self,
+        field: &str,
+        resolve: &impl Fn(&str) -> PathBuf,
+        now: NaiveDateTime,
+        errors: &mut ValidationErrors,
+    ) {
+        let checked = |path: &str| !path.is_empty() && !path.contains('$');
+
+        if checked(&self.certificate_key) && !resolve(&self.certificate_key).is_file() {
+            errors.push(
+                format!("{field}.certificate_key"),
+                format!("`{}` does not exist", self.certificate_key),
+            );
+        }
+
+        if !checked(&self.certificate) {
+            return;
+        }
+
+        let field = format!("{field}.certificate");
+        let certificate = match CertificateInfo::read(&resolve(&self.certificate)) {
+            Ok(certificate) => certificate,
+            Err(e) => return errors.push(field, format!("`{}` {e}", self.certificate)),
+        };
+
+        if certificate.not_after < now {
+            errors.push(
+                field,
+                format!(
+                    "`{}` expired on {}",
+                    self.certificate, certificate.not_after
+                ),
+            );
+        } else if certificate.not_before > now {
+            errors.push(
+                field,
+                format!(
+                    "`{}` is not valid before {}",
+                    self.certificate, certificate.not_before
+                ),
+            );
+        }
+    }
+}
+
+impl TlsDirective {
+    /// In the order new directives are written.
+    pub(super) const ALL: [Self; 7] = [
+        Self::Certificate,
+        Self::CertificateKey,
+        Self::Protocols,
+        Self::Ciphers,
+        Self::Stapling,
+        Self::StaplingVerify,
+        Self::Hsts,
+    ];
+
+    /// The kind of `directive`, if it is one [Tls] is written as.
+    pub(super) fn of(directive: &Directive) -> Option<Self> {
+        let kind = match directive.name.as_str() {
+            "ssl_certificate" => Self::Certificate,
+            "ssl_certificate_key" => Self::CertificateKey,
+            "ssl_protocols" => Self::Protocols,
+            "ssl_ciphers" => Self::Ciphers,
+            "ssl_stapling" => Self::Stapling,
+            "ssl_stapling_verify" => Self::StaplingVerify,
+            "add_header" if Hsts::from_args(&directive.args).is_some() => Self::Hsts,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            Self::Certificate => "ssl_certificate",
+            Self::CertificateKey => "ssl_certificate_key",
+            Self::Protocols => "ssl_protocols",
+            Self::Ciphers => "ssl_ciphers",
+            Self::Stapling => "ssl_stapling",
+            Self::StaplingVerify => "ssl_stapling_verify",
+            Self::Hsts => "add_header",
+        }
+    }
+}
+
+impl Hsts {
+    /// Parse the arguments of `add_header Strict-Transport-Security "max-age=..." always`,
+    /// None if they are not an HSTS header piosphere understands.
+    fn from_args(args: &[String]) -> Option<Self> {
+        let (header, value) = match args {
+            [header, value] => (header, value),
+            [header, value, always] if always == "always" => (header, value),
+            _ => return None,
+        };
+        if !ast::unquote(header).eq_ignore_ascii_case(HSTS_HEADER) {
+            return None;
+        }
+
+        let mut max_age = None;
+        let mut include_subdomains = false;
+        let mut preload = false;
+
+        for directive in ast::unquote(value).split(';').map(str::trim) {
+            match directive.split_once('=') {
+                Some((name, age)) if name.trim().eq_ignore_ascii_case("max-age") => {
+                    max_age = Some(age.trim().trim_matches('"').parse().ok()?)
+                }
+                None if directive.eq_ignore_ascii_case("includeSubDomains") => {
+                    include_subdomains = true
+                }
+                None if directive.eq_ignore_ascii_case("preload") => preload = true,
+                None if directive.is_empty() => {}
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            max_age: max_age?,
+            include_subdomains,
+            preload,
+        })
+    }
+}
+
+impl Display for Hsts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "max-age={}", self.max_age)?;
+        if self.include_subdomains {
+            f.write_str("; includeSubDomains")?;
+        }
+        if self.preload {
+            f.write_str("; preload")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TlsProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "TLSv1" => Ok(Self::Tls1),
+            "TLSv1.1" => Ok(Self::Tls1_1),
+            "TLSv1.2" => Ok(Self::Tls1_2),
+            "TLSv1.3" => Ok(Self::Tls1_3),
+            _ => Err(format!(
+                "unsupported protocol `{s}`, expected `TLSv1`, `TLSv1.1`, `TLSv1.2` or `TLSv1.3`"
+            )),
+        }
+    }
+}
+
+impl Display for TlsProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Tls1 => "TLSv1",
+            Self::Tls1_1 => "TLSv1.1",
+            Self::Tls1_2 => "TLSv1.2",
+            Self::Tls1_3 => "TLSv1.3",
+        })
+    }
+}
+
+impl CertificateInfo {
+    /// Read the first certificate of a PEM file, the one of the server in a chain.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        match std::fs::read(path) {
+            Ok(pem) => Self::from_pem(&pem),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err("does not exist".to_string()),
+            Err(e) => Err(format!("could not be read: {e}")),
+        }
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self, String> {
+        let not_a_certificate = |e: String| format!("is not a PEM certificate: {e}");
+
+        let (_, pem) = parse_x509_pem(pem).map_err(|e| not_a_certificate(e.to_string()))?;
+        if pem.label != "CERTIFICATE" {
+            return Err(not_a_certificate(format!("found `{}`", pem.label)));
+        }
+        let certificate = pem
+            .parse_x509()
+            .map_err(|e| not_a_certificate(e.to_string()))?;
+
+        let date = |time: x509_parser::time::ASN1Time| {
+            DateTime::from_timestamp(time.timestamp(), 0)
+                .map(|date| date.naive_utc())
+                .ok_or_else(|| not_a_certificate(format!("invalid date {time}")))
+        };
+        let validity = certificate.validity();
+
+        let names = match certificate.subject_alternative_name() {
+            Ok(Some(names)) => names
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        Ok(Self {
+            not_before: date(validity.not_before)?,
+            not_after: date(validity.not_after)?,
+            names,
+        })
+    }
+}
+
+impl NginxConfig {
+    /// Add a server redirecting plain HTTP requests to HTTPS for the names of the servers
+    /// with [Tls::redirect], or issued by [Issuer::Acme], no server of the config serves on
+    /// port 80 yet. Returns the number of added servers.
+    pub fn add_https_redirects(&mut self) -> usize {
+        let count = self.servers.len();
+
+        for i in 0..count {
+            let server = &self.servers[i];
+            let redirect = server
+                .tls
+                .as_ref()
+                .is_some_and(|tls| tls.redirect || tls.issuer == Some(Issuer::Acme));
+            if !redirect {
+                continue;
+            }
+
+            let names: Vec<_> = server
+                .request_names()
+                .filter(|name| self.http_route(name).is_none())
+                .cloned()
+                .collect();
+            if !names.is_empty() {
+                let redirect = https_redirect(server, names);
+                self.servers.push(redirect);
+            }
+        }
+
+        self.servers.len() - count
+    }
+
+    /// Serve the ACME HTTP-01 challenges written to the directory `dir` from the servers
+    /// plain HTTP requests for the names of the servers issued by [Issuer::Acme] go to.
+    /// Returns the number of servers a location was added to.
+    pub fn serve_acme_challenges(&mut self, dir: &str) -> usize {
+        let mut serving = vec![];
+
+        for server in self.servers.iter() {
+            if !server
+                .tls
+                .as_ref()
+                .is_some_and(|tls| tls.issuer == Some(Issuer::Acme))
+            {
+                continue;
+            }
+
+            for name in server.request_names() {
+                let Some(route) = self.http_route(name) else {
+                    continue;
+                };
+                let i = self
+                    .servers
+                    .iter()
+                    .position(|server| std::ptr::eq(server, route.server));
+                if let Some(i) = i.filter(|i| !serving.contains(i)) {
+                    serving.push(i);
+                }
+            }
+        }
+
+        let mut added = 0;
+        for i in serving {
+            let server = &mut self.servers[i];
+            if server
+                .location
+                .iter()
+                .any(|location| location.path == ACME_CHALLENGE_PATH)
+            {
+                continue;
+            }
+
+            let files = StaticFiles {
+                alias: Some(format!("{}/", dir.trim_end_matches('/'))),
+                ..Default::default()
+            };
+            server.location.push(NginxLocation {
+                modifier: Modifier::PreferPrefix,
+                ..NginxLocation::serve(ACME_CHALLENGE_PATH, files)
+            });
+            added += 1;
+        }
+
+        added
+    }
+
+    /// Check the certificate files of the servers, see [Tls::check_files].
+    pub fn check_certificates(
+        &self,
+        field: &str,
+        resolve: impl Fn(&str) -> PathBuf,
+        now: NaiveDateTime,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+
+        for (i, server) in self.servers.iter().enumerate() {
+            if let Some(ref tls) = server.tls {
+                tls.check_files(
+                    &format!("{field}.servers[{i}].tls"),
+                    &resolve,
+                    now,
+                    &mut errors,
+                );
+            }
+        }
+
+        errors.into_result()
+    }
+
+    /// Set [Tls::redirect] of the parsed servers whose names are all redirected to HTTPS.
+    pub(super) fn find_https_redirects(&mut self) {
+        let redirected: Vec<_> = self
+            .servers
+            .iter()
+            .map(|server| {
+                let mut names = server.request_names().peekable();
+                names.peek().is_some()
+                    && names.all(|name| self.http_route(name).is_some_and(redirects_to_https))
+            })
+            .collect();
+
+        for (server, redirected) in self.servers.iter_mut().zip(redirected) {
+            if let Some(ref mut tls) = server.tls {
+                tls.redirect = redirected;
+            }
+        }
+    }
+
+    /// Where a plain HTTP request for `name` goes, None if no server is named after it.
+    fn http_route(&self, name: &DomainName) -> Option<Route<'_>> {
+        self.route(&Request::new(name.as_str(), Port::HTTP, "/"))
+            .filter(|route| route.server_match != ServerMatch::Default)
+    }
+}
+
+impl NginxServer {
+    /// The server names requests can be made for, without regexes and the catch-all `_`.
+    pub fn request_names(&self) -> impl Iterator<Item = &DomainName> {
+        self.server_name
+            .iter()
+            .filter(|name| !name.as_str().starts_with('~') && name.as_str() != "_")
+    }
+}
+
+/// Whether the route ends with a `return` to an `https://` url, the one of the server
+/// taking precedence over the one of the location.
+fn redirects_to_https(route: Route) -> bool {
+    let location = route.location.map(|location| &location.directives[..]);
+    let returns = [Some(&route.server.directives[..]), location]
+        .into_iter()
+        .flatten()
+        .find_map(|directives| directives.iter().find(|d| d.name == "return"));
+
+    match returns.map(|d| &d.args[..]) {
+        Some([code, url]) => {
+            matches!(code.as_str(), "301" | "302" | "303" | "307" | "308")
+                && ast::unquote(url).starts_with("https://")
+        }
+        _ => false,
+    }
+}
+
+/// A server redirecting plain HTTP requests for `names` to the HTTPS port of `server`.
+/// The redirect is made by a location, so other locations can serve requests over HTTP,
+/// e.g. ACME challenges.
+fn https_redirect(server: &NginxServer, names: Vec<DomainName>) -> NginxServer {
+    let port = server
+        .listen
+        .iter()
+        .find(|listen| listen.ssl)
+        .and_then(Listen::port)
+        .unwrap_or(Port::HTTPS);
+    let url = match port == Port::HTTPS {
+        true => "https://$host$request_uri".to_string(),
+        false => format!("https://$host:{port}$request_uri"),
+    };
+
+    let mut listen = vec![Listen::new(Port::HTTP)];
+    let ipv6 = server.listen.iter().any(|listen| match listen.address {
+        ListenAddress::Inet {
+            host: Some(ref host),
+            ..
+        } => host.starts_with('['),
+        _ => false,
+    });
+    if ipv6 {
+        listen.push(Listen {
+            address: ListenAddress::Inet {
+                host: Some("[::]".to_string()),
+                port: Port::HTTP,
+            },
+            ..Listen::new(Port::HTTP)
+        });
+    }
+
+    NginxServer {
+        listen,
+        server_name: names,
+        location: vec![NginxLocation {
+            path: "/".to_string(),
+            directives: vec![Directive::new("return", &["301", &url])],
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
@@ -0,0 +1,414 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+use super::{
+    ast::{self, leading, Directive, Format},
+    validate_directives, write_managed,
+};
+use crate::deployment::{
+    parse::ParseError,
+    validate::{Duration, ServerAddress, UpstreamName, ValidationErrors},
+};
+
+/// An `upstream` block spreading requests over several servers.
+///
+/// Locations use it with a `proxy_pass` to [UpstreamUrl::upstream][crate::deployment::validate::UpstreamUrl::upstream].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NginxUpstream {
+    pub name: UpstreamName,
+
+    /// How nginx picks the server for a request.
+    #[serde(default)]
+    pub balancing: Balancing,
+
+    pub servers: Vec<UpstreamServer>,
+
+    /// Number of idle connections to the servers each worker keeps open.
+    #[serde(default)]
+    pub keepalive: Option<u32>,
+
+    /// Directives of the block piosphere does not manage, e.g. `zone`, kept as they are.
+    #[serde(default)]
+    pub directives: Vec<Directive>,
+
+    /// How the parsed block is laid out, empty for new upstreams.
+    #[serde(default)]
+    pub layout: UpstreamLayout,
+}
+
+/// The load balancing method of an upstream.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Balancing {
+    /// Requests go to the servers in turn, respecting their weights.
+    #[default]
+    RoundRobin,
+
+    /// `least_conn`: the server with the fewest active connections.
+    LeastConn,
+
+    /// `ip_hash`: the same server for each client address.
+    IpHash,
+
+    /// `hash`: the same server for each value of `key`, e.g. `$request_uri`.
+    Hash { key: String, consistent: bool },
+
+    /// `random`: a random server.
+    Random,
+}
+
+/// A `server` of an upstream with its parameters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct UpstreamServer {
+    pub address: ServerAddress,
+
+    /// Share of the requests relative to the other servers, 1 by default.
+    #[serde(default)]
+    pub weight: Option<u32>,
+
+    /// Failed attempts within `fail_timeout` after which the server is considered down.
+    #[serde(default)]
+    pub max_fails: Option<u32>,
+
+    /// How long the server is considered down after `max_fails` failed attempts.
+    #[serde(default)]
+    pub fail_timeout: Option<Duration>,
+
+    /// Only used when the other servers are down.
+    #[serde(default)]
+    pub backup: bool,
+
+    /// Never used.
+    #[serde(default)]
+    pub down: bool,
+
+    /// Parameters piosphere does not manage, e.g. `max_conns=100`, kept as they are.
+    #[serde(default)]
+    pub params: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct UpstreamLayout {
+    /// Format of the `upstream` block.
+    format: Format,
+
+    /// The directives of the block in the order of the file.
+    items: Vec<Item>,
+}
+
+/// A directive of the `upstream` block. Managed ones keep the directive as parsed,
+/// which is written as it was as long as the value did not change.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+enum Item {
+    Balancing(Directive),
+    Keepalive(Directive),
+
+    /// The next of the [servers][NginxUpstream::servers].
+    Server(Directive),
+
+    /// The next of the unmanaged [directives][NginxUpstream::directives].
+    Directive,
+}
+
+impl NginxUpstream {
+    pub fn new(name: UpstreamName, servers: Vec<UpstreamServer>) -> Self {
+        Self {
+            name,
+            balancing: Balancing::default(),
+            servers,
+            keepalive: None,
+            directives: vec![],
+            layout: UpstreamLayout::default(),
+        }
+    }
+
+    /// Map an `upstream` block, keeping the directives piosphere does not manage as they are.
+    pub(super) fn from_directive(directive: Directive) -> Result<Self, ParseError> {
+        let name = directive.value().unwrap_or_default();
+        let invalid = |e: String| directive.invalid(format!("Invalid `upstream`: {e}"));
+
+        if directive.args.len() != 1 {
+            return Err(invalid(format!(
+                "expected a name, found {} arguments",
+                directive.args.len()
+            )));
+        }
+
+        let mut upstream = Self::new(name.parse().map_err(invalid)?, vec![]);
+        upstream.layout.format = directive.format;
+
+        for directive in directive.block.unwrap_or_default() {
+            let balancing = Balancing::from_directive(&directive);
+
+            let item = match directive.name.as_str() {
+                "server" => {
+                    let server = UpstreamServer::from_directive(&directive).map_err(|e| {
+                        directive.invalid(format!("Invalid `server` in upstream `{name}`: {e}"))
+                    })?;
+                    upstream.servers.push(server);
+                    Item::Server(directive)
+                }
+                "keepalive" if upstream.keepalive.is_none() && directive.args.len() == 1 => {
+                    let keepalive = directive.value().unwrap_or_default();
+                    upstream.keepalive = Some(keepalive.parse().map_err(|_| {
+                        directive.invalid(format!(
+                            "Invalid `keepalive` in upstream `{name}`: `{keepalive}` is not a number"
+                        ))
+                    })?);
+                    Item::Keepalive(directive)
+                }
+                _ if balancing.is_some() && upstream.balancing == Balancing::RoundRobin => {
+                    upstream.balancing = balancing.unwrap_or_default();
+                    Item::Balancing(directive)
+                }
+                _ => {
+                    upstream.directives.push(directive);
+                    Item::Directive
+                }
+            };
+            upstream.layout.items.push(item);
+        }
+
+        Ok(upstream)
+    }
+
+    /// Check the values the types of the fields cannot, prefixing the fields with `field`.
+    pub(super) fn validate(&self, field: &str, errors: &mut ValidationErrors) {
+        if self.servers.is_empty() {
+            errors.push(
+                format!("{field}.servers"),
+                "at least one server is required",
+            );
+        }
+
+        if let Balancing::Hash { ref key, .. } = self.balancing {
+            if key.is_empty() || !ast::is_word(key) {
+                errors.push(
+                    format!("{field}.balancing.key"),
+                    format!("invalid hash key `{key}`"),
+                );
+            }
+        }
+
+        for (i, server) in self.servers.iter().enumerate() {
+            for (j, param) in server.params.iter().enumerate() {
+                if !ast::is_word(param) {
+                    errors.push(
+                        format!("{field}.servers[{i}].params[{j}]"),
+                        format!("`{param}` must be quoted"),
+                    );
+                }
+            }
+        }
+
+        validate_directives(&format!("{field}.directives"), &self.directives, errors);
+    }
+
+    /// Write the block, `first` if nothing precedes it in the file.
+    pub(super) fn write(&self, f: &mut std::fmt::Formatter<'_>, first: bool) -> std::fmt::Result {
+        let NginxUpstream {
+            name,
+            balancing,
+            servers,
+            keepalive,
+            directives,
+            layout,
+        } = self;
+
+        layout
+            .format
+            .write_head(f, &leading(0, first), "upstream", &[name.as_str()], true)?;
+        f.write_str("{")?;
+
+        let has = |matches: fn(&Item) -> bool| layout.items.iter().any(matches);
+
+        // The balancing method has to come before `keepalive`
+        if !has(|item| matches!(item, Item::Balancing(_))) {
+            if let Some((name, args)) = balancing.to_args() {
+                write_managed(f, 1, None, name, args)?;
+            }
+        }
+
+        // Servers which were added go after the last one of the file
+        let last_server = layout
+            .items
+            .iter()
+            .rposition(|item| matches!(item, Item::Server(_)));
+
+        let mut servers = servers.iter();
+        let mut directives = directives.iter();
+
+        if last_server.is_none() {
+            for server in servers.by_ref() {
+                write_managed(f, 1, None, "server", server.to_args())?;
+            }
+        }
+
+        if let Some(keepalive) = keepalive {
+            if !has(|item| matches!(item, Item::Keepalive(_))) {
+                write_managed(f, 1, None, "keepalive", vec![keepalive.to_string()])?;
+            }
+        }
+
+        for (i, item) in layout.items.iter().enumerate() {
+            match item {
+                Item::Balancing(original) => {
+                    if let Some((name, args)) = balancing.to_args() {
+                        let unchanged =
+                            Balancing::from_directive(original).as_ref() == Some(balancing);
+                        let args = if unchanged {
+                            original.args.clone()
+                        } else {
+                            args
+                        };
+                        write_managed(f, 1, Some(original), name, args)?;
+                    }
+                }
+                Item::Keepalive(original) => {
+                    if let Some(keepalive) = keepalive {
+                        let args = match original.value() == Some(keepalive.to_string()) {
+                            true => original.args.clone(),
+                            false => vec![keepalive.to_string()],
+                        };
+                        write_managed(f, 1, Some(original), "keepalive", args)?;
+                    }
+                }
+                Item::Server(original) => {
+                    if let Some(server) = servers.next() {
+                        let args = match UpstreamServer::from_directive(original) {
+                            Ok(ref parsed) if parsed == server => original.args.clone(),
+                            _ => server.to_args(),
+                        };
+                        write_managed(f, 1, Some(original), "server", args)?;
+                    }
+
+                    if Some(i) == last_server {
+                        for server in servers.by_ref() {
+                            write_managed(f, 1, None, "server", server.to_args())?;
+                        }
+                    }
+                }
+                Item::Directive => {
+                    if let Some(directive) = directives.next() {
+                        directive.write(f, 1, false)?;
+                    }
+                }
+            }
+        }
+
+        for directive in directives {
+            directive.write(f, 1, false)?;
+        }
+
+        layout.format.write_closing(f, 0)
+    }
+}
+
+impl Display for NginxUpstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, true)
+    }
+}
+
+impl Balancing {
+    /// The method a directive selects, if it is one.
+    fn from_directive(directive: &Directive) -> Option<Self> {
+        let args: Vec<_> = directive.args.iter().map(|arg| ast::unquote(arg)).collect();
+
+        match (directive.name.as_str(), &args[..]) {
+            ("least_conn", []) => Some(Self::LeastConn),
+            ("ip_hash", []) => Some(Self::IpHash),
+            ("random", []) => Some(Self::Random),
+            ("hash", [key]) => Some(Self::Hash {
+                key: key.clone(),
+                consistent: false,
+            }),
+            ("hash", [key, consistent]) if consistent == "consistent" => Some(Self::Hash {
+                key: key.clone(),
+                consistent: true,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The directive selecting the method, none for the default.
+    fn to_args(&self) -> Option<(&'static str, Vec<String>)> {
+        match self {
+            Self::RoundRobin => None,
+            Self::LeastConn => Some(("least_conn", vec![])),
+            Self::IpHash => Some(("ip_hash", vec![])),
+            Self::Random => Some(("random", vec![])),
+            Self::Hash { key, consistent } => {
+                let mut args = vec![key.clone()];
+                if *consistent {
+                    args.push("consistent".to_string());
+                }
+                Some(("hash", args))
+            }
+        }
+    }
+}
+
+impl UpstreamServer {
+    pub fn new(address: ServerAddress) -> Self {
+        Self {
+            address,
+            weight: None,
+            max_fails: None,
+            fail_timeout: None,
+            backup: false,
+            down: false,
+            params: vec![],
+        }
+    }
+
+    fn from_directive(directive: &Directive) -> Result<Self, String> {
+        let mut args = directive.args.iter().map(|arg| ast::unquote(arg));
+
+        let address = args.next().ok_or("missing address")?;
+        let mut server = Self::new(address.parse()?);
+
+        let number = |param: &str, value: &str| {
+            value
+                .parse()
+                .map_err(|_| format!("`{param}` must be a number, found `{value}`"))
+        };
+
+        for (arg, raw) in args.zip(directive.args.iter().skip(1)) {
+            match arg.split_once('=') {
+                Some(("weight", value)) => server.weight = Some(number("weight", value)?),
+                Some(("max_fails", value)) => server.max_fails = Some(number("max_fails", value)?),
+                Some(("fail_timeout", value)) => server.fail_timeout = Some(value.parse()?),
+                None if arg == "backup" => server.backup = true,
+                None if arg == "down" => server.down = true,
+                _ => server.params.push(raw.clone()),
+            }
+        }
+
+        Ok(server)
+    }
+
+    fn to_args(&self) -> Vec<String> {
+        let mut args = vec![self.address.to_string()];
+
+        if let Some(weight) = self.weight {
+            args.push(format!("weight={weight}"));
+        }
+        if let Some(max_fails) = self.max_fails {
+            args.push(format!("max_fails={max_fails}"));
+        }
+        if let Some(ref fail_timeout) = self.fail_timeout {
+            args.push(format!("fail_timeout={fail_timeout}"));
+        }
+        if self.backup {
+            args.push("backup".to_string());
+        }
+        if self.down {
+            args.push("down".to_string());
+        }
+
+        args.extend(self.params.iter().cloned());
+        args
+    }
+}
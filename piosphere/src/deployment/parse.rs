@@ -0,0 +1,121 @@
+//! Errors of the config file parsers, pointing at the position of the problem.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// A config file which could not be parsed, with the position of the problem.
+///
+/// Rendered as the position, the message and the line of the input with a caret under the
+/// column, e.g.
+///
+/// ```text
+/// /etc/nginx/sites-enabled/app:3:16: Invalid `proxy_pass`: upstream url `localhost:8000` must start with `http://` or `https://`
+///   |
+/// 3 |     proxy_pass localhost:8000;
+///   |                ^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ParseError {
+    /// The file the input was read from, if it was read from a file.
+    pub file: Option<String>,
+
+    /// Byte offset of the problem in the input.
+    pub offset: usize,
+
+    /// Starting at 1.
+    pub line: usize,
+
+    /// Starting at 1, counted in characters.
+    pub column: usize,
+
+    pub message: String,
+
+    /// What the parser expected at the position, e.g. `` `;` ``.
+    pub expected: Option<String>,
+
+    /// The line of the input with a caret under the column.
+    pub snippet: String,
+}
+
+impl ParseError {
+    /// An error at byte `offset` of `input`.
+    pub fn new(input: &str, offset: usize, message: impl Into<String>) -> Self {
+        Self::at(offset, message).locate(input)
+    }
+
+    /// An error at byte `offset` of an input which is not at hand, [locate][Self::locate]
+    /// has to be called with it once it is.
+    pub(crate) fn at(offset: usize, message: impl Into<String>) -> Self {
+        Self {
+            file: None,
+            offset,
+            line: 0,
+            column: 0,
+            message: message.into(),
+            expected: None,
+            snippet: String::new(),
+        }
+    }
+
+    pub fn expected(mut self, expected: impl Into<String>) -> Self {
+        self.expected = Some(expected.into());
+        self
+    }
+
+    /// Set the file the input was read from.
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// Compute the line, column and snippet of the offset in `input`.
+    pub(crate) fn locate(mut self, input: &str) -> Self {
+        let mut offset = self.offset.min(input.len());
+        while !input.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = input[offset..]
+            .find('\n')
+            .map_or(input.len(), |i| offset + i);
+        let line = input[start..end].trim_end_matches('\r');
+        let before = &input[start..offset];
+
+        self.offset = offset;
+        self.line = input[..start].matches('\n').count() + 1;
+        self.column = before.chars().count() + 1;
+
+        // Tabs are kept so the caret lines up however wide they are rendered
+        let indent: String = before
+            .chars()
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        self.snippet = format!("{gutter} |\n{number} | {line}\n{gutter} | {indent}^");
+        self
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{file}:{}:{}: ", self.line, self.column)?,
+            None => write!(f, "line {}, column {}: ", self.line, self.column)?,
+        }
+        f.write_str(&self.message)?;
+
+        if let Some(ref expected) = self.expected {
+            write!(f, ", expected {expected}")?;
+        }
+        if !self.snippet.is_empty() {
+            write!(f, "\n{}", self.snippet)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
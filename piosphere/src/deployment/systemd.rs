@@ -1,10 +1,15 @@
 use std::{collections::HashMap, fmt::Display};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use super::{
+    parse::ParseError,
+    validate::{validate_param, EnvKey, UnitName, ValidationErrors},
+};
 use crate::{error::PiosphereError, PiosphereResult, SYSD_FILE_PATH};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SystemdConfig {
     /// Absolute path to the systemd service file.
     ///
@@ -22,7 +27,9 @@ pub struct SystemdConfig {
 }
 
 impl SystemdConfig {
-    pub fn parse(file: &str) -> Self {
+    /// Parse a unit file. Comments are dropped, lines which are neither a section header nor
+    /// a `key=value` parameter of a known section are rejected.
+    pub fn parse(file: &str) -> Result<Self, ParseError> {
         enum ParseState {
             Unit,
             Service,
@@ -31,35 +38,49 @@ impl SystemdConfig {
 
         let mut this = Self::default();
         let mut state = None;
+        let mut offset = 0;
 
-        for line in file.lines() {
-            let line = line.trim();
+        for raw in file.split_inclusive('\n') {
+            let start = offset + raw.len() - raw.trim_start().len();
+            offset += raw.len();
+            let line = raw.trim();
+            let error = |at: usize, message: String| ParseError::new(file, start + at, message);
 
-            if line.is_empty() {
+            if line.is_empty() || line.starts_with(['#', ';']) {
                 continue;
             }
 
-            if line.starts_with("[Unit]") {
-                state = Some(ParseState::Unit);
-                continue;
-            }
+            if let Some(header) = line.strip_prefix('[') {
+                let Some(section) = header.strip_suffix(']') else {
+                    return Err(error(line.len(), "unterminated section header".to_string())
+                        .expected("`]`"));
+                };
 
-            if line.starts_with("[Service]") {
-                state = Some(ParseState::Service);
+                state = Some(match section {
+                    "Unit" => ParseState::Unit,
+                    "Service" => ParseState::Service,
+                    "Install" => ParseState::Install,
+                    _ => {
+                        return Err(error(0, format!("unknown section `{line}`"))
+                            .expected("`[Unit]`, `[Service]` or `[Install]`"))
+                    }
+                });
                 continue;
             }
 
-            if line.starts_with("[Install]") {
-                state = Some(ParseState::Install);
-                continue;
+            let Some((key, val)) = line.split_once('=') else {
+                return Err(error(line.len(), format!("`{line}` is not a parameter"))
+                    .expected("`=` followed by a value"));
+            };
+            let (key, val) = (key.trim_end(), val.trim_start());
+
+            if key.is_empty() {
+                return Err(error(0, "missing parameter name".to_string()).expected("a name"));
             }
 
             let Some(ref state) = state else {
-                continue;
-            };
-
-            let Some((key, val)) = line.split_once('=') else {
-                continue;
+                return Err(error(0, format!("`{key}` is outside of a section"))
+                    .expected("a section header like `[Service]`"));
             };
 
             match state {
@@ -67,7 +88,20 @@ impl SystemdConfig {
                     this.unit.params.insert(key.to_string(), val.to_string());
                 }
                 ParseState::Service => {
-                    this.service.params.insert(key.to_string(), val.to_string());
+                    let env = match (key, val.split_once('=')) {
+                        ("Environment", Some((env, val))) => {
+                            env.parse::<EnvKey>().ok().map(|env| (env, val))
+                        }
+                        _ => None,
+                    };
+                    match env {
+                        Some((env, val)) => {
+                            this.service.env.insert(env, val.to_string());
+                        }
+                        None => {
+                            this.service.params.insert(key.to_string(), val.to_string());
+                        }
+                    }
                 }
                 ParseState::Install => {
                     this.install.params.insert(key.to_string(), val.to_string());
@@ -75,13 +109,69 @@ impl SystemdConfig {
             }
         }
 
-        this
+        Ok(this)
+    }
+
+    /// The name of the unit, the file name of its location, e.g. `app.service`.
+    pub fn unit_name(&self) -> &str {
+        super::file_name(&self.file_location)
     }
 
     pub fn write_to_file(&self) -> PiosphereResult<()> {
         let path = &self.file_location;
         std::fs::write(path, self.to_string()).map_err(PiosphereError::from)
     }
+
+    /// Check the parameters of all sections, prefixing the fields with `field`.
+    pub(crate) fn validate(&self, field: &str, errors: &mut ValidationErrors) {
+        validate_params(&format!("{field}.unit"), &self.unit.params, errors);
+        validate_params(&format!("{field}.service"), &self.service.params, errors);
+        validate_params(&format!("{field}.install"), &self.install.params, errors);
+
+        for (key, value) in self.service.env.iter() {
+            if let Err(e) = validate_param(value) {
+                errors.push(format!("{field}.service.env.{key}"), e);
+            }
+        }
+    }
+}
+
+/// Parameters that list other units.
+const UNIT_LIST_PARAMS: [&str; 11] = [
+    "After",
+    "Before",
+    "Requires",
+    "Requisite",
+    "Wants",
+    "BindsTo",
+    "PartOf",
+    "Conflicts",
+    "WantedBy",
+    "RequiredBy",
+    "Also",
+];
+
+fn validate_params(field: &str, params: &HashMap<String, String>, errors: &mut ValidationErrors) {
+    for (key, value) in params.iter() {
+        let field = format!("{field}.params.{key}");
+
+        if key.is_empty() || !key.chars().all(|ch| ch.is_ascii_alphanumeric()) {
+            errors.push(&field, format!("invalid parameter name `{key}`"));
+        }
+
+        if let Err(e) = validate_param(value) {
+            errors.push(&field, e);
+            continue;
+        }
+
+        if UNIT_LIST_PARAMS.contains(&key.as_str()) {
+            for unit in value.split_whitespace() {
+                if let Err(e) = unit.parse::<UnitName>() {
+                    errors.push(&field, e);
+                }
+            }
+        }
+    }
 }
 
 impl Default for SystemdConfig {
@@ -103,7 +193,7 @@ impl Display for SystemdConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SysdUnitConfig {
     /// Parameters under the \[Unit\] directive.
     pub params: HashMap<String, String>,
@@ -130,7 +220,7 @@ impl Display for SysdUnitConfig {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SysdInstallConfig {
     /// Parameters under the \[Install\] directive.
     pub params: HashMap<String, String>,
@@ -154,10 +244,10 @@ impl Display for SysdInstallConfig {
     }
 }
 /// Configuration for systemd.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SysdServiceConfig {
     params: HashMap<String, String>,
-    env: HashMap<String, String>,
+    env: HashMap<EnvKey, String>,
 }
 
 impl Default for SysdServiceConfig {
@@ -173,7 +263,7 @@ impl Default for SysdServiceConfig {
                     "/path/to/my-app".to_string(),
                 ),
             ]),
-            env: HashMap::from([("MyKey".to_string(), "MyValue".to_string())]),
+            env: HashMap::from([("MyKey".parse().unwrap(), "MyValue".to_string())]),
         }
     }
 }
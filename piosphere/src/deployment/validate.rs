@@ -0,0 +1,636 @@
+//! Values of deployments that end up in generated config files.
+//!
+//! Each type can only be constructed from a valid value, including when deserialized,
+//! so nothing can inject extra directives into the nginx or systemd files.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+/// A TCP port other than 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "u16", into = "u16")]
+#[schemars(transparent)]
+pub struct Port(u16);
+
+impl Port {
+    pub const HTTP: Port = Port(80);
+    pub const HTTPS: Port = Port(443);
+
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<u16> for Port {
+    type Error = String;
+
+    fn try_from(port: u16) -> Result<Self, Self::Error> {
+        if port == 0 {
+            return Err("port must be between 1 and 65535".to_string());
+        }
+        Ok(Self(port))
+    }
+}
+
+impl From<Port> for u16 {
+    fn from(port: Port) -> Self {
+        port.0
+    }
+}
+
+impl FromStr for Port {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let port: u16 = s
+            .parse()
+            .map_err(|_| format!("invalid port `{s}`, must be between 1 and 65535"))?;
+        port.try_into()
+    }
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Implements the conversions shared by all string based values. The type needs a
+/// `validate(&str) -> Result<(), String>` function.
+macro_rules! string_value {
+    ($ty:ident) => {
+        impl $ty {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl TryFrom<String> for $ty {
+            type Error = String;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                Self::validate(&value)?;
+                Ok(Self(value))
+            }
+        }
+
+        impl FromStr for $ty {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.to_string().try_into()
+            }
+        }
+
+        impl From<$ty> for String {
+            fn from(value: $ty) -> Self {
+                value.0
+            }
+        }
+
+        impl Display for $ty {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+/// A name in an nginx `server_name` directive.
+///
+/// Besides host names, the wildcard forms `*.example.org` and `example.*`, regex names
+/// starting with `~` and the catch-all `_` are accepted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct DomainName(String);
+
+string_value!(DomainName);
+
+impl DomainName {
+    fn validate(name: &str) -> Result<(), String> {
+        if name == "_" {
+            return Ok(());
+        }
+
+        if let Some(regex) = name.strip_prefix('~') {
+            return match regex.chars().find(|ch| is_special(*ch)) {
+                Some(ch) => Err(format!("invalid character {ch:?} in server name `{name}`")),
+                None if regex.is_empty() => Err("empty server name regex".to_string()),
+                None => Ok(()),
+            };
+        }
+
+        let host = name
+            .strip_prefix("*.")
+            .or_else(|| name.strip_suffix(".*"))
+            .unwrap_or(name);
+
+        validate_host(host).map_err(|e| format!("invalid server name `{name}`: {e}"))
+    }
+}
+
+/// The address in a `proxy_pass` directive, e.g. `http://localhost:8080/`,
+/// `http://unix:/run/app.sock:` or `http://backend` for an upstream.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct UpstreamUrl(String);
+
+string_value!(UpstreamUrl);
+
+impl UpstreamUrl {
+    fn validate(url: &str) -> Result<(), String> {
+        if let Some(ch) = url.chars().find(|ch| is_special(*ch)) {
+            return Err(format!("invalid character {ch:?} in upstream url `{url}`"));
+        }
+
+        let Some(rest) = url
+            .strip_prefix("http://")
+            .or_else(|| url.strip_prefix("https://"))
+        else {
+            return Err(format!(
+                "upstream url `{url}` must start with `http://` or `https://`"
+            ));
+        };
+
+        if rest.starts_with("unix:/") || rest.contains('$') {
+            return Ok(());
+        }
+
+        let authority = rest
+            .split_once('/')
+            .map_or(rest, |(authority, _)| authority);
+
+        validate_authority(authority).map_err(|e| format!("invalid upstream url `{url}`: {e}"))
+    }
+
+    /// A url proxying to the `upstream` block named `name`.
+    pub fn upstream(name: &UpstreamName) -> Self {
+        Self(format!("http://{name}"))
+    }
+
+    /// The host of the url without the port, which may be the name of an `upstream` block.
+    pub fn host(&self) -> Option<&str> {
+        let rest = self.0.split_once("://")?.1;
+        let authority = rest.split(['/', '?']).next()?;
+
+        if authority.starts_with("unix:") {
+            return None;
+        }
+
+        match authority.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => Some(host),
+            _ => Some(authority),
+        }
+    }
+
+    /// The URI part after the host, e.g. `/app/` of `http://127.0.0.1:8000/app/`, which
+    /// replaces the matched part of the request URI. None if the url has none.
+    pub fn uri(&self) -> Option<&str> {
+        let rest = self.0.split_once("://")?.1;
+
+        match rest.strip_prefix("unix:") {
+            // `unix:/run/app.sock:/app/`, the socket path is ended by a colon
+            Some(socket) => socket.split_once(':').map(|(_, uri)| uri),
+            None => rest.find('/').map(|i| &rest[i..]),
+        }
+    }
+}
+
+/// The name of an `upstream` block, e.g. `app_pool`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct UpstreamName(String);
+
+string_value!(UpstreamName);
+
+impl UpstreamName {
+    fn validate(name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("upstream name cannot be empty".to_string());
+        }
+
+        match name
+            .chars()
+            .find(|ch| !(ch.is_ascii_alphanumeric() || "-_.".contains(*ch)))
+        {
+            Some(ch) => Err(format!(
+                "invalid character {ch:?} in upstream name `{name}`"
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The address of a `server` in an `upstream` block, e.g. `127.0.0.1:8080`,
+/// `app.internal` or `unix:/run/app.sock`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct ServerAddress(String);
+
+string_value!(ServerAddress);
+
+impl ServerAddress {
+    fn validate(address: &str) -> Result<(), String> {
+        if let Some(ch) = address.chars().find(|ch| is_special(*ch) || *ch == '=') {
+            return Err(format!(
+                "invalid character {ch:?} in server address `{address}`"
+            ));
+        }
+
+        if address.starts_with("unix:/") {
+            return Ok(());
+        }
+
+        validate_authority(address).map_err(|e| format!("invalid server address `{address}`: {e}"))
+    }
+}
+
+/// A duration in nginx syntax, e.g. `10s`, `1m30s` or `500ms`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct Duration(String);
+
+string_value!(Duration);
+
+impl Duration {
+    const UNITS: [&'static str; 8] = ["ms", "s", "m", "h", "d", "w", "M", "y"];
+
+    fn validate(duration: &str) -> Result<(), String> {
+        let invalid = || format!("invalid duration `{duration}`, e.g. `10s` or `1m30s`");
+
+        let mut rest = duration;
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+
+        while !rest.is_empty() {
+            let digits = rest.len()
+                - rest
+                    .trim_start_matches(|ch: char| ch.is_ascii_digit())
+                    .len();
+            if digits == 0 {
+                return Err(invalid());
+            }
+            rest = &rest[digits..];
+
+            // A plain number is in seconds
+            if rest.is_empty() {
+                break;
+            }
+
+            let unit = Self::UNITS
+                .iter()
+                .find(|unit| rest.starts_with(*unit))
+                .ok_or_else(invalid)?;
+            rest = &rest[unit.len()..];
+        }
+
+        Ok(())
+    }
+}
+
+/// The name of a systemd unit including its type, e.g. `app.service` or `multi-user.target`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct UnitName(String);
+
+string_value!(UnitName);
+
+impl UnitName {
+    /// See `systemd.unit(5)`.
+    const TYPES: [&'static str; 11] = [
+        "service",
+        "socket",
+        "device",
+        "mount",
+        "automount",
+        "swap",
+        "target",
+        "path",
+        "timer",
+        "slice",
+        "scope",
+    ];
+
+    /// Longest unit name accepted by systemd.
+    const MAX_LEN: usize = 255;
+
+    fn validate(name: &str) -> Result<(), String> {
+        let Some((prefix, ty)) = name.rsplit_once('.') else {
+            return Err(format!(
+                "unit name `{name}` is missing its type, e.g. `.service`"
+            ));
+        };
+
+        if !Self::TYPES.contains(&ty) {
+            return Err(format!("unknown unit type `{ty}` in `{name}`"));
+        }
+
+        if prefix.is_empty() || name.len() > Self::MAX_LEN {
+            return Err(format!(
+                "unit name `{name}` must be between 1 and {} characters",
+                Self::MAX_LEN
+            ));
+        }
+
+        match prefix
+            .chars()
+            .find(|ch| !(ch.is_ascii_alphanumeric() || ":-_.\\@".contains(*ch)))
+        {
+            Some(ch) => Err(format!("invalid character {ch:?} in unit name `{name}`")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The name of an environment variable passed to a service.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct EnvKey(String);
+
+string_value!(EnvKey);
+
+impl EnvKey {
+    fn validate(key: &str) -> Result<(), String> {
+        match key.chars().next() {
+            None => Err("environment variable name cannot be empty".to_string()),
+            Some(ch) if ch.is_ascii_digit() => Err(format!(
+                "environment variable `{key}` cannot start with a digit"
+            )),
+            _ if key
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '_') =>
+            {
+                Ok(())
+            }
+            _ => Err(format!(
+                "environment variable `{key}` can only contain letters, digits and `_`"
+            )),
+        }
+    }
+}
+
+/// A value that was rejected, with the path of the field it was found in,
+/// e.g. `nginx_cfgs[0].servers[0].location[0].proxy_pass`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl ValidationErrors {
+    pub fn push(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        })
+    }
+
+    /// `Ok` if nothing was rejected.
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Validation failed")?;
+        for (i, FieldError { field, message }) in self.0.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { "; " };
+            write!(f, "{sep}{field}: {message}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Check a free form value of a config file, such as a systemd parameter.
+pub(crate) fn validate_param(value: &str) -> Result<(), String> {
+    match value.chars().find(|ch| ch.is_control()) {
+        Some(ch) => Err(format!("control character {ch:?} is not allowed")),
+        None => Ok(()),
+    }
+}
+
+/// Characters that would end a directive or start a new one in an nginx config.
+fn is_special(ch: char) -> bool {
+    ch.is_whitespace() || ch.is_control() || matches!(ch, ';' | '{' | '}' | '"' | '\'' | '#')
+}
+
+/// A host with an optional port, the host being a name or an IPv4 or bracketed IPv6 address.
+pub(crate) fn validate_authority(authority: &str) -> Result<(), String> {
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(':') => {
+            port.parse::<Port>()?;
+            host
+        }
+        _ => authority,
+    };
+
+    if host.starts_with('[') && host.ends_with(']') {
+        return host[1..host.len() - 1]
+            .parse::<std::net::Ipv6Addr>()
+            .map(|_| ())
+            .map_err(|_| "invalid IPv6 address".to_string());
+    }
+
+    validate_host(host)
+}
+
+/// A host name or IPv4 address.
+fn validate_host(host: &str) -> Result<(), String> {
+    if host.is_empty() {
+        return Err("host cannot be empty".to_string());
+    }
+
+    if host.len() > 253 {
+        return Err("host is longer than 253 characters".to_string());
+    }
+
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!(
+                "label `{label}` must be between 1 and 63 characters"
+            ));
+        }
+
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label `{label}` cannot start or end with `-`"));
+        }
+
+        if let Some(ch) = label
+            .chars()
+            .find(|ch| !(ch.is_ascii_alphanumeric() || *ch == '-' || *ch == '_'))
+        {
+            return Err(format!("invalid character {ch:?}"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        deployment::{
+            nginx::ast::Directive, nginx::NginxConfig, systemd::SystemdConfig, Deployment,
+        },
+        error::PiosphereError,
+    };
+
+    #[test]
+    fn ports() {
+        assert_eq!("1".parse::<Port>(), Ok(Port(1)));
+        assert_eq!("65535".parse::<Port>(), Ok(Port(65535)));
+
+        for port in ["0", "65536", "-1", "http", ""] {
+            assert!(port.parse::<Port>().is_err(), "{port}");
+        }
+        assert!(serde_json::from_str::<Port>("0").is_err());
+    }
+
+    #[test]
+    fn domain_names() {
+        for name in [
+            "example.org",
+            "a-b.example.org",
+            "*.example.org",
+            "example.*",
+            "~^www\\d+\\.example\\.org$",
+            "_",
+            "localhost",
+        ] {
+            assert!(name.parse::<DomainName>().is_ok(), "{name}");
+        }
+
+        for name in [
+            "",
+            "example..org",
+            "-example.org",
+            "example.org;",
+            "example.org return 301",
+            "~",
+            "~a{1}",
+            "*.*",
+            &format!("{}.org", "a".repeat(64)),
+        ] {
+            assert!(name.parse::<DomainName>().is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn upstream_urls() {
+        for url in [
+            "http://localhost:8080/",
+            "https://127.0.0.1",
+            "http://[::1]:8000/app/",
+            "http://unix:/run/app.sock:",
+            "http://backend",
+            "http://$upstream",
+        ] {
+            assert!(url.parse::<UpstreamUrl>().is_ok(), "{url}");
+        }
+
+        for url in [
+            "localhost:8080",
+            "ftp://localhost",
+            "http://",
+            "http://localhost:0",
+            "http://[::g]",
+            "http://localhost; deny all",
+            "http://local'host",
+        ] {
+            assert!(url.parse::<UpstreamUrl>().is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn unit_names() {
+        for name in [
+            "app.service",
+            "multi-user.target",
+            "getty@tty1.service",
+            "a.b.socket",
+        ] {
+            assert!(name.parse::<UnitName>().is_ok(), "{name}");
+        }
+
+        for name in [
+            "app",
+            ".service",
+            "app.unit",
+            "app service.service",
+            "app\n.service",
+            &format!("{}.service", "a".repeat(250)),
+        ] {
+            assert!(name.parse::<UnitName>().is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn env_keys() {
+        for key in ["PATH", "_private", "db_2"] {
+            assert!(key.parse::<EnvKey>().is_ok(), "{key}");
+        }
+
+        for key in ["", "2FA", "A-B", "A=B", "A B"] {
+            assert!(key.parse::<EnvKey>().is_err(), "{key}");
+        }
+    }
+
+    #[test]
+    fn errors_are_collected_with_their_field() {
+        let nginx = NginxConfig {
+            file_location: "/etc/nginx/sites-enabled/app.test".to_string(),
+            global: vec![
+                Directive::new("map!", &[]),
+                Directive::new("map", &["$uri", "a b"]),
+            ],
+            ..Default::default()
+        };
+
+        let mut sysd = SystemdConfig {
+            file_location: "/etc/systemd/system/app".to_string(),
+            ..Default::default()
+        };
+        sysd.unit
+            .params
+            .insert("After".to_string(), "network.target db".to_string());
+
+        let Err(PiosphereError::Validation(errors)) =
+            Deployment::new(" ", "", nginx, sysd).validate()
+        else {
+            panic!("expected a validation error");
+        };
+
+        let fields: Vec<_> = errors.0.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "name",
+                "nginx_cfgs[0].servers",
+                "nginx_cfgs[0].global[0]",
+                "nginx_cfgs[0].global[1].args[1]",
+                "service_cfgs[0].unit.params.After",
+                "service_cfgs[0].file_location",
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Using piosphere as a library, without going through the socket.
+//!
+//! ```ignore
+//! let piosphere = Piosphere::builder()
+//!     .db("/var/lib/mytool/piosphere.db")
+//!     .root("/var/lib/mytool/root")
+//!     .build()
+//!     .await?;
+//!
+//! let deployments = piosphere.overview().await?;
+//! ```
+//!
+//! The [socket server][Piosphere::serve] is one front-end to the same service.
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    acme::AcmeConfig,
+    backend::{Nginx, NginxRunner, ServiceManager, Systemctl},
+    db::PiosphereDatabase,
+    layer::{Layer, Layers},
+    socket::{server::Server, session::Peer, Encoding, Message},
+    PiosphereResult, PiosphereService, PITERIA_DATA_DIR, PITERIA_DB_FILE,
+};
+
+/// The piosphere service, called directly.
+///
+/// Has a method for each message, named after it in snake case.
+#[derive(Debug)]
+pub struct Piosphere {
+    service: PiosphereService,
+
+    /// Requests are sent as the current process, which is always an admin.
+    peer: Peer,
+}
+
+impl Piosphere {
+    pub fn builder() -> PiosphereBuilder {
+        PiosphereBuilder::default()
+    }
+
+    /// Handle the message and return its response.
+    ///
+    /// Requests take the same path as those of socket clients, so batches, layers and
+    /// admin checks behave the same.
+    pub async fn request<M: Message>(&self, message: M) -> PiosphereResult<M::Response> {
+        let response = self
+            .service
+            .respond(Some(&self.peer), message.to_request()?, Encoding::Bincode)
+            .await?;
+        Encoding::Bincode.decode(&response)
+    }
+
+    /// Serve the service on the unix socket at `socket`.
+    pub fn serve(self, socket: &str) -> PiosphereResult<Server> {
+        Server::new(self.service, socket)
+    }
+}
+
+#[derive(Debug)]
+pub struct PiosphereBuilder {
+    db: String,
+    root: Option<PathBuf>,
+    service_manager: Box<dyn ServiceManager>,
+    nginx: Box<dyn NginxRunner>,
+    layers: Layers,
+    data_dir: String,
+    acme: Option<AcmeConfig>,
+}
+
+impl Default for PiosphereBuilder {
+    fn default() -> Self {
+        Self {
+            db: PITERIA_DB_FILE.to_string(),
+            root: None,
+            service_manager: Box::new(Systemctl),
+            nginx: Box::new(Nginx),
+            layers: Layers::default(),
+            data_dir: PITERIA_DATA_DIR.to_string(),
+            acme: None,
+        }
+    }
+}
+
+impl PiosphereBuilder {
+    /// Path to the sqlite file, created if missing.
+    ///
+    /// [MEMORY][crate::db::MEMORY] keeps the database in memory.
+    pub fn db(mut self, file: &str) -> Self {
+        self.db = file.to_string();
+        self
+    }
+
+    /// Directory the config file locations of deployments are relative to,
+    /// e.g. `/etc/nginx/sites-enabled/app` is written to `<root>/etc/nginx/sites-enabled/app`.
+    ///
+    /// By default the locations are used as they are.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    pub fn service_manager(mut self, service_manager: impl ServiceManager + 'static) -> Self {
+        self.service_manager = Box::new(service_manager);
+        self
+    }
+
+    pub fn nginx(mut self, nginx: impl NginxRunner + 'static) -> Self {
+        self.nginx = Box::new(nginx);
+        self
+    }
+
+    /// Directory of the files piosphere manages itself, e.g. the certificates obtained by
+    /// [acme][Self::acme]. Like config file locations, it is relative to the [root][Self::root].
+    pub fn data_dir(mut self, location: &str) -> Self {
+        self.data_dir = location.to_string();
+        self
+    }
+
+    /// Obtain the certificates of servers with [Issuer::Acme][crate::deployment::nginx::Issuer::Acme]
+    /// from the CA, see [acme][crate::acme].
+    pub fn acme(mut self, config: AcmeConfig) -> Self {
+        self.acme = Some(config);
+        self
+    }
+
+    /// Add a layer around the handlers, see [PiosphereService::layer].
+    pub fn layer(mut self, layer: impl Layer + 'static) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Connect to the database and run its migrations.
+    pub async fn build(self) -> PiosphereResult<Piosphere> {
+        let db = PiosphereDatabase::new(&self.db).await?;
+        db.migrate().await?;
+
+        let mut service = PiosphereService::new(db);
+        service.root = self.root;
+        service.service_manager = Arc::from(self.service_manager);
+        service.nginx = Arc::from(self.nginx);
+        service.layers.extend(self.layers);
+        service.data_dir = self.data_dir;
+        service.acme = self.acme;
+
+        Ok(Piosphere {
+            service,
+            peer: Peer::current(),
+        })
+    }
+}
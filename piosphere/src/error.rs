@@ -1,21 +1,97 @@
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::socket::PiosphereIOError;
+use crate::{
+    deployment::{parse::ParseError, validate::ValidationErrors},
+    socket::PiosphereIOError,
+};
 
 #[derive(Debug, Error)]
 pub enum PiosphereError {
     #[error("{0}")]
     IO(#[from] std::io::Error),
 
+    /// A config file could not be parsed.
     #[error("{0}")]
-    NginxParse(String),
+    Parse(#[from] ParseError),
+
+    #[error("{0}")]
+    Validation(#[from] ValidationErrors),
+
+    #[error("{0}")]
+    Forbidden(String),
+
+    #[error("{0}")]
+    Batch(String),
 
     #[error("{0}")]
     PiosphereIO(#[from] PiosphereIOError),
 
+    #[error("{0}")]
+    Backend(String),
+
+    /// A certificate could not be obtained from its issuer.
+    #[error("{0}")]
+    Certificate(String),
+
+    /// A request failed on the server, with the message of the error there.
+    /// Errors with a structure, e.g. validation errors, keep their variant.
+    #[error("{0}")]
+    Server(String),
+
     #[error("{0}")]
     Sqlx(#[from] sqlx::Error),
 
+    #[error("{0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+
     #[error("{0}")]
     Bincode(#[from] bincode::Error),
+
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A [PiosphereError] as sent to bincode clients in response to a failed request.
+///
+/// Errors without a structure of their own are sent as their message and are
+/// [Server][PiosphereError::Server] errors on the client.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RemoteError {
+    Parse(ParseError),
+    Validation(ValidationErrors),
+    Forbidden(String),
+    Batch(String),
+    Backend(String),
+    Certificate(String),
+    Server(String),
+}
+
+impl From<PiosphereError> for RemoteError {
+    fn from(e: PiosphereError) -> Self {
+        match e {
+            PiosphereError::Parse(e) => Self::Parse(e),
+            PiosphereError::Validation(errors) => Self::Validation(errors),
+            PiosphereError::Forbidden(message) => Self::Forbidden(message),
+            PiosphereError::Batch(message) => Self::Batch(message),
+            PiosphereError::Backend(message) => Self::Backend(message),
+            PiosphereError::Certificate(message) => Self::Certificate(message),
+            PiosphereError::Server(message) => Self::Server(message),
+            e => Self::Server(e.to_string()),
+        }
+    }
+}
+
+impl From<RemoteError> for PiosphereError {
+    fn from(e: RemoteError) -> Self {
+        match e {
+            RemoteError::Parse(e) => Self::Parse(e),
+            RemoteError::Validation(errors) => Self::Validation(errors),
+            RemoteError::Forbidden(message) => Self::Forbidden(message),
+            RemoteError::Batch(message) => Self::Batch(message),
+            RemoteError::Backend(message) => Self::Backend(message),
+            RemoteError::Certificate(message) => Self::Certificate(message),
+            RemoteError::Server(message) => Self::Server(message),
+        }
+    }
 }
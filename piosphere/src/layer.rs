@@ -0,0 +1,184 @@
+//! Middleware around the [Handler][crate::Handler]s of the service.
+//!
+//! Every request passes through the [Layer]s of the service in the order they were added
+//! before it reaches its handler, and back through them in reverse order afterwards.
+//! A [Batch][crate::socket::message::Batch] passes through them as a whole, then each of its
+//! requests one by one.
+//!
+//! [Layer::before] may wait, e.g. for a permit, and returns a [Guard] which is held while the
+//! request is handled and dropped after [Layer::after] of the same layer ran.
+
+use std::{any::Any, fmt::Debug, future::Future, pin::Pin, time::Instant};
+
+use crate::{
+    error::PiosphereError,
+    socket::{session::Peer, PiosphereTag},
+    PiosphereResult,
+};
+
+/// A request on its way through the layers.
+#[derive(Debug)]
+pub struct Call<'a> {
+    pub tag: PiosphereTag,
+
+    /// `None` if the credentials of the sender are unknown.
+    pub peer: Option<&'a Peer>,
+
+    /// When the request entered the first layer.
+    pub started_at: Instant,
+}
+
+/// A decoded message or response.
+#[derive(Clone, Copy)]
+pub struct Value<'a> {
+    any: &'a (dyn Any + Send + Sync),
+    debug: &'a (dyn Debug + Sync),
+}
+
+impl<'a> Value<'a> {
+    fn new<T: Debug + Send + Sync + 'static>(value: &'a T) -> Self {
+        Self {
+            any: value,
+            debug: value,
+        }
+    }
+
+    /// The value if it is a `T`, e.g. a message or its response.
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&'a T> {
+        self.any.downcast_ref()
+    }
+}
+
+impl Debug for Value<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.debug.fmt(f)
+    }
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Held by a layer while the request is handled, see [Layer::before].
+pub type Guard<'l> = Box<dyn Send + 'l>;
+
+/// Middleware around the handlers, added with [PiosphereService::layer][crate::PiosphereService::layer].
+pub trait Layer: Debug + Send + Sync {
+    /// Called with the decoded message before it is handled. The guard is dropped once the
+    /// request is handled and [after][Self::after] ran.
+    /// Returning an error rejects the request without reaching the handler.
+    fn before<'l, 'a>(
+        &'l self,
+        _call: &'a Call<'a>,
+        _message: Value<'a>,
+    ) -> BoxFuture<'a, PiosphereResult<Guard<'l>>>
+    where
+        'l: 'a,
+    {
+        pass()
+    }
+
+    /// Called with the outcome of the request, including rejections by later layers.
+    fn after(&self, _call: &Call, _result: Result<Value, &PiosphereError>) {}
+}
+
+#[derive(Debug, Default)]
+pub struct Layers(Vec<Box<dyn Layer>>);
+
+impl Layers {
+    pub(crate) fn push(&mut self, layer: impl Layer + 'static) {
+        self.0.push(Box::new(layer));
+    }
+
+    pub(crate) fn extend(&mut self, layers: Layers) {
+        self.0.extend(layers.0);
+    }
+
+    /// Pass the message through the layers to `handle` and the result back.
+    pub(crate) async fn call<M, R, F, Fut>(
+        &self,
+        call: &Call<'_>,
+        message: M,
+        handle: F,
+    ) -> PiosphereResult<R>
+    where
+        M: Debug + Send + Sync + 'static,
+        R: Debug + Send + Sync + 'static,
+        F: FnOnce(M) -> Fut,
+        Fut: Future<Output = PiosphereResult<R>>,
+    {
+        let mut guards = Vec::with_capacity(self.0.len());
+
+        for layer in self.0.iter() {
+            match layer.before(call, Value::new(&message)).await {
+                Ok(guard) => guards.push(guard),
+                Err(e) => {
+                    for (layer, guard) in self.0.iter().zip(guards).rev() {
+                        layer.after(call, Err(&e));
+                        drop(guard);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let result = handle(message).await;
+
+        for (layer, guard) in self.0.iter().zip(guards).rev() {
+            layer.after(call, result.as_ref().map(Value::new));
+            drop(guard);
+        }
+
+        result
+    }
+}
+
+/// Let the request through without holding anything, what [Layer::before] does by default.
+pub fn pass<'l, 'a>() -> BoxFuture<'a, PiosphereResult<Guard<'l>>>
+where
+    'l: 'a,
+{
+    Box::pin(std::future::ready(Ok(Box::new(()) as Guard)))
+}
+
+/// Rejects [admin only][PiosphereTag::admin_only] messages from peers that are not admins.
+///
+/// Always the first layer of the service.
+#[derive(Debug)]
+pub struct AdminOnly;
+
+impl Layer for AdminOnly {
+    fn before<'l, 'a>(
+        &'l self,
+        call: &'a Call<'a>,
+        _: Value<'a>,
+    ) -> BoxFuture<'a, PiosphereResult<Guard<'l>>>
+    where
+        'l: 'a,
+    {
+        if call.tag.admin_only() && !call.peer.is_some_and(Peer::is_admin) {
+            return Box::pin(std::future::ready(Err(PiosphereError::Forbidden(format!(
+                "{:?} requires admin privileges",
+                call.tag
+            )))));
+        }
+        pass()
+    }
+}
+
+/// Logs every request with its sender, outcome and duration.
+#[derive(Debug)]
+pub struct RequestLog;
+
+impl Layer for RequestLog {
+    fn after(&self, call: &Call, result: Result<Value, &PiosphereError>) {
+        let peer = match call.peer {
+            Some(peer) => format!("uid {}", peer.uid),
+            None => "unknown peer".to_string(),
+        };
+        let elapsed = call.started_at.elapsed();
+
+        match result {
+            Ok(_) => println!("{:?} from {peer} took {elapsed:?}", call.tag),
+            Err(e) => println!("{:?} from {peer} failed after {elapsed:?}: {e}", call.tag),
+        }
+    }
+}
@@ -1,23 +1,59 @@
+use acme::AcmeConfig;
+use backend::{Nginx, NginxRunner, ServiceAction, ServiceManager, Systemctl};
+use batch::{BatchResponse, BatchResult, Journal, Reloaded, Undo};
+use chrono::NaiveDateTime;
 use db::PiosphereDatabase;
-use deployment::{nginx::NginxConfig, systemd::SystemdConfig};
+use deployment::{
+    nginx::{
+        lint::{self, Diagnostic},
+        NginxConfig,
+    },
+    systemd::SystemdConfig,
+    validate::ValidationErrors,
+    Component,
+};
 use error::PiosphereError;
+use layer::{AdminOnly, Call, Layer, Layers};
 use socket::{
-    message::{Hello, Overview, ViewDeployment},
-    Message, PiosphereRequest, PiosphereTag, PiosphereWrite,
+    message::{
+        Batch, ControlDeployment, CreateDeployment, DeleteDeployment, ExportCaCertificate, Hello,
+        KillSession, LintDeployment, Overview, RenewCertificates, ServerInfo, UpdateDeployment,
+        UploadSite, ViewDeployment,
+    },
+    server::ServerStatus,
+    session::{Peer, Sessions},
+    Encoding, Message, PiosphereRequest, PiosphereTag,
+};
+use std::{
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
 };
-use std::process::{Command, Stdio};
-use tokio::net::UnixStream;
+use tokio::sync::RwLock;
 
+pub mod acme;
+pub mod backend;
+pub mod batch;
+pub mod ca;
+pub mod certificates;
 pub mod db;
 pub mod deployment;
+pub mod embed;
 pub mod error;
+pub mod layer;
+pub mod site;
 pub mod socket;
 
+pub use embed::{Piosphere, PiosphereBuilder};
+
 pub type PiosphereResult<T> = Result<T, PiosphereError>;
 
 /// Default location for the DB file.
 pub const PITERIA_DB_FILE: &str = "/opt/piosphere/piosphere.db";
 
+/// Default location of the files piosphere manages itself, e.g. certificates.
+pub const PITERIA_DATA_DIR: &str = "/opt/piosphere";
+
 /// Default location for the unix socket.
 pub const PITERIA_SOCKET: &str = "/tmp/piosphere";
 
@@ -30,9 +66,46 @@ pub const SYSD_FILE_PATH: &str = "dump/hello.service"; // TODO: /etc/systemd/sys
 #[derive(Debug)]
 pub struct PiosphereService {
     db: PiosphereDatabase,
+
+    /// Clients connected to the server.
+    pub(crate) sessions: Sessions,
+
+    /// Set by the server once it is listening.
+    pub(crate) socket: Option<String>,
+
+    started_at: NaiveDateTime,
+
+    /// Held for reading by all requests and for writing by atomic batches and requests
+    /// changing deployments, so nothing observes or interleaves with them before they complete.
+    batch_lock: RwLock<()>,
+
+    /// Changes to revert if the running atomic batch or request fails.
+    journal: Journal,
+
+    /// Middleware every request passes through before reaching its handler.
+    pub(crate) layers: Layers,
+
+    /// Prefix of the config file locations, see [PiosphereBuilder::root].
+    pub(crate) root: Option<PathBuf>,
+
+    pub(crate) service_manager: Arc<dyn ServiceManager>,
+
+    pub(crate) nginx: Arc<dyn NginxRunner>,
+
+    /// Location of the files piosphere manages itself, see [PiosphereBuilder::data_dir].
+    pub(crate) data_dir: String,
+
+    /// The CA certificates of [Issuer::Acme][deployment::nginx::Issuer::Acme] servers are
+    /// obtained from, see [PiosphereBuilder::acme].
+    pub(crate) acme: Option<AcmeConfig>,
 }
 
 #[allow(async_fn_in_trait)]
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` has no handler for `{M}`",
+    label = "every message needs a handler",
+    note = "implement `Handler<{M}>` for `{Self}`"
+)]
 pub trait Handler<M: Message> {
     async fn handle(&self, request: M) -> PiosphereResult<M::Response>;
 }
@@ -63,50 +136,588 @@ impl Handler<ViewDeployment> for PiosphereService {
     }
 }
 
+impl Handler<ServerInfo> for PiosphereService {
+    async fn handle(&self, _: ServerInfo) -> PiosphereResult<<ServerInfo as Message>::Response> {
+        let now = chrono::Utc::now().naive_utc();
+
+        Ok(ServerStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: self.started_at,
+            uptime: (now - self.started_at).num_seconds(),
+            socket: self.socket.clone(),
+            db_file: self.db.file().to_string(),
+            root: self.root.as_ref().map(|root| root.display().to_string()),
+            data_dir: self.resolve(&self.data_dir).display().to_string(),
+            db: self.db.stats().await?,
+            sessions: self.sessions.list(),
+        })
+    }
+}
+
+impl Handler<KillSession> for PiosphereService {
+    async fn handle(
+        &self,
+        KillSession(id): KillSession,
+    ) -> PiosphereResult<<KillSession as Message>::Response> {
+        println!("Killing session {id}");
+        Ok(self.sessions.kill(id))
+    }
+}
+
+impl Handler<CreateDeployment> for PiosphereService {
+    async fn handle(
+        &self,
+        CreateDeployment(mut deployment): CreateDeployment,
+    ) -> PiosphereResult<<CreateDeployment as Message>::Response> {
+        self.prepare_deployment(&mut deployment);
+        deployment.validate()?;
+        lint::deny_errors(&self.lint_deployment(&deployment).await?)?;
+        self.issue_certificates(&deployment).await?;
+        self.check_certificates(&deployment)?;
+        let created = self.db.insert_deployment(&deployment).await?;
+        self.write_deployment(&deployment)?;
+        self.reload(
+            !deployment.service_cfgs.is_empty(),
+            !deployment.nginx_cfgs.is_empty(),
+        )
+        .await?;
+        Ok(created)
+    }
+}
+
+impl Handler<UpdateDeployment> for PiosphereService {
+    async fn handle(
+        &self,
+        UpdateDeployment(mut deployment): UpdateDeployment,
+    ) -> PiosphereResult<<UpdateDeployment as Message>::Response> {
+        self.prepare_deployment(&mut deployment);
+        deployment.validate()?;
+        lint::deny_errors(&self.lint_deployment(&deployment).await?)?;
+        self.issue_certificates(&deployment).await?;
+        self.check_certificates(&deployment)?;
+
+        let (_, nginx_cfgs, sysd_cfgs) = self.db.get_deployment(&deployment.id).await?;
+        let dropped = |configs: Vec<db::Config>, kept: Vec<&String>| -> Vec<String> {
+            configs
+                .into_iter()
+                .map(|config| config.file_path)
+                .filter(|location| !kept.contains(&location))
+                .collect()
+        };
+        let dropped_nginx = dropped(
+            nginx_cfgs,
+            deployment
+                .nginx_cfgs
+                .iter()
+                .map(|c| &c.file_location)
+                .collect(),
+        );
+        let dropped_sysd = dropped(
+            sysd_cfgs,
+            deployment
+                .service_cfgs
+                .iter()
+                .map(|c| &c.file_location)
+                .collect(),
+        );
+
+        let updated = self.db.update_deployment(&deployment).await?;
+        self.write_deployment(&deployment)?;
+        self.remove_configs(&dropped_nginx, &dropped_sysd).await?;
+        self.reload(
+            !deployment.service_cfgs.is_empty() || !dropped_sysd.is_empty(),
+            !deployment.nginx_cfgs.is_empty() || !dropped_nginx.is_empty(),
+        )
+        .await?;
+        Ok(updated)
+    }
+}
+
+impl Handler<DeleteDeployment> for PiosphereService {
+    async fn handle(
+        &self,
+        DeleteDeployment(id): DeleteDeployment,
+    ) -> PiosphereResult<<DeleteDeployment as Message>::Response> {
+        let (_, nginx_cfgs, sysd_cfgs) = match self.db.get_deployment(&id).await {
+            Ok(deployment) => deployment,
+            Err(sqlx::Error::RowNotFound) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        let deleted = self.db.delete_deployment(&id).await?;
+
+        let nginx_locations: Vec<_> = nginx_cfgs.into_iter().map(|cfg| cfg.file_path).collect();
+        let sysd_locations: Vec<_> = sysd_cfgs.into_iter().map(|cfg| cfg.file_path).collect();
+        self.remove_configs(&nginx_locations, &sysd_locations)
+            .await?;
+        self.reload(!sysd_locations.is_empty(), !nginx_locations.is_empty())
+            .await?;
+
+        Ok(deleted > 0)
+    }
+}
+
+impl Handler<ControlDeployment> for PiosphereService {
+    async fn handle(
+        &self,
+        ControlDeployment {
+            id,
+            action,
+            component,
+        }: ControlDeployment,
+    ) -> PiosphereResult<<ControlDeployment as Message>::Response> {
+        self.control_deployment(&id, action, component.as_deref())
+            .await
+    }
+}
+
+impl Handler<LintDeployment> for PiosphereService {
+    async fn handle(
+        &self,
+        LintDeployment(mut deployment): LintDeployment,
+    ) -> PiosphereResult<<LintDeployment as Message>::Response> {
+        self.prepare_deployment(&mut deployment);
+        self.lint_deployment(&deployment).await
+    }
+}
+
+impl Handler<RenewCertificates> for PiosphereService {
+    async fn handle(
+        &self,
+        _: RenewCertificates,
+    ) -> PiosphereResult<<RenewCertificates as Message>::Response> {
+        // Atomic batches hold the batch lock while the journal records
+        self.renew_certificates(!self.journal.is_recording()).await
+    }
+}
+
+impl Handler<ExportCaCertificate> for PiosphereService {
+    async fn handle(
+        &self,
+        _: ExportCaCertificate,
+    ) -> PiosphereResult<<ExportCaCertificate as Message>::Response> {
+        self.ca_certificate()
+    }
+}
+
+impl Handler<UploadSite> for PiosphereService {
+    async fn handle(
+        &self,
+        UploadSite(upload): UploadSite,
+    ) -> PiosphereResult<<UploadSite as Message>::Response> {
+        self.upload_site(upload).await
+    }
+}
+
+/// Top level batches are executed by [PiosphereService::respond], this is only reached
+/// when a batch is nested in another one.
+impl Handler<Batch> for PiosphereService {
+    async fn handle(&self, _: Batch) -> PiosphereResult<<Batch as Message>::Response> {
+        Err(PiosphereError::Batch(
+            "Batches cannot be nested".to_string(),
+        ))
+    }
+}
+
 impl PiosphereService {
     pub fn new(db: PiosphereDatabase) -> Self {
-        Self { db }
+        let mut layers = Layers::default();
+        layers.push(AdminOnly);
+
+        Self {
+            db,
+            sessions: Sessions::default(),
+            socket: None,
+            started_at: chrono::Utc::now().naive_utc(),
+            batch_lock: RwLock::new(()),
+            journal: Journal::default(),
+            layers,
+            root: None,
+            service_manager: Arc::new(Systemctl),
+            nginx: Arc::new(Nginx),
+            data_dir: PITERIA_DATA_DIR.to_string(),
+            acme: None,
+        }
+    }
+
+    /// Add a layer around the handlers, after the ones already added.
+    pub fn layer(mut self, layer: impl Layer + 'static) -> Self {
+        self.layers.push(layer);
+        self
     }
 
+    /// Dispatch the request to its handler and return the response in the given encoding.
+    ///
+    /// `peer` is the sender of the request, the request passes through the
+    /// [layers][PiosphereService::layer] with it before reaching its handler.
     pub async fn respond(
         &self,
-        stream: &mut UnixStream,
+        peer: Option<&Peer>,
+        msg: PiosphereRequest,
+        encoding: Encoding,
+    ) -> PiosphereResult<Vec<u8>> {
+        if let PiosphereTag::Batch = msg.tag {
+            let call = Call {
+                tag: msg.tag,
+                peer,
+                started_at: std::time::Instant::now(),
+            };
+            let batch: Batch = encoding.decode(&msg.message)?;
+            let response = self
+                .layers
+                .call(&call, batch, |batch| self.batch(peer, batch, encoding))
+                .await?;
+            return encoding.encode(&response);
+        }
+
+        self.execute(peer, msg, encoding).await
+    }
+
+    /// Handle a request outside of an atomic batch. Requests changing deployments are
+    /// executed atomically on their own, so the rows of a deployment are only committed once
+    /// its files are written and reloaded.
+    async fn execute(
+        &self,
+        peer: Option<&Peer>,
         msg: PiosphereRequest,
+        encoding: Encoding,
+    ) -> PiosphereResult<Vec<u8>> {
+        if msg.tag == PiosphereTag::RenewCertificates {
+            // Takes the batch lock itself, it is not held while certificates are obtained
+            return self.handle_request(msg, peer, encoding).await;
+        }
+
+        if !changes_deployments(msg.tag) {
+            let _lock = self.batch_lock.read().await;
+            return self.handle_request(msg, peer, encoding).await;
+        }
+
+        let _lock = self.batch_lock.write().await;
+        self.begin_atomic().await?;
+        let result = self.handle_request(msg, peer, encoding).await;
+        self.finish_atomic(result.is_ok()).await?;
+        result
+    }
+
+    async fn batch(
+        &self,
+        peer: Option<&Peer>,
+        Batch { requests, atomic }: Batch,
+        encoding: Encoding,
+    ) -> PiosphereResult<BatchResponse> {
+        let mut results = Vec::with_capacity(requests.len());
+
+        if !atomic {
+            for request in requests {
+                let result = match self.execute(peer, request, encoding).await {
+                    Ok(response) => BatchResult::Ok(response),
+                    Err(e) => BatchResult::Err(e.to_string()),
+                };
+                results.push(result);
+            }
+            return Ok(BatchResponse {
+                rolled_back: false,
+                results,
+            });
+        }
+
+        let _lock = self.batch_lock.write().await;
+        self.begin_atomic().await?;
+
+        let mut failed = false;
+
+        for request in requests {
+            if failed {
+                results.push(BatchResult::Skipped);
+                continue;
+            }
+
+            match self.handle_request(request, peer, encoding).await {
+                Ok(response) => results.push(BatchResult::Ok(response)),
+                Err(e) => {
+                    failed = true;
+                    results.push(BatchResult::Err(e.to_string()));
+                }
+            }
+        }
+
+        if failed {
+            println!("Batch failed, rolling back");
+        }
+        self.finish_atomic(!failed).await?;
+
+        Ok(BatchResponse {
+            rolled_back: failed,
+            results,
+        })
+    }
+
+    /// Run all queries in a transaction and record the changes to files from now on,
+    /// the batch lock must be held for writing until [finish_atomic][Self::finish_atomic].
+    async fn begin_atomic(&self) -> PiosphereResult<()> {
+        self.db.begin_batch().await?;
+        self.journal.begin();
+        Ok(())
+    }
+
+    /// Commit the transaction, or roll it back and revert the recorded changes if `commit`
+    /// is false or committing fails.
+    async fn finish_atomic(&self, commit: bool) -> PiosphereResult<()> {
+        let undo = self.journal.finish();
+
+        if commit {
+            if let Err(e) = self.db.commit_batch().await {
+                self.revert(undo).await;
+                return Err(e.into());
+            }
+            return Ok(());
+        }
+
+        self.revert(undo).await;
+        self.db.rollback_batch().await?;
+        Ok(())
+    }
+
+    async fn revert(&self, undo: Vec<Undo>) {
+        Undo::revert_all(undo, &self.service_manager, &self.nginx).await;
+    }
+
+    /// Add what piosphere manages to the vhosts of the deployment: the HTTPS redirects and the
+    /// locations of the certificates it obtains.
+    fn prepare_deployment(&self, deployment: &mut deployment::Deployment) {
+        for nginx_cfg in deployment.nginx_cfgs.iter_mut() {
+            nginx_cfg.add_https_redirects();
+            self.prepare_certificates(nginx_cfg);
+        }
+    }
+
+    /// Write the config files of the deployment, recording the changes if in an atomic batch.
+    fn write_deployment(&self, deployment: &deployment::Deployment) -> PiosphereResult<()> {
+        let deployment::Deployment {
+            nginx_cfgs,
+            service_cfgs,
+            ..
+        } = deployment;
+
+        for nginx_cfg in nginx_cfgs.iter() {
+            self.journal.write_file(
+                &self.resolve(&nginx_cfg.file_location),
+                nginx_cfg.to_string(),
+            )?;
+        }
+        for service_cfg in service_cfgs.iter() {
+            self.journal.write_file(
+                &self.resolve(&service_cfg.file_location),
+                service_cfg.to_string(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Stop the units and remove the config files at the given locations, recording the
+    /// removals if in an atomic batch. Units are stopped in reverse order and are not started
+    /// again if the batch is reverted.
+    async fn remove_configs(
+        &self,
+        nginx_locations: &[String],
+        sysd_locations: &[String],
     ) -> PiosphereResult<()> {
-        handle! {self, stream, msg,
-            Hello => Hello,
-            Overview => Overview,
-            ViewDeployment => ViewDeployment,
+        for location in sysd_locations.iter().rev() {
+            let unit = deployment::file_name(location);
+            let stopped = unit.to_string();
+            backend::blocking(&self.service_manager, move |manager| {
+                manager.control(ServiceAction::Stop, &stopped)
+            })
+            .await?;
+            println!("Stopped {unit}, its unit file is removed");
+        }
+
+        for location in nginx_locations.iter().chain(sysd_locations) {
+            self.journal.remove_file(&self.resolve(location))?;
         }
+        Ok(())
+    }
 
+    /// Let systemd and nginx pick up the changed config files of theirs.
+    /// The reloads are recorded, so they run again if the changes are reverted.
+    async fn reload(&self, systemd: bool, nginx: bool) -> PiosphereResult<()> {
+        if systemd {
+            self.journal.reload(Reloaded::Systemd);
+            backend::blocking(&self.service_manager, |manager| manager.reload()).await?;
+        }
+        if nginx {
+            self.journal.reload(Reloaded::Nginx);
+            backend::blocking(&self.nginx, |nginx| nginx.reload()).await?;
+        }
         Ok(())
     }
 
+    /// The path a config file location refers to, relative to the root if one is set.
+    fn resolve(&self, location: &str) -> PathBuf {
+        match self.root {
+            Some(ref root) => root.join(location.trim_start_matches('/')),
+            None => PathBuf::from(location),
+        }
+    }
+
     pub async fn view_deployment(&self, id: &str) -> PiosphereResult<deployment::Deployment> {
-        let (deployment, nginx_cfg, sysd_cfg) = self.db.get_deployment(id).await?;
+        let (deployment, nginx_cfgs, sysd_cfgs) = self.db.get_deployment(id).await?;
 
-        let nginx_cfg = Self::read_nginx_config(&nginx_cfg.file_path)?;
-        let sysd_cfg = Self::read_sysd_config(&sysd_cfg.file_path)?;
+        let nginx_cfgs = nginx_cfgs
+            .iter()
+            .map(|config| self.read_nginx_config(&config.file_path))
+            .collect::<PiosphereResult<_>>()?;
+        let sysd_cfgs = sysd_cfgs
+            .iter()
+            .map(|config| self.read_sysd_config(&config.file_path))
+            .collect::<PiosphereResult<_>>()?;
 
-        Ok(deployment::Deployment::new(
-            &deployment.name,
-            &deployment.description,
-            nginx_cfg,
-            sysd_cfg,
-        ))
+        Ok(deployment::Deployment {
+            id: deployment.id,
+            name: deployment.name,
+            description: deployment.description,
+            service_cfgs: sysd_cfgs,
+            nginx_cfgs,
+        })
+    }
+
+    /// Apply `action` to the units of the deployment in order, or to the one named `component`.
+    /// Units are stopped in reverse order. Returns the names of the units.
+    async fn control_deployment(
+        &self,
+        id: &str,
+        action: ServiceAction,
+        component: Option<&str>,
+    ) -> PiosphereResult<Vec<String>> {
+        let deployment = self.view_deployment(id).await?;
+
+        let mut errors = ValidationErrors::default();
+
+        let mut units: Vec<_> = match component {
+            None => deployment
+                .service_cfgs
+                .iter()
+                .map(|config| config.unit_name())
+                .collect(),
+            Some(name) => match deployment.component(name) {
+                Some(Component::Service(config)) => vec![config.unit_name()],
+                Some(Component::Vhost(_)) => {
+                    errors.push(
+                        "component",
+                        format!(
+                            "`{name}` is a vhost, only units can be started, stopped or restarted"
+                        ),
+                    );
+                    vec![]
+                }
+                None => {
+                    errors.push("component", format!("the deployment has no `{name}`"));
+                    vec![]
+                }
+            },
+        };
+
+        errors.into_result()?;
+
+        if action == ServiceAction::Stop {
+            units.reverse();
+        }
+
+        for unit in units.iter() {
+            let controlled = unit.to_string();
+            backend::blocking(&self.service_manager, move |manager| {
+                manager.control(action, &controlled)
+            })
+            .await?;
+            println!("Ran {action} on {unit} of deployment {id}");
+        }
+
+        Ok(units.into_iter().map(str::to_string).collect())
+    }
+
+    /// Lint the deployment against the vhosts of the other deployments. Vhosts which cannot
+    /// be read are skipped, as well as those the deployment is about to replace.
+    async fn lint_deployment(
+        &self,
+        deployment: &deployment::Deployment,
+    ) -> PiosphereResult<Vec<Diagnostic>> {
+        if deployment.nginx_cfgs.is_empty() {
+            return Ok(vec![]);
+        }
+        let replaced = |path: &str| {
+            deployment
+                .nginx_cfgs
+                .iter()
+                .any(|config| config.file_location == path)
+        };
+        let mut others = vec![];
+
+        for other in self.db.list_deployments().await? {
+            if other.id == deployment.id {
+                continue;
+            }
+
+            let (_, nginx_cfgs, _) = self.db.get_deployment(&other.id).await?;
+            for nginx_cfg in nginx_cfgs.iter().filter(|cfg| !replaced(&cfg.file_path)) {
+                match self.read_nginx_config(&nginx_cfg.file_path) {
+                    Ok(config) => others.push((other.name.clone(), config)),
+                    Err(e) => println!(
+                        "Not linting against {} of deployment {}, it could not be read: {e}",
+                        nginx_cfg.file_path, other.id
+                    ),
+                }
+            }
+        }
+
+        let others: Vec<_> = others
+            .iter()
+            .map(|(name, config)| (name.as_str(), config))
+            .collect();
+
+        Ok(deployment.lint(&others))
+    }
+
+    /// Fail if a certificate of the deployment is missing or not valid now, before nginx
+    /// is asked to load it.
+    fn check_certificates(&self, deployment: &deployment::Deployment) -> PiosphereResult<()> {
+        let now = chrono::Utc::now().naive_utc();
+        for (i, nginx_cfg) in deployment.nginx_cfgs.iter().enumerate() {
+            nginx_cfg.check_certificates(
+                &format!("nginx_cfgs[{i}]"),
+                |location| self.resolve(location),
+                now,
+            )?;
+        }
+        Ok(())
     }
 
-    fn read_nginx_config(path: &str) -> PiosphereResult<NginxConfig> {
-        let file = std::fs::read_to_string(path)?;
-        NginxConfig::parse(&file)
+    fn read_nginx_config(&self, location: &str) -> PiosphereResult<NginxConfig> {
+        let file = std::fs::read_to_string(self.resolve(location))?;
+        let mut config = NginxConfig::parse(&file).map_err(|e| e.in_file(location))?;
+        config.file_location = location.to_string();
+        self.find_issuers(&mut config);
+        Ok(config)
     }
 
-    fn read_sysd_config(path: &str) -> PiosphereResult<SystemdConfig> {
-        let file = std::fs::read_to_string(path)?;
-        Ok(SystemdConfig::parse(&file))
+    fn read_sysd_config(&self, location: &str) -> PiosphereResult<SystemdConfig> {
+        let file = std::fs::read_to_string(self.resolve(location))?;
+        let mut config = SystemdConfig::parse(&file).map_err(|e| e.in_file(location))?;
+        config.file_location = location.to_string();
+        Ok(config)
     }
 }
 
+/// Whether the request writes or removes config files, see [PiosphereService::execute].
+fn changes_deployments(tag: PiosphereTag) -> bool {
+    matches!(
+        tag,
+        PiosphereTag::CreateDeployment
+            | PiosphereTag::UpdateDeployment
+            | PiosphereTag::DeleteDeployment
+            | PiosphereTag::UploadSite
+    )
+}
+
 pub fn invoke_sysd() {
     let res = Command::new("systemctl")
         .arg("show")
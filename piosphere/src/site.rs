@@ -0,0 +1,124 @@
+//! The files of static sites, uploaded with [UploadSite][crate::socket::message::UploadSite]
+//! to the directories the vhosts of their deployment serve files from.
+
+use std::path::{Component, Path};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deployment::{nginx::NginxConfig, validate::ValidationErrors},
+    PiosphereResult, PiosphereService,
+};
+
+/// Files to write below a directory a vhost of a deployment serves files from, either the
+/// `root` of one of its servers or the [root][crate::deployment::nginx::StaticFiles::root]
+/// or [alias][crate::deployment::nginx::StaticFiles::alias] of one of its locations.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SiteUpload {
+    pub deployment_id: String,
+
+    /// The directory to write the files to, e.g. `/var/www/site`.
+    pub dir: String,
+
+    pub files: Vec<SiteFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SiteFile {
+    /// Location of the file relative to the directory, e.g. `assets/app.js`.
+    pub path: String,
+
+    pub contents: Vec<u8>,
+}
+
+impl PiosphereService {
+    /// Write the uploaded files, recording the changes if in an atomic batch. Files of the
+    /// directory which were not uploaded are kept, and created directories are not removed
+    /// if the batch fails.
+    pub(crate) async fn upload_site(&self, upload: SiteUpload) -> PiosphereResult<usize> {
+        let deployment = self.view_deployment(&upload.deployment_id).await?;
+        let dir = upload.dir.trim_end_matches('/');
+
+        let mut errors = ValidationErrors::default();
+
+        let served = deployment
+            .nginx_cfgs
+            .iter()
+            .any(|config| served_dirs(config).any(|served| served == dir));
+        if dir.is_empty() || !served {
+            errors.push(
+                "dir",
+                format!(
+                    "`{}` is not a directory a vhost of the deployment serves files from",
+                    upload.dir
+                ),
+            );
+        }
+
+        for (i, file) in upload.files.iter().enumerate() {
+            if let Err(e) = validate_path(&file.path) {
+                errors.push(format!("files[{i}].path"), e);
+            }
+        }
+
+        errors.into_result()?;
+
+        for file in upload.files.iter() {
+            let path = self.resolve(&format!("{dir}/{}", file.path));
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            self.journal.write_file(&path, &file.contents)?;
+        }
+
+        println!(
+            "Uploaded {} files of deployment {} to {dir}",
+            upload.files.len(),
+            upload.deployment_id
+        );
+
+        Ok(upload.files.len())
+    }
+}
+
+/// The directories the servers and locations of the vhost serve files from, without the
+/// trailing slash.
+fn served_dirs(config: &NginxConfig) -> impl Iterator<Item = String> + '_ {
+    config.servers.iter().flat_map(|server| {
+        let roots = server
+            .directives
+            .iter()
+            .filter(|directive| directive.name == "root")
+            .filter_map(|directive| directive.value());
+        let locations = server
+            .location
+            .iter()
+            .filter_map(|location| Some(location.files.as_ref()?.dir()?.to_string()));
+
+        roots
+            .chain(locations)
+            .map(|dir| dir.trim_end_matches('/').to_string())
+    })
+}
+
+/// Uploaded files cannot leave the directory they are uploaded to.
+fn validate_path(path: &str) -> Result<(), String> {
+    if path.is_empty() {
+        return Err("path cannot be empty".to_string());
+    }
+    if path.contains('\0') {
+        return Err("null bytes are not allowed".to_string());
+    }
+
+    let normal = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !normal || path.ends_with('/') {
+        return Err(format!(
+            "`{path}` must be a file relative to the directory, without `.` or `..`"
+        ));
+    }
+
+    Ok(())
+}
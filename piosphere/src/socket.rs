@@ -1,5 +1,6 @@
 //! Exposes main functionality for unix sockets to be used by the server and clients.
 
+use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::array::TryFromSliceError;
 use thiserror::Error;
@@ -8,16 +9,27 @@ use tokio::{
     net::UnixStream,
 };
 
-use crate::PiosphereResult;
+use crate::{
+    deployment::validate::ValidationErrors,
+    error::{PiosphereError, RemoteError},
+    PiosphereResult,
+};
 
 pub mod client;
+pub mod jsonrpc;
 pub mod message;
 pub mod server;
+pub mod session;
+
+pub use message::PiosphereTag;
 
 type PiosphereIOResult<T> = Result<T, PiosphereIOError>;
 
 const HEADER_SIZE: usize = std::mem::size_of::<usize>();
 
+/// Size of the [preamble][Encoding::preamble] of length delimited sessions.
+const PREAMBLE_SIZE: usize = 4;
+
 type PiosphereHeader = [u8; HEADER_SIZE];
 
 pub(crate) trait Header: Sized {
@@ -58,17 +70,141 @@ pub trait Message: Serialize + Sized {
     fn tag(&self) -> PiosphereTag;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Decode a bincode response frame, which is the `Result` of the request with
+/// the encoded response or the [error][RemoteError] of the server.
+pub(crate) fn decode_response<T: DeserializeOwned>(frame: &[u8]) -> PiosphereResult<T> {
+    let response: Result<Vec<u8>, RemoteError> = bincode::deserialize(frame)?;
+    let response = response.map_err(PiosphereError::from)?;
+    Ok(bincode::deserialize(&response)?)
+}
+
+/// In JSON, requests nested in other messages are written as `{"method": ..., "params": ...}`,
+/// the same as in JSON-RPC calls.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct PiosphereRequest {
+    #[serde(rename = "method")]
     pub tag: PiosphereTag,
+
+    #[serde(rename = "params", with = "payload", default = "payload::null")]
+    #[schemars(with = "serde_json::Value")]
     pub message: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum PiosphereTag {
-    Hello,
-    Overview,
-    ViewDeployment,
+/// (De)serializes already encoded messages as bytes in binary formats and embeds them as is
+/// in human readable ones, so messages nested in JSON are plain JSON.
+pub(crate) mod payload {
+    use serde::{
+        de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer,
+    };
+    use serde_json::value::RawValue;
+
+    pub fn serialize<S: Serializer>(message: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            let raw: &RawValue = serde_json::from_slice(message).map_err(S::Error::custom)?;
+            raw.serialize(serializer)
+        } else {
+            serializer.serialize_bytes(message)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            let raw = Box::<RawValue>::deserialize(deserializer).map_err(D::Error::custom)?;
+            Ok(raw.get().as_bytes().to_vec())
+        } else {
+            Vec::deserialize(deserializer)
+        }
+    }
+
+    /// Used for messages without fields when omitted in JSON.
+    pub fn null() -> Vec<u8> {
+        b"null".to_vec()
+    }
+}
+
+/// The encoding of messages in a session.
+///
+/// The encoding is selected by the first bytes a client sends. Sessions starting with `{`
+/// use newline delimited JSON-RPC. Length delimited sessions start with the
+/// [preamble][Encoding::preamble] of their encoding, followed by their messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Length delimited bincode, used by the Rust [Client][client::Client].
+    Bincode,
+
+    /// JSON-RPC 2.0, see [jsonrpc].
+    Json(Framing),
+}
+
+impl Encoding {
+    /// Invalid values in JSON messages are reported as a [validation error][ValidationErrors]
+    /// of the field they were found in.
+    pub fn decode<T: DeserializeOwned>(&self, message: &[u8]) -> PiosphereResult<T> {
+        match self {
+            Encoding::Bincode => Ok(bincode::deserialize(message)?),
+            Encoding::Json(_) => {
+                let mut de = serde_json::Deserializer::from_slice(message);
+                let message = serde_path_to_error::deserialize(&mut de).map_err(|e| {
+                    let field = e.path().to_string();
+                    let e = e.into_inner();
+                    if e.classify() != serde_json::error::Category::Data || field == "." {
+                        return PiosphereError::from(e);
+                    }
+                    // The position is meaningless to the client since it is relative to `params`
+                    let position = format!(" at line {} column {}", e.line(), e.column());
+                    let message = e.to_string();
+                    let mut errors = ValidationErrors::default();
+                    errors.push(field, message.trim_end_matches(&position));
+                    errors.into()
+                })?;
+                de.end()?;
+                Ok(message)
+            }
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, message: &T) -> PiosphereResult<Vec<u8>> {
+        match self {
+            Encoding::Bincode => Ok(bincode::serialize(message)?),
+            Encoding::Json(_) => Ok(serde_json::to_vec(message)?),
+        }
+    }
+
+    /// Sent by the client when it connects, before its first message.
+    ///
+    /// `PIO` followed by `B` for bincode and `J` for length delimited JSON-RPC.
+    /// Newline delimited JSON-RPC sessions have none, they start with the first request.
+    pub fn preamble(&self) -> &'static [u8] {
+        match self {
+            Encoding::Bincode => b"PIOB",
+            Encoding::Json(Framing::Length) => b"PIOJ",
+            Encoding::Json(Framing::Newline) => b"",
+        }
+    }
+
+    /// The length delimited encoding selected by `preamble`.
+    pub(crate) fn from_preamble(preamble: &[u8]) -> Option<Self> {
+        [Encoding::Bincode, Encoding::Json(Framing::Length)]
+            .into_iter()
+            .find(|encoding| encoding.preamble() == preamble)
+    }
+
+    pub fn framing(&self) -> Framing {
+        match self {
+            Encoding::Bincode => Framing::Length,
+            Encoding::Json(framing) => *framing,
+        }
+    }
+}
+
+/// Determines where a message ends on the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Messages are prefixed with their size as a little endian `usize`.
+    Length,
+
+    /// Messages are terminated by `\n`.
+    Newline,
 }
 
 #[derive(Debug, Error)]
@@ -85,9 +221,16 @@ pub enum PiosphereIOError {
     #[error("{0}")]
     Bincode(#[from] bincode::Error),
 
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
     #[error("{0}")]
     MalformedHeader(#[from] TryFromSliceError),
 
+    /// The session did not start with a known [preamble][Encoding::preamble].
+    #[error("{0}")]
+    Handshake(String),
+
     #[error("{0}")]
     Io(#[from] std::io::Error),
 }
@@ -95,22 +238,37 @@ pub enum PiosphereIOError {
 #[allow(async_fn_in_trait)]
 pub trait PiosphereWrite {
     async fn write<T: Serialize>(&mut self, message: T) -> PiosphereIOResult<()>;
+
+    /// Write an already encoded message using the given framing.
+    async fn write_framed(&mut self, framing: Framing, message: &[u8]) -> PiosphereIOResult<()>;
 }
 
 impl PiosphereWrite for UnixStream {
     async fn write<T: Serialize>(&mut self, message: T) -> PiosphereIOResult<()> {
+        let request = bincode::serialize(&message)?;
+        self.write_framed(Framing::Length, &request).await
+    }
+
+    async fn write_framed(&mut self, framing: Framing, message: &[u8]) -> PiosphereIOResult<()> {
         self.writable().await?;
 
         println!("Stream is writable");
-        let request = bincode::serialize(&message)?;
-
-        let header = PiosphereHeader::create(request.len());
 
-        self.write_all(&header).await?;
-        println!("Wrote header");
+        match framing {
+            Framing::Length => {
+                let header = PiosphereHeader::create(message.len());
+                self.write_all(&header).await?;
+                println!("Wrote header");
 
-        self.write_all(&request).await?;
-        println!("Wrote body");
+                self.write_all(message).await?;
+                println!("Wrote body");
+            }
+            Framing::Newline => {
+                self.write_all(message).await?;
+                self.write_all(b"\n").await?;
+                println!("Wrote line");
+            }
+        }
 
         self.flush().await?;
         println!("Socket Flushed");
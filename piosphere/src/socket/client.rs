@@ -1,7 +1,11 @@
 use crate::{
-    socket::{message::Hello, Header, PiosphereHeader, PiosphereWrite},
+    socket::{
+        decode_response, message::Hello, Encoding, Header, PiosphereHeader, PiosphereWrite,
+        HEADER_SIZE,
+    },
     PiosphereResult,
 };
+use std::io::{ErrorKind, Read, Write};
 use tokio::{
     io::AsyncReadExt,
     net::UnixStream,
@@ -25,7 +29,8 @@ impl Client {
         let (client_tx, session_rx) = tokio::sync::mpsc::channel(128);
         let (terminate_tx, terminate_rx) = tokio::sync::mpsc::channel(128);
 
-        let stream = UnixStream::connect(socket).await?;
+        let mut stream = UnixStream::connect(socket).await?;
+        tokio::io::AsyncWriteExt::write_all(&mut stream, Encoding::Bincode.preamble()).await?;
 
         let session = ClientSession::new(stream, terminate_rx, session_rx);
         let session_handle = session.start();
@@ -58,9 +63,7 @@ impl Client {
             .await
             .map_err(|e| PiosphereIOError::ChannelClosed(e.to_string()))?;
 
-        let res = bincode::deserialize(&res)?;
-
-        Ok(res)
+        decode_response(&res)
     }
 
     pub async fn close(self) -> Result<(), tokio::task::JoinError> {
@@ -71,6 +74,51 @@ impl Client {
     }
 }
 
+/// Synchronous counterpart of [Client] for callers without a tokio runtime.
+///
+/// Typed methods for each message are generated in [message][super::message].
+///
+/// Speaks the same length delimited bincode protocol over a std [UnixStream][std::os::unix::net::UnixStream].
+pub struct BlockingClient {
+    stream: std::os::unix::net::UnixStream,
+}
+
+impl BlockingClient {
+    pub fn new(socket: &str) -> PiosphereResult<Self> {
+        let mut stream = std::os::unix::net::UnixStream::connect(socket)?;
+        stream.write_all(Encoding::Bincode.preamble())?;
+
+        let mut this = Self { stream };
+
+        this.request(Hello)?;
+
+        Ok(this)
+    }
+
+    /// Send a Piosphere message to the server and block until it responds.
+    pub fn request<M: Message>(&mut self, msg: M) -> PiosphereResult<M::Response> {
+        let request = bincode::serialize(&msg.to_request()?)?;
+
+        let header = PiosphereHeader::create(request.len());
+        self.stream.write_all(&header)?;
+        self.stream.write_all(&request)?;
+        self.stream.flush()?;
+
+        let mut header = [0; HEADER_SIZE];
+        if let Err(e) = self.stream.read_exact(&mut header) {
+            if let ErrorKind::UnexpectedEof = e.kind() {
+                return Err(PiosphereIOError::SocketClosed(e.to_string()).into());
+            }
+            return Err(e.into());
+        }
+
+        let mut buf = vec![0; header.size()];
+        self.stream.read_exact(&mut buf)?;
+
+        decode_response(&buf)
+    }
+}
+
 struct ClientSession {
     stream: UnixStream,
     terminate_rx: Receiver<()>,
@@ -0,0 +1,131 @@
+//! JSON-RPC 2.0 encoding of the socket protocol, for clients not written in Rust.
+//!
+//! Requests use the snake cased [PiosphereTag][super::PiosphereTag] as the method and the JSON representation of the
+//! message as `params`, e.g.
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "method": "view_deployment", "params": "<id>", "id": 1}
+//! ```
+//!
+//! Messages without fields, such as `hello` and `overview`, can omit `params`.
+//! Requests without an `id` are treated as notifications and do not get a response.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{value::RawValue, Value};
+
+use super::PiosphereRequest;
+use crate::error::PiosphereError;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+#[derive(Debug, Deserialize)]
+pub struct JsonRpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Option<Box<RawValue>>,
+    #[serde(default)]
+    pub id: Option<Value>,
+}
+
+impl JsonRpcRequest {
+    /// Convert to a request that can be dispatched by the service.
+    ///
+    /// The message of the resulting request is JSON encoded.
+    pub fn to_request(&self) -> Result<PiosphereRequest, JsonRpcError> {
+        if self.jsonrpc != JSONRPC_VERSION {
+            return Err(JsonRpcError::new(
+                JsonRpcError::INVALID_REQUEST,
+                format!("Unsupported JSON-RPC version: {}", self.jsonrpc),
+            ));
+        }
+
+        let tag = serde_json::from_value(Value::String(self.method.clone())).map_err(|_| {
+            JsonRpcError::new(
+                JsonRpcError::METHOD_NOT_FOUND,
+                format!("Unknown method: {}", self.method),
+            )
+        })?;
+
+        let message = match self.params {
+            Some(ref params) => params.get().as_bytes().to_vec(),
+            None => b"null".to_vec(),
+        };
+
+        Ok(PiosphereRequest { tag, message })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRpcResponse {
+    pub jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Box<RawValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+    pub id: Value,
+}
+
+impl JsonRpcResponse {
+    pub fn success(id: Value, result: Box<RawValue>) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION,
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    pub fn error(id: Value, error: JsonRpcError) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION,
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+
+    /// The rejected fields for validation errors, the position for parse errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+
+    /// Errors originating from the handlers.
+    pub const SERVER_ERROR: i64 = -32000;
+
+    pub fn new(code: i64, message: String) -> Self {
+        Self {
+            code,
+            message,
+            data: None,
+        }
+    }
+}
+
+impl From<PiosphereError> for JsonRpcError {
+    fn from(e: PiosphereError) -> Self {
+        let code = match e {
+            PiosphereError::Json(_) | PiosphereError::Validation(_) => Self::INVALID_PARAMS,
+            _ => Self::SERVER_ERROR,
+        };
+        let mut error = Self::new(code, e.to_string());
+        match e {
+            PiosphereError::Validation(errors) => error.data = serde_json::to_value(errors).ok(),
+            PiosphereError::Parse(e) => error.data = serde_json::to_value(e).ok(),
+            _ => {}
+        }
+        error
+    }
+}
@@ -1,31 +1,131 @@
-use macros::request;
+//! Messages of the Piosphere protocol.
+//!
+//! To add a message, declare it in [messages] and implement [Handler][crate::Handler] for it
+//! on [PiosphereService][crate::PiosphereService].
+
+use macros::messages;
+use schemars::{schema::RootSchema, JsonSchema};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
-#[request(Self, Hello)]
-pub struct Hello;
-
-#[derive(Debug, Serialize, Deserialize)]
-#[request(Vec<crate::db::Deployment>, Overview)]
-pub struct Overview;
-
-#[derive(Debug, Serialize, Deserialize)]
-#[request(crate::deployment::Deployment, ViewDeployment)]
-pub struct ViewDeployment(pub String);
-
-#[macro_export]
-macro_rules! handle {
-    ($self:ident, $stream:ident, $msg:ident, $($tag:ident => $handler:path,)*) => {
-        let PiosphereRequest { tag, message } = $msg;
-
-        match tag {
-            $(
-                PiosphereTag::$tag => {
-                    let message = bincode::deserialize(&message)?;
-                    let response = <Self as Handler<$handler>>::handle($self, message).await?;
-                    $stream.write(response).await?;
-                }
-            ),*
+use super::{Message, PiosphereRequest};
+use crate::PiosphereResult;
+
+messages! {
+    handler: crate::PiosphereService;
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(Self)]
+    pub struct Hello;
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(Vec<crate::db::Deployment>)]
+    pub struct Overview;
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(crate::deployment::Deployment)]
+    pub struct ViewDeployment(#[param(id)] pub String);
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(crate::socket::server::ServerStatus)]
+    pub struct ServerInfo;
+
+    /// Terminate the session with the given ID. Responds with `false` if it does not exist.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(bool, admin)]
+    pub struct KillSession(#[param(id)] pub usize);
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(crate::db::Deployment)]
+    pub struct CreateDeployment(pub crate::deployment::Deployment);
+
+    /// Replace the configs of a deployment. Units it no longer has are stopped, and their
+    /// files removed along with those of the vhosts it no longer has.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(crate::db::Deployment)]
+    pub struct UpdateDeployment(pub crate::deployment::Deployment);
+
+    /// Responds with `false` if the deployment does not exist.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(bool)]
+    pub struct DeleteDeployment(#[param(id)] pub String);
+
+    /// Start, stop or restart the units of a deployment, or only the one named `component`,
+    /// e.g. `api.service`. Responds with the units the action was applied to, in order.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(Vec<String>)]
+    pub struct ControlDeployment {
+        pub id: String,
+
+        pub action: crate::backend::ServiceAction,
+
+        #[serde(default)]
+        pub component: Option<String>,
+    }
+
+    /// Lint the nginx configs of a deployment, without writing them. The server names are checked
+    /// against the other deployments. Deployments with lint errors cannot be created or updated.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(Vec<crate::deployment::nginx::lint::Diagnostic>)]
+    pub struct LintDeployment(pub crate::deployment::Deployment);
+
+    /// Upload the files of a static site to a directory a vhost of its deployment serves
+    /// files from. Responds with the number of written files.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(usize)]
+    pub struct UploadSite(#[param(upload)] pub crate::site::SiteUpload);
+
+    /// Renew the certificates piosphere obtained which expire soon, which the server also
+    /// does periodically. Responds with the renewed certificates.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(Vec<crate::certificates::IssuedCertificate>, admin)]
+    pub struct RenewCertificates;
+
+    /// The certificate of the local CA issuing the certificates of servers with
+    /// [Issuer::Local][crate::deployment::nginx::Issuer::Local], created if missing.
+    /// Install it on the machines which should trust them.
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    #[request(crate::ca::CaCertificate)]
+    pub struct ExportCaCertificate;
+
+    /// Execute multiple requests in order.
+    ///
+    /// If `atomic` is set, the first failing request reverts the changes made by the previous ones
+    /// and the rest are skipped. Atomic batches block all other requests while executing.
+    /// Batches cannot be nested.
+    #[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+    #[request(crate::batch::BatchResponse)]
+    pub struct Batch {
+        pub requests: Vec<PiosphereRequest>,
+
+        #[serde(default)]
+        pub atomic: bool,
+    }
+}
+
+/// Describes a message as exchanged in JSON-RPC sessions, see [schema].
+#[derive(Debug, Serialize)]
+pub struct MessageSchema {
+    pub method: &'static str,
+
+    /// Whether the message can only be sent by admins.
+    pub admin: bool,
+
+    pub params: RootSchema,
+
+    pub result: RootSchema,
+}
+
+impl Batch {
+    pub fn atomic() -> Self {
+        Self {
+            requests: vec![],
+            atomic: true,
         }
-    };
+    }
+
+    /// Append a message to the batch.
+    pub fn push<M: Message>(mut self, message: M) -> PiosphereResult<Self> {
+        self.requests.push(message.to_request()?);
+        Ok(self)
+    }
 }
@@ -1,49 +1,177 @@
 use crate::{
-    socket::{Header, PiosphereIOError, PiosphereRequest, HEADER_SIZE},
+    certificates,
+    db::DatabaseStats,
+    error::RemoteError,
+    socket::{
+        jsonrpc::{JsonRpcError, JsonRpcRequest, JsonRpcResponse},
+        session::Peer,
+        Encoding, Framing, Header, PiosphereIOError, PiosphereRequest, PiosphereWrite, HEADER_SIZE,
+        PREAMBLE_SIZE,
+    },
     PiosphereResult, PiosphereService,
 };
-use serde::de::DeserializeOwned;
-use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc};
+use chrono::NaiveDateTime;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{value::RawValue, Value};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    mem::ManuallyDrop,
+    os::fd::{FromRawFd, RawFd},
+    path::Path,
+    sync::Arc,
+};
 use tokio::{
-    io::AsyncReadExt,
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
     net::{UnixListener, UnixStream},
     sync::mpsc::{Receiver, Sender},
     task::JoinHandle,
 };
 
-use super::PiosphereIOResult;
+use super::{session::SessionInfo, PiosphereIOResult};
+
+/// The first file descriptor passed by the service manager, see `sd_listen_fds(3)`.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Response to [ServerInfo][super::message::ServerInfo].
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ServerStatus {
+    /// Version of the piosphere library the server is running.
+    pub version: String,
+
+    pub started_at: NaiveDateTime,
+
+    /// Uptime in seconds.
+    pub uptime: i64,
+
+    /// `None` if the service is not served by a [Server].
+    pub socket: Option<String>,
+
+    pub db_file: String,
+
+    /// Directory config file locations are relative to, `None` if they are absolute paths.
+    pub root: Option<String>,
+
+    /// Directory of the files piosphere manages itself, e.g. certificates, below the root.
+    pub data_dir: String,
+
+    pub db: DatabaseStats,
+
+    pub sessions: Vec<SessionInfo>,
+}
 
 pub struct Server {
     terminate_tx: Sender<()>,
     rt_handle: JoinHandle<()>,
+
+    /// Renews the certificates of the service.
+    renewal: JoinHandle<()>,
 }
 
 impl Server {
-    pub fn new(service: PiosphereService, socket: &str) -> Self {
+    /// Start the server runtime on the given socket.
+    ///
+    /// If the process was started by a systemd `.socket` unit, the inherited listener is used
+    /// and `socket` is ignored. Otherwise the socket is bound at the given path.
+    pub fn new(mut service: PiosphereService, socket: &str) -> PiosphereResult<Self> {
+        let listener = match Self::inherited_listener()? {
+            Some(listener) => listener,
+            None => Self::bind(socket)?,
+        };
+
+        service.socket = listener
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()));
+
+        let (terminate_tx, terminate_rx) = tokio::sync::mpsc::channel(128);
+        let (sys_tx, sys_rx) = tokio::sync::mpsc::channel(128);
+
+        let service = Arc::new(service);
+        let renewal = certificates::spawn_renewal(service.clone());
+
+        let rt = ServerRuntime::new(listener, sys_rx, terminate_rx, service);
+
+        let handle = rt.run(sys_tx);
+
+        Ok(Self {
+            terminate_tx,
+            rt_handle: handle,
+            renewal,
+        })
+    }
+
+    fn bind(socket: &str) -> std::io::Result<UnixListener> {
         let socket = Path::new(socket);
 
         // Delete old socket if necessary
         if socket.exists() {
-            std::fs::remove_file(socket).unwrap();
+            std::fs::remove_file(socket)?;
         }
 
         println!("Binding to {}", socket.display());
-        let listener = UnixListener::bind(socket).unwrap();
+        UnixListener::bind(socket)
+    }
 
-        let (terminate_tx, terminate_rx) = tokio::sync::mpsc::channel(128);
-        let (sys_tx, sys_rx) = tokio::sync::mpsc::channel(128);
+    /// Obtain the listener passed to us via socket activation (`LISTEN_PID`/`LISTEN_FDS`).
+    ///
+    /// Returns `None` if no file descriptors were passed to this process, in which case
+    /// the socket needs to be bound manually. The variables are left in the environment since
+    /// the runtime is already running, children ignore them as `LISTEN_PID` is not theirs.
+    fn inherited_listener() -> std::io::Result<Option<UnixListener>> {
+        let (Ok(pid), Ok(fds)) = (std::env::var("LISTEN_PID"), std::env::var("LISTEN_FDS")) else {
+            return Ok(None);
+        };
 
-        let rt = ServerRuntime::new(listener, sys_rx, terminate_rx, Arc::new(service));
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return Ok(None);
+        }
 
-        let handle = rt.run(sys_tx);
+        let fds = match fds.parse::<RawFd>() {
+            Ok(fds) if fds >= 1 => fds,
+            _ => return Ok(None),
+        };
 
-        Self {
-            terminate_tx,
-            rt_handle: handle,
+        if fds > 1 {
+            println!("Received {fds} file descriptors, only the first one will be used");
+        }
+
+        if !accepts_connections(SD_LISTEN_FDS_START) {
+            println!("Inherited file descriptor is not a listening socket");
+            return Ok(None);
+        }
+
+        // SAFETY: Only sets a flag of the descriptor, so it is not passed to children.
+        if unsafe { libc::fcntl(SD_LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // SAFETY: The service manager guarantees the descriptors starting at
+        // `SD_LISTEN_FDS_START` are open and belong to this process. It is not closed
+        // on drop until it is known to be a unix socket.
+        let listener = ManuallyDrop::new(unsafe {
+            std::os::unix::net::UnixListener::from_raw_fd(SD_LISTEN_FDS_START)
+        });
+
+        // Fails if the descriptor is not a unix socket
+        match listener.local_addr() {
+            Ok(addr) => println!("Using inherited socket {:?}", addr),
+            Err(e) => {
+                println!("Inherited file descriptor is not a unix socket: {e}");
+                return Ok(None);
+            }
         }
+
+        let listener = ManuallyDrop::into_inner(listener);
+        listener.set_nonblocking(true)?;
+
+        UnixListener::from_std(listener).map(Some)
     }
 
     pub async fn close(self) -> Result<(), tokio::task::JoinError> {
+        self.renewal.abort();
+
         self.terminate_tx.send(()).await.unwrap();
         println!("Sent termination to runtime");
         self.rt_handle.await
@@ -55,7 +183,6 @@ struct ServerRuntime {
     terminate_rx: Receiver<()>,
     listener: UnixListener,
     sys_rx: Receiver<SystemMessage>,
-    terminators: HashMap<usize, Sender<()>>,
     handles: HashMap<usize, JoinHandle<()>>,
     next_id: usize,
     service: Arc<PiosphereService>,
@@ -72,7 +199,6 @@ impl ServerRuntime {
             terminate_rx,
             listener,
             sys_rx,
-            terminators: HashMap::new(),
             handles: HashMap::new(),
             next_id: 0,
             service,
@@ -93,15 +219,24 @@ impl ServerRuntime {
 
                                 let (term_tx, term_rx) = tokio::sync::mpsc::channel(128);
                                 let session_id = self.gen_id();
+                                let peer = match socket.peer_cred() {
+                                    Ok(cred) => Some(Peer::from(cred)),
+                                    Err(e) => {
+                                        println!("Could not obtain peer credentials: {e}");
+                                        None
+                                    }
+                                };
                                 let session = ServerSession {
                                     id: session_id,
-                                    stream: socket,
+                                    peer,
+                                    stream: BufReader::new(socket),
+                                    encoding: None,
                                     sys_tx: sys_tx.clone(),
                                     terminate_rx: term_rx,
                                     service: self.service.clone(),
                                 };
                                 let handle = session.run();
-                                self.terminators.insert(session_id, term_tx);
+                                self.service.sessions.insert(session_id, peer, term_tx);
                                 self.handles.insert(session_id, handle);
                             }
                             Err(e) => println!("Error while accepting connection: {:?}", e),
@@ -125,7 +260,7 @@ impl ServerRuntime {
                     _ = self.terminate_rx.recv() => {
                         println!("Runtime terminating");
 
-                        for (id, term) in self.terminators.into_iter() {
+                        for (id, term) in self.service.sessions.drain() {
                             println!("Sending termination to {id}");
                             if let Err(e) = term.send(()).await {
                                 println!("Error while terminating session: {e}");
@@ -152,7 +287,7 @@ impl ServerRuntime {
                 if let Some(handle) = handle {
                     let _ = handle.await;
                 }
-                self.terminators.remove(&id);
+                self.service.sessions.remove(id);
             }
         }
         Ok(())
@@ -168,8 +303,14 @@ impl ServerRuntime {
 struct ServerSession {
     id: usize,
 
+    /// Credentials of the connected process
+    peer: Option<Peer>,
+
     /// Unix socket handle
-    stream: UnixStream,
+    stream: BufReader<UnixStream>,
+
+    /// Selected by the first message of the session
+    encoding: Option<Encoding>,
 
     /// Sending end for system messages
     sys_tx: Sender<SystemMessage>,
@@ -187,27 +328,32 @@ impl ServerSession {
             loop {
                 tokio::select! {
 
-                message = Self::read::<PiosphereRequest>(&mut self.stream) => {
-                        println!("Session got message: {:?}", message);
-                        match message {
-                            Ok(message) => {
-                                self.service.respond(&mut self.stream, message).await.unwrap();
+                frame = Self::read(&mut self.stream, &mut self.encoding) => {
+                        println!("Session got frame: {:?}", frame);
+                        match frame {
+                            Ok(frame) => {
+                                if let Err(e) = self.process(frame).await {
+                                    println!("Error while writing response: {e}");
+                                }
                             }
                             Err(e) => {
                                 match e {
                                     PiosphereIOError::SocketClosed(msg) => {
                                         println!("Socket closed: {msg}, terminating session");
-                                        self.sys_tx.send(SystemMessage::Close(self.id)).await.unwrap();
-                                        break;
-                                    },
-                                    _ => dbg!(e),
-                                };
+                                    }
+                                    e => println!("Error while reading: {e}, terminating session"),
+                                }
+                                // The runtime might be shutting down, in which case it cleans up on its own
+                                let _ = self.sys_tx.send(SystemMessage::Close(self.id)).await;
+                                break;
                             }
                         }
                 }
 
                 _ = self.terminate_rx.recv() => {
                     println!("Session terminating");
+                    // The runtime might be shutting down, in which case it cleans up on its own
+                    let _ = self.sys_tx.try_send(SystemMessage::Close(self.id));
                     break;
                 }
                 }
@@ -215,14 +361,126 @@ impl ServerSession {
         })
     }
 
-    async fn read<T: DeserializeOwned>(stream: &mut UnixStream) -> PiosphereIOResult<T> {
-        stream.readable().await?;
+    /// Respond to a single frame according to the session encoding.
+    async fn process(&mut self, frame: Vec<u8>) -> PiosphereIOResult<()> {
+        let encoding = self.encoding.unwrap_or(Encoding::Bincode);
+
+        self.service.sessions.record_request(self.id);
+
+        let response = match encoding {
+            Encoding::Bincode => {
+                let response = match bincode::deserialize::<PiosphereRequest>(&frame) {
+                    Ok(message) => {
+                        self.service
+                            .respond(self.peer.as_ref(), message, encoding)
+                            .await
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                bincode::serialize(&response.map_err(RemoteError::from))?
+            }
+            Encoding::Json(_) => match self.respond_json(&frame).await {
+                Some(response) => serde_json::to_vec(&response)?,
+                None => return Ok(()),
+            },
+        };
+
+        self.stream
+            .get_mut()
+            .write_framed(encoding.framing(), &response)
+            .await
+    }
+
+    /// Returns `None` if the request is a notification.
+    async fn respond_json(&self, frame: &[u8]) -> Option<JsonRpcResponse> {
+        let request = match serde_json::from_slice::<JsonRpcRequest>(frame) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = JsonRpcError::new(JsonRpcError::PARSE_ERROR, e.to_string());
+                return Some(JsonRpcResponse::error(Value::Null, error));
+            }
+        };
+
+        let result = match request.to_request() {
+            Ok(message) => self
+                .service
+                .respond(
+                    self.peer.as_ref(),
+                    message,
+                    Encoding::Json(Framing::Newline),
+                )
+                .await
+                .map_err(JsonRpcError::from)
+                .and_then(|result| {
+                    RawValue::from_string(String::from_utf8_lossy(&result).into_owned())
+                        .map_err(|e| JsonRpcError::new(JsonRpcError::SERVER_ERROR, e.to_string()))
+                }),
+            Err(e) => Err(e),
+        };
+
+        let id = request.id?;
 
+        match result {
+            Ok(result) => Some(JsonRpcResponse::success(id, result)),
+            Err(e) => Some(JsonRpcResponse::error(id, e)),
+        }
+    }
+
+    /// Read the next frame from the stream, selecting the session encoding from the
+    /// [preamble][Encoding::preamble] if this is the first one.
+    async fn read(
+        stream: &mut BufReader<UnixStream>,
+        encoding: &mut Option<Encoding>,
+    ) -> PiosphereIOResult<Vec<u8>> {
+        stream.get_ref().readable().await?;
+
+        match encoding {
+            Some(Encoding::Json(Framing::Newline)) => Self::read_line(stream).await,
+            Some(_) => Self::read_length(stream).await,
+            None => {
+                let buf = stream.fill_buf().await?;
+
+                let Some(first) = buf.first() else {
+                    return Err(PiosphereIOError::SocketClosed(
+                        "connection closed before handshake".to_string(),
+                    ));
+                };
+
+                if *first == b'{' {
+                    println!("Session encoding: {:?}", Encoding::Json(Framing::Newline));
+                    *encoding = Some(Encoding::Json(Framing::Newline));
+                    return Self::read_line(stream).await;
+                }
+
+                let mut preamble = [0; PREAMBLE_SIZE];
+                if let Err(e) = stream.read_exact(&mut preamble).await {
+                    if let ErrorKind::UnexpectedEof = e.kind() {
+                        return Err(PiosphereIOError::SocketClosed(e.to_string()));
+                    }
+                    return Err(e.into());
+                }
+
+                let Some(selected) = Encoding::from_preamble(&preamble) else {
+                    return Err(PiosphereIOError::Handshake(format!(
+                        "unknown preamble {preamble:?}"
+                    )));
+                };
+
+                println!("Session encoding: {:?}", selected);
+                *encoding = Some(selected);
+
+                Self::read_length(stream).await
+            }
+        }
+    }
+
+    async fn read_length(stream: &mut BufReader<UnixStream>) -> PiosphereIOResult<Vec<u8>> {
         let mut buf = [0; HEADER_SIZE];
         if let Err(e) = stream.read_exact(&mut buf).await {
             if let ErrorKind::UnexpectedEof = e.kind() {
                 return Err(PiosphereIOError::SocketClosed(e.to_string()));
             }
+            return Err(e.into());
         };
 
         let len = buf.size();
@@ -231,12 +489,44 @@ impl ServerSession {
         let mut buf = vec![0; len];
         stream.read_exact(&mut buf).await?;
 
-        let msg = bincode::deserialize(&buf)?;
+        Ok(buf)
+    }
+
+    async fn read_line(stream: &mut BufReader<UnixStream>) -> PiosphereIOResult<Vec<u8>> {
+        let mut buf = vec![];
 
-        Ok(msg)
+        // Skip blank lines between messages
+        while buf.iter().all(u8::is_ascii_whitespace) {
+            buf.clear();
+            if stream.read_until(b'\n', &mut buf).await? == 0 {
+                return Err(PiosphereIOError::SocketClosed("early eof".to_string()));
+            }
+        }
+
+        Ok(buf)
     }
 }
 
+/// Whether `fd` is a socket in the listening state, i.e. `listen` was called on it.
+fn accepts_connections(fd: RawFd) -> bool {
+    let mut accepting: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    // SAFETY: The option is written to `accepting` which is `len` bytes long,
+    // fails with `ENOTSOCK` or `EBADF` if `fd` is not an open socket.
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut accepting as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    res == 0 && accepting != 0
+}
+
 #[derive(Debug)]
 enum SystemMessage {
     /// Sent when a session closes
@@ -0,0 +1,126 @@
+//! Bookkeeping of connected clients, shared by the server runtime and the service.
+
+use chrono::NaiveDateTime;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Mutex};
+use tokio::{net::unix::UCred, sync::mpsc::Sender};
+
+/// Credentials of the process on the other end of the socket.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct Peer {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl Peer {
+    /// The credentials of this process.
+    pub fn current() -> Self {
+        // SAFETY: These calls are always successful and have no side effects.
+        unsafe {
+            Self {
+                uid: libc::geteuid(),
+                gid: libc::getegid(),
+                pid: Some(libc::getpid()),
+            }
+        }
+    }
+
+    /// Admins are root and the user the server is running as.
+    pub fn is_admin(&self) -> bool {
+        // SAFETY: `geteuid` is always successful and has no side effects.
+        self.uid == 0 || self.uid == unsafe { libc::geteuid() }
+    }
+}
+
+impl From<UCred> for Peer {
+    fn from(cred: UCred) -> Self {
+        Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SessionInfo {
+    pub id: usize,
+
+    /// `None` if the credentials could not be obtained from the socket.
+    pub peer: Option<Peer>,
+
+    pub connected_at: NaiveDateTime,
+
+    /// Number of requests the session received so far.
+    pub requests: u64,
+}
+
+#[derive(Debug)]
+struct SessionEntry {
+    info: SessionInfo,
+    terminate_tx: Sender<()>,
+}
+
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<usize, SessionEntry>>,
+}
+
+impl Sessions {
+    pub(crate) fn insert(&self, id: usize, peer: Option<Peer>, terminate_tx: Sender<()>) {
+        let info = SessionInfo {
+            id,
+            peer,
+            connected_at: chrono::Utc::now().naive_utc(),
+            requests: 0,
+        };
+        self.lock().insert(id, SessionEntry { info, terminate_tx });
+    }
+
+    pub(crate) fn remove(&self, id: usize) {
+        self.lock().remove(&id);
+    }
+
+    /// Remove all sessions, returning their termination senders.
+    pub(crate) fn drain(&self) -> Vec<(usize, Sender<()>)> {
+        self.lock()
+            .drain()
+            .map(|(id, entry)| (id, entry.terminate_tx))
+            .collect()
+    }
+
+    pub(crate) fn record_request(&self, id: usize) {
+        if let Some(entry) = self.lock().get_mut(&id) {
+            entry.info.requests += 1;
+        }
+    }
+
+    /// Signal the session to terminate. Returns `false` if the session does not exist.
+    pub fn kill(&self, id: usize) -> bool {
+        let Some(entry) = self.lock().remove(&id) else {
+            return false;
+        };
+
+        if let Err(e) = entry.terminate_tx.try_send(()) {
+            println!("Error while terminating session {id}: {e}");
+        }
+
+        true
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<_> = self
+            .lock()
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<usize, SessionEntry>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
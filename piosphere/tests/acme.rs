@@ -0,0 +1,451 @@
+//! The order flow of ACME certificates, against a CA which implements the parts of
+//! RFC 8555 piosphere uses and signs every CSR it is sent.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Datelike, NaiveDate};
+use piosphere::{
+    acme::AcmeConfig,
+    deployment::{
+        nginx::{CertificateInfo, Issuer, Listen, NginxConfig, NginxLocation, NginxServer, Tls},
+        Deployment,
+    },
+    Piosphere,
+};
+use piosphere_testkit::{
+    fake::{Command, Commands},
+    TestServer,
+};
+use rcgen::{
+    date_time_ymd, BasicConstraints, Certificate, CertificateParams,
+    CertificateSigningRequestParams, IsCa, KeyPair,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+const TOKEN: &str = "challenge-token";
+
+/// What the CA knows about the current order, orders are not run concurrently.
+#[derive(Debug, Default)]
+struct State {
+    names: Vec<String>,
+    validated: Option<bool>,
+    certificate: Option<String>,
+    nonces: usize,
+}
+
+/// An ACME CA served over plain HTTP, validating HTTP-01 challenges by reading them from the
+/// directory piosphere writes them to.
+struct MockCa {
+    url: String,
+    challenges: PathBuf,
+    certificate: Certificate,
+    key: KeyPair,
+    state: Mutex<State>,
+
+    /// How long the certificates it issues are valid.
+    validity: Mutex<chrono::Duration>,
+
+    /// Fail the challenges, as if nginx did not serve them.
+    reject: Mutex<bool>,
+}
+
+/// A response, with the `Location` of the resource if it created one.
+struct Reply {
+    status: &'static str,
+    location: Option<String>,
+    body: String,
+}
+
+impl Reply {
+    fn json(body: Value) -> Self {
+        Self {
+            status: "200 OK",
+            location: None,
+            body: body.to_string(),
+        }
+    }
+
+    fn created(location: String, body: Value) -> Self {
+        Self {
+            status: "201 Created",
+            location: Some(location),
+            body: body.to_string(),
+        }
+    }
+}
+
+impl MockCa {
+    async fn start(challenges: PathBuf) -> Arc<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key).unwrap();
+
+        let ca = Arc::new(Self {
+            url,
+            challenges,
+            certificate,
+            key,
+            state: Mutex::default(),
+            validity: Mutex::new(chrono::Duration::days(90)),
+            reject: Mutex::new(false),
+        });
+
+        let server = ca.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(server.clone().connection(stream));
+            }
+        });
+
+        ca
+    }
+
+    fn directory(&self) -> String {
+        format!("{}/directory", self.url)
+    }
+
+    /// Answer one request, the connection is closed afterwards.
+    async fn connection(self: Arc<Self>, stream: TcpStream) {
+        let mut stream = BufReader::new(stream);
+
+        let mut request_line = String::new();
+        stream.read_line(&mut request_line).await.unwrap();
+        let mut parts = request_line.split_whitespace();
+        let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+
+        let reply = self.reply(method, path, &body);
+        let nonce = {
+            let mut state = self.state.lock().unwrap();
+            state.nonces += 1;
+            format!("nonce-{}", state.nonces)
+        };
+        let location = reply
+            .location
+            .map(|location| format!("Location: {location}\r\n"))
+            .unwrap_or_default();
+        let content_type = match reply.body.starts_with("-----") {
+            true => "application/pem-certificate-chain",
+            false => "application/json",
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nReplay-Nonce: {nonce}\r\n{location}Content-Type: {content_type}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            reply.status,
+            reply.body.len()
+        );
+        let stream = stream.get_mut();
+        stream.write_all(response.as_bytes()).await.unwrap();
+        if method != "HEAD" {
+            stream.write_all(reply.body.as_bytes()).await.unwrap();
+        }
+        stream.shutdown().await.unwrap();
+    }
+
+    fn reply(&self, method: &str, path: &str, body: &[u8]) -> Reply {
+        if method == "GET" && path == "/directory" {
+            return Reply::json(json!({
+                "newNonce": format!("{}/nonce", self.url),
+                "newAccount": format!("{}/account", self.url),
+                "newOrder": format!("{}/order", self.url),
+            }));
+        }
+        if method == "HEAD" {
+            return Reply::json(json!({}));
+        }
+
+        let payload = payload(body);
+        let mut state = self.state.lock().unwrap();
+
+        match path {
+            "/account" => Reply::created(format!("{}/account/1", self.url), json!({})),
+            "/order" => {
+                *state = State {
+                    names: payload["identifiers"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|identifier| identifier["value"].as_str().unwrap().to_string())
+                        .collect(),
+                    nonces: state.nonces,
+                    ..Default::default()
+                };
+                Reply::created(format!("{}/order/1", self.url), self.order(&state))
+            }
+            "/order/1" => Reply::json(self.order(&state)),
+            "/authorization" => Reply::json(self.authorization(&state)),
+            "/challenge" => {
+                let file = self.challenges.join(TOKEN);
+                let served = std::fs::read_to_string(&file)
+                    .is_ok_and(|contents| contents.starts_with(&format!("{TOKEN}.")));
+                state.validated = Some(served && !*self.reject.lock().unwrap());
+                Reply::json(json!({}))
+            }
+            "/finalize" => {
+                let csr = URL_SAFE_NO_PAD
+                    .decode(payload["csr"].as_str().unwrap())
+                    .unwrap();
+                state.certificate = Some(self.sign(&csr));
+                Reply::json(self.order(&state))
+            }
+            "/certificate" => Reply {
+                status: "200 OK",
+                location: None,
+                body: state.certificate.clone().unwrap(),
+            },
+            path => panic!("unexpected request {method} {path}"),
+        }
+    }
+
+    fn order(&self, state: &State) -> Value {
+        let status = match (state.validated, &state.certificate) {
+            (_, Some(_)) => "valid",
+            (Some(true), None) => "ready",
+            (Some(false), None) => "invalid",
+            (None, None) => "pending",
+        };
+
+        json!({
+            "status": status,
+            "authorizations": [format!("{}/authorization", self.url)],
+            "finalize": format!("{}/finalize", self.url),
+            "certificate": state.certificate.as_ref().map(|_| format!("{}/certificate", self.url)),
+        })
+    }
+
+    fn authorization(&self, state: &State) -> Value {
+        let (status, error) = match state.validated {
+            Some(true) => ("valid", None),
+            Some(false) => (
+                "invalid",
+                Some(json!({
+                    "type": "urn:ietf:params:acme:error:unauthorized",
+                    "detail": "the challenge was not served",
+                })),
+            ),
+            None => ("pending", None),
+        };
+
+        json!({
+            "identifier": { "type": "dns", "value": state.names[0] },
+            "status": status,
+            "challenges": [{
+                "type": "http-01",
+                "url": format!("{}/challenge", self.url),
+                "token": TOKEN,
+                "error": error,
+            }],
+        })
+    }
+
+    /// Sign the CSR, valid from yesterday for [validity][Self::validity].
+    fn sign(&self, csr: &[u8]) -> String {
+        let mut csr = CertificateSigningRequestParams::from_der(&csr.into()).unwrap();
+        let date =
+            |date: NaiveDate| date_time_ymd(date.year(), date.month() as u8, date.day() as u8);
+        let today = chrono::Utc::now().date_naive();
+        csr.params.not_before = date(today - chrono::Duration::days(1));
+        csr.params.not_after = date(today + *self.validity.lock().unwrap());
+
+        let certificate = csr.signed_by(&self.certificate, &self.key).unwrap();
+        format!("{}{}", certificate.pem(), self.certificate.pem())
+    }
+}
+
+/// The payload of a JWS request body, `Null` for POST-as-GET requests.
+fn payload(body: &[u8]) -> Value {
+    let jws: Value = serde_json::from_slice(body).unwrap();
+    let payload = URL_SAFE_NO_PAD
+        .decode(jws["payload"].as_str().unwrap())
+        .unwrap();
+    serde_json::from_slice(&payload).unwrap_or(Value::Null)
+}
+
+async fn piosphere(root: &Path, commands: &Commands, ca: &MockCa) -> Piosphere {
+    TestServer::builder(root, commands)
+        .data_dir("/var/lib/piosphere")
+        .acme(AcmeConfig {
+            poll_interval: Duration::from_millis(10),
+            ..AcmeConfig::new(&ca.directory())
+        })
+        .build()
+        .await
+        .unwrap()
+}
+
+fn plain() -> NginxServer {
+    NginxServer {
+        server_name: vec!["shop.test".parse().unwrap()],
+        location: vec![NginxLocation::new()],
+        ..Default::default()
+    }
+}
+
+fn secure() -> NginxServer {
+    NginxServer {
+        listen: vec![Listen {
+            ssl: true,
+            ..Listen::new("443".parse().unwrap())
+        }],
+        tls: Some(Tls {
+            issuer: Some(Issuer::Acme),
+            ..Tls::new("", "")
+        }),
+        ..plain()
+    }
+}
+
+fn shop(server: NginxServer) -> Deployment {
+    Deployment::site(
+        "shop",
+        "shop deployment",
+        NginxConfig {
+            file_location: "/etc/nginx/sites-enabled/shop".to_string(),
+            servers: vec![server],
+            ..Default::default()
+        },
+    )
+}
+
+/// The certificate obtained for `shop.test`.
+fn certificate(root: &Path) -> PathBuf {
+    root.join("var/lib/piosphere/acme/certificates/shop.test/fullchain.pem")
+}
+
+fn challenges(root: &Path) -> PathBuf {
+    root.join("var/lib/piosphere/acme/challenges")
+}
+
+#[tokio::test]
+async fn certificates_are_ordered_on_create() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let ca = MockCa::start(challenges(root.path())).await;
+    let piosphere = piosphere(root.path(), &commands, &ca).await;
+
+    piosphere.create_deployment(shop(secure())).await.unwrap();
+
+    let info = CertificateInfo::read(&certificate(root.path())).unwrap();
+    assert_eq!(info.names, ["shop.test"]);
+    assert!(info.not_after > chrono::Utc::now().naive_utc() + chrono::Duration::days(80));
+
+    let vhost = std::fs::read_to_string(root.path().join("etc/nginx/sites-enabled/shop")).unwrap();
+    assert!(
+        vhost.contains(
+            "ssl_certificate /var/lib/piosphere/acme/certificates/shop.test/fullchain.pem;"
+        ),
+        "{vhost}"
+    );
+    assert!(
+        vhost.contains("/var/lib/piosphere/acme/challenges"),
+        "{vhost}"
+    );
+
+    // The challenge is removed once validated
+    assert!(!challenges(root.path()).join(TOKEN).exists());
+
+    // Once for the vhost serving the challenge, once for the complete vhost
+    assert_eq!(
+        commands.take(),
+        [Command::NginxReload, Command::NginxReload]
+    );
+}
+
+#[tokio::test]
+async fn failed_orders_restore_the_vhost() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let ca = MockCa::start(challenges(root.path())).await;
+    let piosphere = piosphere(root.path(), &commands, &ca).await;
+
+    let id = piosphere.create_deployment(shop(plain())).await.unwrap().id;
+    let vhost = root.path().join("etc/nginx/sites-enabled/shop");
+    let before = std::fs::read_to_string(&vhost).unwrap();
+    commands.take();
+
+    *ca.reject.lock().unwrap() = true;
+    let mut updated = shop(secure());
+    updated.id = id.clone();
+    let e = piosphere.update_deployment(updated).await.unwrap_err();
+    assert!(
+        e.to_string()
+            .contains("Could not validate `shop.test`, the authorization is invalid"),
+        "{e}"
+    );
+
+    assert_eq!(std::fs::read_to_string(&vhost).unwrap(), before);
+    assert!(!certificate(root.path()).exists());
+
+    // The vhost serving the challenge was loaded, then nginx reloads once the order reverts
+    // it and once the failed request is rolled back
+    assert_eq!(
+        commands.take(),
+        [
+            Command::NginxReload,
+            Command::NginxReload,
+            Command::NginxReload
+        ]
+    );
+
+    let viewed = piosphere.view_deployment(&id).await.unwrap();
+    assert!(viewed.nginx_cfgs[0].servers[0].tls.is_none());
+}
+
+#[tokio::test]
+async fn certificates_expiring_soon_are_renewed() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let ca = MockCa::start(challenges(root.path())).await;
+    let piosphere = piosphere(root.path(), &commands, &ca).await;
+
+    *ca.validity.lock().unwrap() = chrono::Duration::days(10);
+    piosphere.create_deployment(shop(secure())).await.unwrap();
+    let expiring = CertificateInfo::read(&certificate(root.path())).unwrap();
+    commands.take();
+
+    *ca.validity.lock().unwrap() = chrono::Duration::days(90);
+    let renewed = piosphere.renew_certificates().await.unwrap();
+    assert_eq!(renewed.len(), 1);
+    assert_eq!(renewed[0].names, ["shop.test"]);
+
+    let info = CertificateInfo::read(&certificate(root.path())).unwrap();
+    assert_eq!(info.not_after, renewed[0].not_after);
+    assert!(info.not_after > expiring.not_after + chrono::Duration::days(70));
+    assert_eq!(commands.take(), [Command::NginxReload]);
+
+    // Certificates which do not expire soon are kept
+    assert!(piosphere.renew_certificates().await.unwrap().is_empty());
+    assert!(commands.take().is_empty());
+}
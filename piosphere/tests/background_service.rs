@@ -0,0 +1,71 @@
+use piosphere::deployment::{systemd::SystemdConfig, Deployment};
+use piosphere_testkit::{
+    fake::{Command, Commands},
+    TestServer,
+};
+
+fn worker() -> Deployment {
+    let mut sysd = SystemdConfig {
+        file_location: "/etc/systemd/system/queue.service".to_string(),
+        ..Default::default()
+    };
+    sysd.unit
+        .params
+        .insert("Description".to_string(), "Queue consumer".to_string());
+
+    Deployment::service("queue", "queue consumer", sysd)
+}
+
+#[tokio::test]
+async fn workers_do_not_involve_nginx() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    // Deployments which reload nginx fail
+    commands.fail(Command::NginxReload, "nginx is not installed");
+
+    assert!(piosphere
+        .lint_deployment(worker())
+        .await
+        .unwrap()
+        .is_empty());
+
+    let created = piosphere.create_deployment(worker()).await.unwrap();
+    let unit =
+        std::fs::read_to_string(root.path().join("etc/systemd/system/queue.service")).unwrap();
+    assert!(unit.contains("Description=Queue consumer"), "{unit}");
+
+    let stats = piosphere.server_info().await.unwrap().db;
+    assert_eq!((stats.nginx_configs, stats.sysd_configs), (0, 1));
+
+    let mut updated = worker();
+    updated.id = created.id.clone();
+    updated.description = "queue consumer, 2 threads".to_string();
+    piosphere.update_deployment(updated).await.unwrap();
+
+    assert!(piosphere.renew_certificates().await.unwrap().is_empty());
+    assert_eq!(
+        commands.take(),
+        [Command::SystemdReload, Command::SystemdReload]
+    );
+
+    let viewed = piosphere.view_deployment(&created.id).await.unwrap();
+    assert!(viewed.nginx_cfgs.is_empty());
+    assert_eq!(viewed.description, "queue consumer, 2 threads");
+
+    // Views in JSON leave out the vhost, bincode keeps every field
+    let json = serde_json::to_value(&viewed).unwrap();
+    assert!(json.get("nginx_cfgs").is_none(), "{json}");
+    assert!(json.get("service_cfgs").is_some(), "{json}");
+    let parsed: Deployment = serde_json::from_value(json).unwrap();
+    assert!(parsed.nginx_cfgs.is_empty());
+
+    let encoded = bincode::serialize(&viewed).unwrap();
+    let decoded: Deployment = bincode::deserialize(&encoded).unwrap();
+    assert!(decoded.nginx_cfgs.is_empty());
+    assert_eq!(decoded.id, created.id);
+}
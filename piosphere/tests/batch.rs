@@ -0,0 +1,137 @@
+use piosphere::{
+    batch::BatchResult,
+    deployment::{
+        nginx::{NginxConfig, NginxLocation, NginxServer},
+        systemd::SystemdConfig,
+        Deployment,
+    },
+    error::PiosphereError,
+    socket::message::{Batch, CreateDeployment},
+};
+use piosphere_testkit::{
+    fake::{Command, Commands},
+    TestServer,
+};
+
+/// A deployment named `name` serving `server_name`.
+fn deployment(name: &str, server_name: &str) -> Deployment {
+    let nginx = NginxConfig {
+        file_location: format!("/etc/nginx/sites-enabled/{name}"),
+        servers: vec![NginxServer {
+            server_name: vec![server_name.parse().unwrap()],
+            location: vec![NginxLocation::new()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let sysd = SystemdConfig {
+        file_location: format!("/etc/systemd/system/{name}.service"),
+        ..Default::default()
+    };
+    Deployment::new(name, &format!("{name} deployment"), nginx, sysd)
+}
+
+#[tokio::test]
+async fn failing_atomic_batches_leave_nothing_behind() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    // The second deployment fails linting, it serves the name of the first one
+    let batch = Batch::atomic()
+        .push(CreateDeployment(deployment("shop", "shop.test")))
+        .unwrap()
+        .push(CreateDeployment(deployment("copy", "shop.test")))
+        .unwrap();
+
+    let response = piosphere.request(batch).await.unwrap();
+
+    assert!(response.rolled_back);
+    assert!(matches!(
+        response.results[..],
+        [BatchResult::Ok(_), BatchResult::Err(_)]
+    ));
+
+    assert!(piosphere.overview().await.unwrap().is_empty());
+    let stats = piosphere.server_info().await.unwrap().db;
+    assert_eq!((stats.nginx_configs, stats.sysd_configs), (0, 0));
+
+    for file in [
+        "etc/nginx/sites-enabled/shop",
+        "etc/systemd/system/shop.service",
+        "etc/nginx/sites-enabled/copy",
+        "etc/systemd/system/copy.service",
+    ] {
+        assert!(!root.path().join(file).exists(), "{file}");
+    }
+
+    // What picked up the files of the first deployment does again once they are removed
+    assert_eq!(
+        commands.take(),
+        [
+            Command::SystemdReload,
+            Command::NginxReload,
+            Command::NginxReload,
+            Command::SystemdReload,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn deployments_are_only_committed_once_reloaded() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    commands.fail(Command::NginxReload, "invalid vhost");
+    let e = piosphere
+        .create_deployment(deployment("shop", "shop.test"))
+        .await
+        .unwrap_err();
+    assert!(matches!(e, PiosphereError::Backend(_)), "{e:?}");
+
+    assert!(piosphere.overview().await.unwrap().is_empty());
+    assert!(!root.path().join("etc/nginx/sites-enabled/shop").exists());
+    assert!(!root.path().join("etc/systemd/system/shop.service").exists());
+    // Failing commands are not recorded, systemd picks up the removed unit again
+    assert_eq!(
+        commands.take(),
+        [Command::SystemdReload, Command::SystemdReload]
+    );
+
+    // Nothing is left to conflict with
+    commands.succeed(Command::NginxReload);
+    piosphere
+        .create_deployment(deployment("shop", "shop.test"))
+        .await
+        .unwrap();
+    assert_eq!(piosphere.overview().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn successful_atomic_batches_are_committed() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    let batch = Batch::atomic()
+        .push(CreateDeployment(deployment("shop", "shop.test")))
+        .unwrap()
+        .push(CreateDeployment(deployment("blog", "blog.test")))
+        .unwrap();
+
+    let response = piosphere.request(batch).await.unwrap();
+
+    assert!(!response.rolled_back);
+    assert_eq!(piosphere.overview().await.unwrap().len(), 2);
+    assert!(root.path().join("etc/nginx/sites-enabled/blog").exists());
+}
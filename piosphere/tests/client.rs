@@ -0,0 +1,126 @@
+use piosphere::{
+    deployment::{systemd::SystemdConfig, Deployment},
+    error::PiosphereError,
+    socket::{client::BlockingClient, message::Hello},
+};
+use piosphere_testkit::TestServer;
+use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+
+/// User ID of `nobody`.
+const NOBODY: libc::uid_t = 65534;
+
+#[tokio::test]
+async fn validation_errors_keep_their_fields() {
+    let server = TestServer::start().await;
+
+    let sysd = SystemdConfig {
+        file_location: "/etc/systemd/system/app.service".to_string(),
+        ..Default::default()
+    };
+    let deployment = Deployment::service("", "no name", sysd);
+
+    let Err(PiosphereError::Validation(errors)) =
+        server.client().create_deployment(deployment).await
+    else {
+        panic!("expected a validation error");
+    };
+
+    let fields: Vec<_> = errors.0.iter().map(|e| e.field.as_str()).collect();
+    assert_eq!(fields, ["name"]);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn server_info_reports_the_directories() {
+    let server = TestServer::start().await;
+
+    let status = server.client().server_info().await.unwrap();
+    assert_eq!(status.socket.as_deref(), Some(server.socket()));
+    assert_eq!(status.root, Some(server.root().display().to_string()));
+    assert_eq!(
+        status.data_dir,
+        server.path("/opt/piosphere").display().to_string()
+    );
+
+    server.stop().await;
+}
+
+/// The credentials of a socket are those of the thread connecting it, so the non-admin
+/// client connects from a thread running as `nobody`.
+#[tokio::test]
+async fn non_admins_are_forbidden_and_keep_their_session() {
+    // SAFETY: `geteuid` is always successful and has no side effects.
+    if unsafe { libc::geteuid() } != 0 {
+        println!("Not running as root, cannot connect as another user");
+        return;
+    }
+
+    let server = TestServer::start().await;
+
+    std::fs::set_permissions(server.root(), Permissions::from_mode(0o755)).unwrap();
+    std::fs::set_permissions(server.socket(), Permissions::from_mode(0o777)).unwrap();
+
+    let path = server.socket().to_string();
+    let (killed, hello) = tokio::task::spawn_blocking(move || {
+        std::thread::spawn(move || {
+            // SAFETY: Unlike `seteuid`, the syscall only changes the user of this thread,
+            // which exits afterwards.
+            let res = unsafe { libc::syscall(libc::SYS_setresuid, -1, NOBODY, -1) };
+            assert_eq!(res, 0, "could not switch to nobody");
+
+            let mut client = BlockingClient::new(&path).unwrap();
+            let killed = client.kill_session(0);
+            let hello = client.request(Hello);
+            (killed, hello)
+        })
+        .join()
+        .unwrap()
+    })
+    .await
+    .unwrap();
+
+    assert!(
+        matches!(killed, Err(PiosphereError::Forbidden(_))),
+        "{killed:?}"
+    );
+    assert!(hello.is_ok(), "session died after the rejection: {hello:?}");
+
+    // The session of the admin was not killed
+    server.client().overview().await.unwrap();
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn blocking_clients_have_a_method_per_message() {
+    let server = TestServer::start().await;
+    let socket = server.socket().to_string();
+    let deployment = server.worker("worker");
+
+    tokio::task::spawn_blocking(move || {
+        let mut client = BlockingClient::new(&socket).unwrap();
+
+        let created = client.create_deployment(deployment).unwrap();
+        assert_eq!(created.name, "worker");
+
+        let listed: Vec<_> = client
+            .overview()
+            .unwrap()
+            .into_iter()
+            .map(|deployment| deployment.id)
+            .collect();
+        assert_eq!(listed, [created.id.as_str()]);
+
+        let viewed = client.view_deployment(&created.id).unwrap();
+        assert_eq!(viewed.service_cfgs[0].unit_name(), "worker.service");
+
+        assert!(client.delete_deployment(&created.id).unwrap());
+        assert!(client.view_deployment(&created.id).is_err());
+        assert!(client.overview().unwrap().is_empty());
+    })
+    .await
+    .unwrap();
+
+    server.stop().await;
+}
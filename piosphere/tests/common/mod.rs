@@ -0,0 +1,10 @@
+//! Helpers shared by the integration tests.
+
+/// The lines of `after` which are not in `before`.
+pub fn changed_lines<'a>(before: &str, after: &'a str) -> Vec<&'a str> {
+    let before: Vec<_> = before.lines().collect();
+    after
+        .lines()
+        .filter(|line| !before.contains(line))
+        .collect()
+}
@@ -0,0 +1,99 @@
+use piosphere::{
+    backend::ServiceAction,
+    deployment::{
+        nginx::{NginxConfig, NginxLocation, NginxServer},
+        systemd::SystemdConfig,
+        Deployment,
+    },
+    socket::message::{Batch, DeleteDeployment, ViewDeployment},
+};
+use piosphere_testkit::{
+    fake::{Command, Commands},
+    TestServer,
+};
+
+const UNIT: &str = "etc/systemd/system/app.service";
+const VHOST: &str = "etc/nginx/sites-enabled/app.test";
+
+fn app() -> Deployment {
+    let nginx = NginxConfig {
+        file_location: format!("/{VHOST}"),
+        servers: vec![NginxServer {
+            server_name: vec!["app.test".parse().unwrap()],
+            location: vec![NginxLocation::new()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let sysd = SystemdConfig {
+        file_location: format!("/{UNIT}"),
+        ..Default::default()
+    };
+    Deployment::new("app", "app deployment", nginx, sysd)
+}
+
+#[tokio::test]
+async fn deleting_stops_the_units_and_removes_the_files() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    let id = piosphere.create_deployment(app()).await.unwrap().id;
+    commands.clear();
+
+    assert!(piosphere.delete_deployment(&id).await.unwrap());
+
+    assert!(!root.path().join(UNIT).exists());
+    assert!(!root.path().join(VHOST).exists());
+    assert_eq!(
+        commands.take(),
+        [
+            Command::SystemdControl(ServiceAction::Stop, "app.service".to_string()),
+            Command::SystemdReload,
+            Command::NginxReload,
+        ]
+    );
+
+    let stats = piosphere.server_info().await.unwrap().db;
+    assert_eq!(
+        (stats.deployments, stats.nginx_configs, stats.sysd_configs),
+        (0, 0, 0)
+    );
+
+    assert!(!piosphere.delete_deployment(&id).await.unwrap());
+    assert!(commands.take().is_empty());
+
+    // The locations are free for a new deployment
+    piosphere.create_deployment(app()).await.unwrap();
+    assert!(root.path().join(UNIT).exists());
+}
+
+#[tokio::test]
+async fn reverted_deletes_restore_the_files() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    let id = piosphere.create_deployment(app()).await.unwrap().id;
+    let unit = std::fs::read_to_string(root.path().join(UNIT)).unwrap();
+
+    let batch = Batch::atomic()
+        .push(DeleteDeployment(id.clone()))
+        .unwrap()
+        .push(ViewDeployment("missing".to_string()))
+        .unwrap();
+    assert!(piosphere.request(batch).await.unwrap().rolled_back);
+
+    assert_eq!(
+        std::fs::read_to_string(root.path().join(UNIT)).unwrap(),
+        unit
+    );
+    assert!(root.path().join(VHOST).exists());
+    assert_eq!(piosphere.view_deployment(&id).await.unwrap().name, "app");
+}
@@ -0,0 +1,178 @@
+use piosphere::{
+    batch::{BatchResponse, BatchResult},
+    error::RemoteError,
+    socket::{
+        message::{Batch, ViewDeployment},
+        Encoding, Framing, Message,
+    },
+};
+use piosphere_testkit::TestServer;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+
+/// Send a newline delimited request and read the response line.
+async fn call(stream: &mut BufReader<UnixStream>, request: &str) -> Value {
+    stream
+        .get_mut()
+        .write_all(format!("{request}\n").as_bytes())
+        .await
+        .unwrap();
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+/// Send a length delimited frame and read the response frame.
+async fn frame(stream: &mut UnixStream, frame: &[u8]) -> Vec<u8> {
+    stream.write_all(&frame.len().to_le_bytes()).await.unwrap();
+    stream.write_all(frame).await.unwrap();
+
+    let mut header = [0; std::mem::size_of::<usize>()];
+    stream.read_exact(&mut header).await.unwrap();
+
+    let mut response = vec![0; usize::from_le_bytes(header)];
+    stream.read_exact(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn requests_are_answered() {
+    let server = TestServer::start().await;
+    let mut stream = BufReader::new(UnixStream::connect(server.socket()).await.unwrap());
+
+    let response = call(
+        &mut stream,
+        r#"{"jsonrpc": "2.0", "method": "overview", "id": 1}"#,
+    )
+    .await;
+    assert_eq!(response, json!({"jsonrpc": "2.0", "result": [], "id": 1}));
+
+    let response = call(
+        &mut stream,
+        r#"{"jsonrpc": "2.0", "method": "server_info", "id": "info"}"#,
+    )
+    .await;
+    assert_eq!(response["id"], "info");
+    assert_eq!(response["result"]["socket"], server.socket());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unknown_methods_are_rejected() {
+    let server = TestServer::start().await;
+    let mut stream = BufReader::new(UnixStream::connect(server.socket()).await.unwrap());
+
+    let response = call(
+        &mut stream,
+        r#"{"jsonrpc": "2.0", "method": "launch_rockets", "id": 7}"#,
+    )
+    .await;
+    assert_eq!(response["id"], 7);
+    assert_eq!(response["error"]["code"], -32601);
+    assert_eq!(
+        response["error"]["message"],
+        "Unknown method: launch_rockets"
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn malformed_json_is_a_parse_error() {
+    let server = TestServer::start().await;
+    let mut stream = BufReader::new(UnixStream::connect(server.socket()).await.unwrap());
+
+    let response = call(&mut stream, r#"{"jsonrpc": "2.0", "method": "#).await;
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+
+    // The session is still usable
+    let response = call(
+        &mut stream,
+        r#"{"jsonrpc": "2.0", "method": "hello", "id": 2}"#,
+    )
+    .await;
+    assert_eq!(response, json!({"jsonrpc": "2.0", "result": null, "id": 2}));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn length_delimited_json_needs_its_preamble() {
+    let server = TestServer::start().await;
+    let mut stream = UnixStream::connect(server.socket()).await.unwrap();
+
+    stream
+        .write_all(Encoding::Json(Framing::Length).preamble())
+        .await
+        .unwrap();
+
+    let response = frame(
+        &mut stream,
+        br#"{"jsonrpc": "2.0", "method": "overview", "id": 1}"#,
+    )
+    .await;
+    let response: Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response, json!({"jsonrpc": "2.0", "result": [], "id": 1}));
+
+    server.stop().await;
+}
+
+/// The low byte of the length of a 123 byte frame is `{`, which must not select JSON.
+#[tokio::test]
+async fn bincode_frames_starting_with_a_brace_are_bincode() {
+    let server = TestServer::start().await;
+    let mut stream = UnixStream::connect(server.socket()).await.unwrap();
+
+    let mut id = String::new();
+    let request = loop {
+        let request = Batch::default()
+            .push(ViewDeployment(id.clone()))
+            .unwrap()
+            .to_request()
+            .unwrap();
+        let request = bincode::serialize(&request).unwrap();
+        if request.len() >= 123 {
+            break request;
+        }
+        id.push('x');
+    };
+    assert_eq!(request.len(), 123);
+    assert_eq!(request.len().to_le_bytes()[0], b'{');
+
+    stream
+        .write_all(Encoding::Bincode.preamble())
+        .await
+        .unwrap();
+
+    let response = frame(&mut stream, &request).await;
+    let response: Result<Vec<u8>, RemoteError> = bincode::deserialize(&response).unwrap();
+    let response: BatchResponse = bincode::deserialize(&response.unwrap()).unwrap();
+
+    assert!(!response.rolled_back);
+    assert!(matches!(response.results[..], [BatchResult::Err(_)]));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn undecodable_bincode_frames_get_an_error() {
+    let server = TestServer::start().await;
+    let mut stream = UnixStream::connect(server.socket()).await.unwrap();
+
+    stream
+        .write_all(Encoding::Bincode.preamble())
+        .await
+        .unwrap();
+
+    let response = frame(&mut stream, &[0xff, 0xff, 0xff]).await;
+    let response: Result<Vec<u8>, RemoteError> = bincode::deserialize(&response).unwrap();
+    assert!(matches!(response, Err(RemoteError::Server(_))));
+
+    server.stop().await;
+}
@@ -0,0 +1,178 @@
+use piosphere::{
+    deployment::{systemd::SystemdConfig, Deployment},
+    error::PiosphereError,
+    layer::{pass, BoxFuture, Call, Guard, Layer, Value},
+    socket::message::{Batch, CreateDeployment, Hello, Overview},
+    Piosphere, PiosphereBuilder, PiosphereResult,
+};
+use piosphere_testkit::{fake::Commands, TestServer};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+/// Records what passes through it, its guard records when it is dropped.
+#[derive(Debug)]
+struct Recorder {
+    name: &'static str,
+    log: Log,
+}
+
+struct Dropped(&'static str, Log);
+
+impl Drop for Dropped {
+    fn drop(&mut self) {
+        self.1.lock().unwrap().push(format!("drop {}", self.0));
+    }
+}
+
+impl Layer for Recorder {
+    fn before<'l, 'a>(
+        &'l self,
+        call: &'a Call<'a>,
+        _: Value<'a>,
+    ) -> BoxFuture<'a, PiosphereResult<Guard<'l>>>
+    where
+        'l: 'a,
+    {
+        Box::pin(async move {
+            // The service waits for the layer
+            tokio::task::yield_now().await;
+
+            let mut log = self.log.lock().unwrap();
+            log.push(format!("before {} {:?}", self.name, call.tag));
+            Ok(Box::new(Dropped(self.name, self.log.clone())) as Guard)
+        })
+    }
+
+    fn after(&self, call: &Call, result: Result<Value, &PiosphereError>) {
+        let outcome = if result.is_ok() { "ok" } else { "err" };
+        let mut log = self.log.lock().unwrap();
+        log.push(format!("after {} {:?} {outcome}", self.name, call.tag));
+    }
+}
+
+/// Rejects deployments.
+#[derive(Debug)]
+struct NoDeployments;
+
+impl Layer for NoDeployments {
+    fn before<'l, 'a>(
+        &'l self,
+        _: &'a Call<'a>,
+        message: Value<'a>,
+    ) -> BoxFuture<'a, PiosphereResult<Guard<'l>>>
+    where
+        'l: 'a,
+    {
+        if message.downcast_ref::<CreateDeployment>().is_some() {
+            return Box::pin(async {
+                Err(PiosphereError::Forbidden("no deployments".to_string()))
+            });
+        }
+        pass()
+    }
+}
+
+/// A service with the layers `configure` adds.
+async fn piosphere(
+    configure: impl FnOnce(PiosphereBuilder, Log) -> PiosphereBuilder,
+) -> (TempDir, Log, Piosphere) {
+    let root = tempfile::tempdir().unwrap();
+    let log = Log::default();
+
+    let builder = TestServer::builder(root.path(), &Commands::default());
+    let piosphere = configure(builder, log.clone()).build().await.unwrap();
+
+    (root, log, piosphere)
+}
+
+fn recorder(name: &'static str, log: &Log) -> Recorder {
+    Recorder {
+        name,
+        log: log.clone(),
+    }
+}
+
+#[tokio::test]
+async fn layers_are_nested_in_the_order_they_were_added() {
+    let (_root, log, piosphere) = piosphere(|builder, log| {
+        builder
+            .layer(recorder("outer", &log))
+            .layer(recorder("inner", &log))
+    })
+    .await;
+
+    piosphere.hello().await.unwrap();
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "before outer Hello",
+            "before inner Hello",
+            "after inner Hello ok",
+            "drop inner",
+            "after outer Hello ok",
+            "drop outer",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rejections_skip_the_handler_and_later_layers() {
+    let (_root, log, piosphere) = piosphere(|builder, log| {
+        builder
+            .layer(recorder("outer", &log))
+            .layer(NoDeployments)
+            .layer(recorder("inner", &log))
+    })
+    .await;
+
+    let sysd = SystemdConfig {
+        file_location: "/etc/systemd/system/app.service".to_string(),
+        ..Default::default()
+    };
+    let e = piosphere
+        .create_deployment(Deployment::service("app", "", sysd))
+        .await
+        .unwrap_err();
+    assert!(matches!(e, PiosphereError::Forbidden(_)), "{e:?}");
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "before outer CreateDeployment",
+            "after outer CreateDeployment err",
+            "drop outer",
+        ]
+    );
+    assert!(piosphere.overview().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn batches_pass_through_the_layers_before_their_requests() {
+    let (_root, log, piosphere) =
+        piosphere(|builder, log| builder.layer(recorder("log", &log))).await;
+
+    let batch = Batch::default()
+        .push(Hello)
+        .unwrap()
+        .push(Overview)
+        .unwrap();
+    piosphere.request(batch).await.unwrap();
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "before log Batch",
+            "before log Hello",
+            "after log Hello ok",
+            "drop log",
+            "before log Overview",
+            "after log Overview ok",
+            "drop log",
+            "after log Batch ok",
+            "drop log",
+        ]
+    );
+}
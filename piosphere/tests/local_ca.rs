@@ -0,0 +1,116 @@
+use std::os::unix::fs::PermissionsExt;
+
+use piosphere::{
+    deployment::{
+        nginx::{CertificateInfo, Issuer, Listen, NginxConfig, NginxServer, Tls},
+        systemd::SystemdConfig,
+        Deployment,
+    },
+    Piosphere,
+};
+use piosphere_testkit::{fake::Commands, TestServer};
+use x509_parser::pem::parse_x509_pem;
+
+async fn piosphere(root: &std::path::Path) -> Piosphere {
+    TestServer::builder(root, &Commands::default())
+        .data_dir("/var/lib/piosphere")
+        .build()
+        .await
+        .unwrap()
+}
+
+fn deployment() -> Deployment {
+    let server = NginxServer {
+        listen: vec![Listen {
+            ssl: true,
+            ..Listen::new("443".parse().unwrap())
+        }],
+        server_name: vec![
+            "app.staging.test".parse().unwrap(),
+            "*.app.staging.test".parse().unwrap(),
+        ],
+        tls: Some(Tls {
+            issuer: Some(Issuer::Local),
+            ..Tls::new("", "")
+        }),
+        ..Default::default()
+    };
+
+    Deployment::new(
+        "app",
+        "app deployment",
+        NginxConfig {
+            file_location: "/etc/nginx/sites-enabled/app".to_string(),
+            servers: vec![server],
+            ..Default::default()
+        },
+        SystemdConfig {
+            file_location: "/etc/systemd/system/app.service".to_string(),
+            ..Default::default()
+        },
+    )
+}
+
+#[tokio::test]
+async fn local_certificates_are_issued_on_create() {
+    let root = tempfile::tempdir().unwrap();
+    let piosphere = piosphere(root.path()).await;
+
+    let created = piosphere.create_deployment(deployment()).await.unwrap();
+
+    let vhost = std::fs::read_to_string(root.path().join("etc/nginx/sites-enabled/app")).unwrap();
+    assert!(
+        vhost.contains(
+            "ssl_certificate /var/lib/piosphere/ca/certificates/app.staging.test/fullchain.pem;\n  \
+             ssl_certificate_key /var/lib/piosphere/ca/certificates/app.staging.test/privkey.pem;"
+        ),
+        "{vhost}"
+    );
+    // Local certificates need no challenge, so HTTP is not redirected
+    assert!(!vhost.contains("listen 80"), "{vhost}");
+
+    let certificates = root
+        .path()
+        .join("var/lib/piosphere/ca/certificates/app.staging.test");
+    let info = CertificateInfo::read(&certificates.join("fullchain.pem")).unwrap();
+    assert_eq!(info.names, ["app.staging.test", "*.app.staging.test"]);
+    assert!(info.not_after > chrono::Utc::now().naive_utc() + chrono::Duration::days(300));
+
+    for key in ["ca/certificates/app.staging.test/privkey.pem", "ca/ca.key"] {
+        let mode = std::fs::metadata(root.path().join("var/lib/piosphere").join(key))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600, "{key}");
+    }
+
+    // The chain ends with the CA, which signed the certificate
+    let ca = piosphere.export_ca_certificate().await.unwrap();
+    assert_eq!(ca.location, "/var/lib/piosphere/ca/ca.pem");
+    assert_eq!(ca.fingerprint.len(), 32 * 3 - 1);
+
+    let chain = std::fs::read_to_string(certificates.join("fullchain.pem")).unwrap();
+    assert!(chain.ends_with(&ca.pem), "{chain}");
+
+    let (_, leaf) = parse_x509_pem(chain.as_bytes()).unwrap();
+    let leaf = leaf.parse_x509().unwrap();
+    let (_, ca_pem) = parse_x509_pem(ca.pem.as_bytes()).unwrap();
+    let ca_certificate = ca_pem.parse_x509().unwrap();
+    assert_eq!(leaf.issuer(), ca_certificate.subject());
+    assert!(ca_certificate.is_ca());
+
+    // The issuer is found again in the written vhost
+    let viewed = piosphere.view_deployment(&created.id).await.unwrap();
+    let tls = viewed.nginx_cfgs[0].servers[0].tls.clone().unwrap();
+    assert_eq!(tls.issuer, Some(Issuer::Local));
+
+    // Nothing is due until the certificate expires soon
+    assert!(piosphere.renew_certificates().await.unwrap().is_empty());
+
+    // The CA is kept, so machines trusting it keep trusting the renewed certificates
+    std::fs::remove_file(certificates.join("fullchain.pem")).unwrap();
+    let renewed = piosphere.renew_certificates().await.unwrap();
+    assert_eq!(renewed.len(), 1);
+    assert_eq!(renewed[0].issuer, Issuer::Local);
+    assert_eq!(piosphere.export_ca_certificate().await.unwrap().pem, ca.pem);
+}
@@ -0,0 +1,125 @@
+use piosphere::{
+    backend::{DryRun, ServiceAction},
+    batch::BatchResult,
+    db::{self, MEMORY},
+    deployment::Deployment,
+    site::SiteUpload,
+    socket::{
+        client::BlockingClient,
+        message::{schema, Batch, Hello, KillSession, Overview, RenewCertificates, ViewDeployment},
+        Message, PiosphereRequest, PiosphereTag,
+    },
+    Piosphere, PiosphereResult,
+};
+
+#[test]
+fn messages_are_tagged_with_their_variant() {
+    assert_eq!(Hello.tag(), PiosphereTag::Hello);
+    assert_eq!(
+        ViewDeployment("id".to_string()).tag(),
+        PiosphereTag::ViewDeployment
+    );
+    assert_eq!(Batch::default().tag(), PiosphereTag::Batch);
+
+    let request = KillSession(3).to_request().unwrap();
+    assert_eq!(request.tag, PiosphereTag::KillSession);
+    assert_eq!(request.message, bincode::serialize(&3usize).unwrap());
+}
+
+#[test]
+fn tags_are_snake_cased_methods() {
+    for (tag, method) in [
+        (PiosphereTag::Hello, "hello"),
+        (PiosphereTag::ViewDeployment, "view_deployment"),
+        (PiosphereTag::ExportCaCertificate, "export_ca_certificate"),
+    ] {
+        assert_eq!(tag.method(), method);
+        assert_eq!(serde_json::to_value(tag).unwrap(), method);
+    }
+}
+
+#[test]
+fn admin_messages_are_flagged() {
+    assert!(KillSession(0).tag().admin_only());
+    assert!(RenewCertificates.tag().admin_only());
+    assert!(!Overview.tag().admin_only());
+    assert!(!Batch::default().tag().admin_only());
+}
+
+#[tokio::test]
+async fn requests_are_dispatched_by_their_tag() {
+    let root = tempfile::tempdir().unwrap();
+    let piosphere = Piosphere::builder()
+        .db(MEMORY)
+        .root(root.path())
+        .service_manager(DryRun)
+        .nginx(DryRun)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(piosphere.request(Overview).await.unwrap().is_empty());
+
+    // The message is decoded as the one its tag names
+    let mismatched = PiosphereRequest {
+        tag: PiosphereTag::ViewDeployment,
+        message: bincode::serialize(&Hello).unwrap(),
+    };
+    let mut batch = Batch::default().push(Hello).unwrap();
+    batch.requests.push(mismatched);
+
+    let response = piosphere.request(batch).await.unwrap();
+    assert!(matches!(
+        response.results[..],
+        [BatchResult::Ok(_), BatchResult::Err(_)]
+    ));
+}
+
+#[test]
+fn schemas_describe_the_messages() {
+    let schemas = schema();
+
+    let view = schemas
+        .iter()
+        .find(|schema| schema.method == "view_deployment")
+        .unwrap();
+    assert!(!view.admin);
+    assert_eq!(
+        serde_json::to_value(&view.params).unwrap()["type"],
+        "string"
+    );
+
+    let delete = schemas
+        .iter()
+        .find(|schema| schema.method == "delete_deployment")
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&delete.result).unwrap()["type"],
+        "boolean"
+    );
+
+    let kill = schemas
+        .iter()
+        .find(|schema| schema.method == "kill_session")
+        .unwrap();
+    assert!(kill.admin);
+
+    assert_eq!(schemas[0].method, "hello");
+}
+
+/// Parameters are named after `#[param]` or their type and strings are borrowed.
+#[test]
+fn methods_take_the_message_fields() {
+    let _: fn(&mut BlockingClient, &str) -> PiosphereResult<Deployment> =
+        BlockingClient::view_deployment;
+    let _: fn(&mut BlockingClient, &str) -> PiosphereResult<bool> =
+        BlockingClient::delete_deployment;
+    let _: fn(&mut BlockingClient, usize) -> PiosphereResult<bool> = BlockingClient::kill_session;
+    let _: fn(&mut BlockingClient, Deployment) -> PiosphereResult<db::Deployment> =
+        BlockingClient::create_deployment;
+    let _ = |client: &mut BlockingClient| -> PiosphereResult<Vec<String>> {
+        client.control_deployment("id", ServiceAction::Start, Some("api.service"))
+    };
+    let _: fn(&mut BlockingClient, SiteUpload) -> PiosphereResult<usize> =
+        BlockingClient::upload_site;
+}
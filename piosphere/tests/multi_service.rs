@@ -0,0 +1,223 @@
+use piosphere::{
+    backend::ServiceAction,
+    deployment::{
+        nginx::{NginxConfig, NginxLocation, NginxServer},
+        systemd::SystemdConfig,
+        Deployment,
+    },
+};
+use piosphere_testkit::{
+    fake::{Command, Commands},
+    TestServer,
+};
+
+/// The units controlled since the last call, e.g. `stop api.service`, without the reloads.
+fn controlled(commands: &Commands) -> Vec<String> {
+    commands
+        .take()
+        .into_iter()
+        .filter_map(|command| match command {
+            Command::SystemdControl(action, unit) => Some(format!("{action} {unit}")),
+            _ => None,
+        })
+        .collect()
+}
+
+fn unit(name: &str) -> SystemdConfig {
+    SystemdConfig {
+        file_location: format!("/etc/systemd/system/{name}"),
+        ..Default::default()
+    }
+}
+
+fn vhost(name: &str) -> NginxConfig {
+    NginxConfig {
+        file_location: format!("/etc/nginx/sites-enabled/{name}"),
+        servers: vec![NginxServer {
+            server_name: vec![name.parse().unwrap()],
+            location: vec![NginxLocation::new()],
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+fn shop() -> Deployment {
+    let mut deployment = Deployment::site("shop", "shop deployment", vhost("shop.test"));
+    deployment.nginx_cfgs.push(vhost("admin.shop.test"));
+    deployment.service_cfgs = vec![unit("api.service"), unit("worker.service")];
+    deployment
+}
+
+#[tokio::test]
+async fn deployments_own_several_units_and_vhosts() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(piosphere.lint_deployment(shop()).await.unwrap().is_empty());
+    let created = piosphere.create_deployment(shop()).await.unwrap();
+
+    for file in [
+        "etc/systemd/system/api.service",
+        "etc/systemd/system/worker.service",
+        "etc/nginx/sites-enabled/shop.test",
+        "etc/nginx/sites-enabled/admin.shop.test",
+    ] {
+        assert!(root.path().join(file).exists(), "{file}");
+    }
+
+    let stats = piosphere.server_info().await.unwrap().db;
+    assert_eq!((stats.nginx_configs, stats.sysd_configs), (2, 2));
+
+    let viewed = piosphere.view_deployment(&created.id).await.unwrap();
+    let units: Vec<_> = viewed.service_cfgs.iter().map(|c| c.unit_name()).collect();
+    assert_eq!(units, ["api.service", "worker.service"]);
+    let vhosts: Vec<_> = viewed.nginx_cfgs.iter().map(|c| c.name()).collect();
+    assert_eq!(vhosts, ["shop.test", "admin.shop.test"]);
+
+    // Removing a unit removes it from the deployment, the others keep their order
+    let mut updated = shop();
+    updated.id = created.id.clone();
+    updated.service_cfgs.remove(0);
+    updated.service_cfgs.push(unit("mailer.service"));
+    piosphere.update_deployment(updated).await.unwrap();
+
+    let viewed = piosphere.view_deployment(&created.id).await.unwrap();
+    let units: Vec<_> = viewed.service_cfgs.iter().map(|c| c.unit_name()).collect();
+    assert_eq!(units, ["worker.service", "mailer.service"]);
+
+    let stats = piosphere.server_info().await.unwrap().db;
+    assert_eq!((stats.nginx_configs, stats.sysd_configs), (2, 2));
+
+    // The dropped unit is stopped and its file removed
+    assert_eq!(controlled(&commands), ["stop api.service"]);
+    assert!(!root.path().join("etc/systemd/system/api.service").exists());
+    assert!(root
+        .path()
+        .join("etc/systemd/system/mailer.service")
+        .exists());
+}
+
+#[tokio::test]
+async fn updates_remove_the_dropped_units_and_vhosts() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    let created = piosphere.create_deployment(shop()).await.unwrap();
+
+    let mut updated = Deployment::site("shop", "shop deployment", vhost("shop.test"));
+    updated.id = created.id.clone();
+    piosphere.update_deployment(updated).await.unwrap();
+
+    assert_eq!(
+        controlled(&commands),
+        ["stop worker.service", "stop api.service"]
+    );
+    for file in [
+        "etc/systemd/system/api.service",
+        "etc/systemd/system/worker.service",
+        "etc/nginx/sites-enabled/admin.shop.test",
+    ] {
+        assert!(!root.path().join(file).exists(), "{file}");
+    }
+    assert!(root
+        .path()
+        .join("etc/nginx/sites-enabled/shop.test")
+        .exists());
+
+    let viewed = piosphere.view_deployment(&created.id).await.unwrap();
+    assert!(viewed.service_cfgs.is_empty());
+    assert_eq!(viewed.nginx_cfgs.len(), 1);
+
+    // The vhost can be taken by another deployment
+    let blog = Deployment::site("blog", "blog deployment", vhost("admin.shop.test"));
+    piosphere.create_deployment(blog).await.unwrap();
+}
+
+#[tokio::test]
+async fn lifecycle_operations_apply_to_the_group_or_a_component() {
+    let root = tempfile::tempdir().unwrap();
+    let commands = Commands::default();
+    let piosphere = TestServer::builder(root.path(), &commands)
+        .build()
+        .await
+        .unwrap();
+
+    let id = piosphere.create_deployment(shop()).await.unwrap().id;
+
+    let started = piosphere
+        .control_deployment(&id, ServiceAction::Start, None)
+        .await
+        .unwrap();
+    assert_eq!(started, ["api.service", "worker.service"]);
+
+    // Stopped in reverse order
+    piosphere
+        .control_deployment(&id, ServiceAction::Stop, None)
+        .await
+        .unwrap();
+
+    let restarted = piosphere
+        .control_deployment(&id, ServiceAction::Restart, Some("worker.service"))
+        .await
+        .unwrap();
+    assert_eq!(restarted, ["worker.service"]);
+
+    assert_eq!(
+        controlled(&commands),
+        [
+            "start api.service",
+            "start worker.service",
+            "stop worker.service",
+            "stop api.service",
+            "restart worker.service",
+        ]
+    );
+
+    let e = piosphere
+        .control_deployment(&id, ServiceAction::Restart, Some("shop.test"))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Validation failed: component: `shop.test` is a vhost, only units can be started, \
+         stopped or restarted"
+    );
+
+    let e = piosphere
+        .control_deployment(&id, ServiceAction::Start, Some("api"))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Validation failed: component: the deployment has no `api`"
+    );
+    assert!(controlled(&commands).is_empty());
+}
+
+#[test]
+fn components_are_named_after_their_file() {
+    let mut deployment = shop();
+    deployment.service_cfgs.push(SystemdConfig {
+        file_location: "/lib/systemd/system/api.service".to_string(),
+        ..Default::default()
+    });
+    deployment.service_cfgs.push(unit("api"));
+    deployment.nginx_cfgs.push(vhost("shop.test"));
+
+    let e = deployment.validate().unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "Validation failed: nginx_cfgs[2].file_location: duplicate component `shop.test`; \
+         service_cfgs[2].file_location: duplicate component `api.service`; \
+         service_cfgs[3].file_location: unit name `api` is missing its type, e.g. `.service`"
+    );
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use common::changed_lines;
+
+use piosphere::deployment::{
+    nginx::{GzipStatic, NginxConfig, NginxLocation, NginxServer, StaticFiles},
+    Deployment,
+};
+
+fn static_site() -> (String, NginxConfig) {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/static_site.conf"
+    ))
+    .unwrap();
+    let config = NginxConfig::parse(&input).unwrap();
+    (input, config)
+}
+
+#[test]
+fn static_locations_are_mapped() {
+    let (input, config) = static_site();
+    assert_eq!(config.to_string(), input);
+
+    let [site, assets] = &config.servers[0].location[..] else {
+        panic!("expected two locations");
+    };
+
+    assert_eq!(
+        site.files,
+        Some(StaticFiles {
+            root: Some("/var/www/docs".to_string()),
+            index: vec!["index.html".to_string()],
+            try_files: vec![
+                "$uri".to_string(),
+                "$uri/".to_string(),
+                "/index.html".to_string()
+            ],
+            ..Default::default()
+        })
+    );
+    assert!(site.directives.is_empty());
+
+    let files = assets.files.as_ref().unwrap();
+    assert_eq!(files.alias.as_deref(), Some("/var/www/docs/assets/"));
+    assert_eq!(files.expires.as_deref(), Some("30d"));
+    assert_eq!(files.gzip_static, Some(GzipStatic::On));
+    assert_eq!(files.dir(), Some("/var/www/docs/assets/"));
+
+    // Not a files directive, kept as it is
+    assert_eq!(assets.directives[0].name, "add_header");
+}
+
+#[test]
+fn changing_a_setting_only_changes_its_line() {
+    let (input, mut config) = static_site();
+
+    let locations = &mut config.servers[0].location;
+    let site = locations[0].files.as_mut().unwrap();
+    site.try_files[2] = "=404".to_string();
+    let assets = locations[1].files.as_mut().unwrap();
+    assets.gzip_static = None;
+    assets.expires = Some("max".to_string());
+    let output = config.to_string();
+
+    assert_eq!(
+        changed_lines(&input, &output),
+        [
+            "        try_files $uri $uri/ =404;   # client side routing",
+            "        expires     max;",
+        ]
+    );
+    assert!(!output.contains("gzip_static"));
+    assert_eq!(NginxConfig::parse(&output).unwrap().to_string(), output);
+}
+
+#[test]
+fn added_settings_follow_the_parsed_ones() {
+    let (_, mut config) = static_site();
+
+    let assets = config.servers[0].location[1].files.as_mut().unwrap();
+    assets.try_files = vec!["$uri".to_string(), "=404".to_string()];
+    let output = config.to_string();
+
+    assert!(
+        output.contains(
+            "        gzip_static on;\n    try_files $uri =404;\n        add_header  Cache-Control"
+        ),
+        "{output}"
+    );
+    assert_eq!(NginxConfig::parse(&output).unwrap().to_string(), output);
+}
+
+#[test]
+fn new_static_locations() {
+    let server = NginxServer {
+        server_name: vec!["docs.example.org".parse().unwrap()],
+        location: vec![NginxLocation::serve(
+            "/",
+            StaticFiles {
+                gzip_static: Some(GzipStatic::Always),
+                ..StaticFiles::root("/var/www/my docs")
+            },
+        )],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        server.to_string(),
+        "server {\n  \
+           listen 80;\n  \
+           server_name docs.example.org;\n  \
+           location / {\n    \
+             root \"/var/www/my docs\";\n    \
+             index index.html;\n    \
+             try_files $uri $uri/ =404;\n    \
+             gzip_static always;\n  \
+           }\n\
+         }"
+    );
+
+    let config = NginxConfig {
+        servers: vec![server],
+        ..Default::default()
+    };
+    assert!(config.lint("nginx_cfg", &[]).is_empty());
+}
+
+#[test]
+fn static_locations_are_validated() {
+    let files = StaticFiles {
+        alias: Some("/var/www/docs/".to_string()),
+        try_files: vec!["$uri".to_string()],
+        expires: Some("1d; return 200".to_string()),
+        ..StaticFiles::root("")
+    };
+    let server = NginxServer {
+        server_name: vec!["docs.example.org".parse().unwrap()],
+        location: vec![NginxLocation::serve("/", files)],
+        ..Default::default()
+    };
+
+    let deployment = Deployment::site(
+        "docs",
+        "docs deployment",
+        NginxConfig {
+            servers: vec![server],
+            ..Default::default()
+        },
+    );
+
+    let e = deployment.validate().unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "Validation failed: nginx_cfgs[0].servers[0].location[0].files.alias: \
+         `root` and `alias` cannot be combined; \
+         nginx_cfgs[0].servers[0].location[0].files.root: root cannot be empty; \
+         nginx_cfgs[0].servers[0].location[0].files.try_files: \
+         at least a file and the fallback are required; \
+         nginx_cfgs[0].servers[0].location[0].files.expires: ';' is not allowed"
+    );
+
+    let e = NginxConfig::parse("server {\n  location / { gzip_static maybe; }\n}\n").unwrap_err();
+    assert_eq!((e.line, e.column), (2, 28));
+    assert!(
+        e.message.contains("expected `on`, `off` or `always`"),
+        "{e}"
+    );
+}
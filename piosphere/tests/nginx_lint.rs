@@ -0,0 +1,225 @@
+use piosphere::deployment::nginx::{
+    lint::{self, Diagnostic, Lint, Severity},
+    NginxConfig,
+};
+
+fn lint(input: &str) -> Vec<Diagnostic> {
+    NginxConfig::parse(input).unwrap().lint("nginx_cfg", &[])
+}
+
+/// The lint and field of each diagnostic.
+fn found(diagnostics: &[Diagnostic]) -> Vec<(Lint, &str)> {
+    diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.lint, diagnostic.field.as_str()))
+        .collect()
+}
+
+#[test]
+fn corpus_is_clean() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/nginx");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let input = std::fs::read_to_string(&path).unwrap();
+        let diagnostics = lint(&input);
+        assert!(
+            diagnostics.is_empty(),
+            "{}: {diagnostics:#?}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn duplicate_locations_are_errors() {
+    let diagnostics = lint(
+        "server {
+            listen 80;
+            server_name example.org;
+            location / { proxy_pass http://127.0.0.1:8000; }
+            location ^~ / { proxy_pass http://127.0.0.1:8000; }
+            location = / { return 204; }
+            location ~ \\.php$ { fastcgi_pass unix:/run/php.sock; }
+            location ~ \\.php$ { fastcgi_pass unix:/run/php.sock; }
+        }",
+    );
+
+    assert_eq!(
+        found(&diagnostics),
+        [
+            (Lint::DuplicateLocation, "nginx_cfg.servers[0].location[1]"),
+            (
+                Lint::UnreachableLocation,
+                "nginx_cfg.servers[0].location[4]"
+            ),
+        ]
+    );
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(
+        diagnostics[0].message,
+        "duplicate location `^~ /`, already defined by location[0]"
+    );
+
+    let e = lint::deny_errors(&diagnostics).unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "Validation failed: nginx_cfg.servers[0].location[1]: duplicate location `^~ /`, \
+         already defined by location[0], nginx refuses to load the file, merge the two blocks"
+    );
+}
+
+#[test]
+fn locations_without_a_handler_are_reported() {
+    let diagnostics = lint(
+        "server {
+            listen 80;
+            server_name example.org;
+            location / { proxy_set_header Host $host; }
+            location /static/ { alias /srv/static/; }
+            location @app { proxy_pass http://127.0.0.1:8000; }
+        }",
+    );
+    assert_eq!(
+        found(&diagnostics),
+        [(Lint::MissingProxyPass, "nginx_cfg.servers[0].location[0]")]
+    );
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert!(lint::deny_errors(&diagnostics).is_ok());
+
+    // The locations inherit the root of the server
+    let diagnostics = lint("server { listen 80; root /srv/www; location / { expires 1h; } }");
+    assert!(diagnostics.is_empty(), "{diagnostics:#?}");
+}
+
+#[test]
+fn server_names_conflict_on_the_same_port() {
+    let config = NginxConfig::parse(
+        "server { listen 80; server_name example.org www.example.org; return 301 https://example.org; }
+         server { listen 443; server_name example.org; root /srv; }
+         server { listen 80; server_name WWW.example.org; root /srv; }",
+    )
+    .unwrap();
+    let blog =
+        NginxConfig::parse("server { listen 443; server_name example.org; root /srv; }").unwrap();
+
+    let diagnostics = config.lint("nginx_cfg", &[("blog", &blog)]);
+    assert_eq!(
+        found(&diagnostics),
+        [
+            (
+                Lint::ConflictingServerName,
+                "nginx_cfg.servers[1].server_name[0]"
+            ),
+            (
+                Lint::ConflictingServerName,
+                "nginx_cfg.servers[2].server_name[0]"
+            ),
+        ]
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "`example.org` on `443` is already served by deployment `blog`"
+    );
+    assert_eq!(
+        diagnostics[1].message,
+        "`WWW.example.org` on `80` is already served by servers[0]"
+    );
+}
+
+#[test]
+fn unreachable_locations_are_reported() {
+    let diagnostics = lint(
+        "server {
+            listen 80;
+            server_name example.org;
+            location / { root /srv; }
+            location images/ { root /srv; }
+            location /img/ { root /srv/img; }
+            location ~* ^/img/ { root /srv/cache; }
+            location ^~ /assets/ { root /srv/assets; }
+            location ~ ^/assets/ { root /srv/never; }
+        }",
+    );
+
+    assert_eq!(
+        found(&diagnostics),
+        [
+            (
+                Lint::UnreachableLocation,
+                "nginx_cfg.servers[0].location[1]"
+            ),
+            (
+                Lint::UnreachableLocation,
+                "nginx_cfg.servers[0].location[2]"
+            ),
+        ]
+    );
+    assert_eq!(diagnostics[0].help.as_deref(), Some("use `/images/`"));
+    assert_eq!(
+        diagnostics[1].message,
+        "location `/img/` is never used, requests below `/img/` go to location `~* ^/img/` \
+         of location[3]"
+    );
+    assert_eq!(
+        diagnostics[1].help.as_deref(),
+        Some("use `location ^~ /img/` to take precedence over regexes")
+    );
+
+    let diagnostics =
+        lint("server { listen 80; return 301 https://example.org; location / { root /srv; } }");
+    assert_eq!(
+        found(&diagnostics),
+        [(
+            Lint::UnreachableLocation,
+            "nginx_cfg.servers[0].directives[0]"
+        )]
+    );
+}
+
+#[test]
+fn proxy_pass_uris_are_checked() {
+    let diagnostics = lint(
+        "server {
+            listen 80;
+            server_name example.org;
+            location /api { proxy_pass http://127.0.0.1:8000/; }
+            location /v1/ { proxy_pass http://127.0.0.1:8000/v1; }
+            location /v2/ { proxy_pass http://unix:/run/app.sock:/v2/; }
+            location /v3 { proxy_pass http://127.0.0.1:8000; }
+            location ~ ^/v4/ { proxy_pass http://127.0.0.1:8000/v4/; }
+            location ~ ^/v5/ { proxy_pass http://127.0.0.1:8000/$1; }
+            location @app { proxy_pass http://app/; }
+        }",
+    );
+
+    assert_eq!(
+        found(&diagnostics),
+        [
+            (
+                Lint::TrailingSlash,
+                "nginx_cfg.servers[0].location[0].proxy_pass"
+            ),
+            (
+                Lint::TrailingSlash,
+                "nginx_cfg.servers[0].location[1].proxy_pass"
+            ),
+            (
+                Lint::ProxyPassUri,
+                "nginx_cfg.servers[0].location[4].proxy_pass"
+            ),
+            (
+                Lint::ProxyPassUri,
+                "nginx_cfg.servers[0].location[6].proxy_pass"
+            ),
+        ]
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "location `/api` replaces `/api` with `/`, `/api/users` is passed as `//users`"
+    );
+    assert_eq!(
+        diagnostics[1].message,
+        "location `/v1/` replaces `/v1/` with `/v1`, `/v1/users` is passed as `/v1users`"
+    );
+    assert_eq!(diagnostics[2].severity, Severity::Error);
+}
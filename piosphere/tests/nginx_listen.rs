@@ -0,0 +1,158 @@
+use piosphere::deployment::{
+    nginx::{route::Request, Listen, ListenAddress, NginxConfig},
+    Deployment,
+};
+
+const CONFIG: &str = "server {
+    listen 80;
+    listen [::]:443 ssl http2 default_server;
+    listen 127.0.0.1:8080 proxy_protocol;
+    listen unix:/run/nginx/app.sock;
+    listen *:8443 default reuseport backlog=511;
+    listen [::1];
+    server_name example.org;
+}
+";
+
+fn listens() -> Vec<Listen> {
+    let mut config = NginxConfig::parse(CONFIG).unwrap();
+    config.servers.remove(0).listen
+}
+
+#[test]
+fn listen_directives_are_typed() {
+    let listen = listens();
+
+    assert_eq!(listen.len(), 6);
+    assert_eq!(listen[0], Listen::new("80".parse().unwrap()));
+
+    let ipv6 = &listen[1];
+    assert_eq!(
+        ipv6.address,
+        ListenAddress::Inet {
+            host: Some("[::]".to_string()),
+            port: "443".parse().unwrap(),
+        }
+    );
+    assert!(ipv6.ssl && ipv6.http2 && ipv6.default_server);
+    assert!(!ipv6.proxy_protocol && !ipv6.reuseport);
+
+    assert!(listen[2].proxy_protocol);
+    assert_eq!(listen[2].port().unwrap().get(), 8080);
+
+    assert_eq!(
+        listen[3].address,
+        ListenAddress::Unix("/run/nginx/app.sock".to_string())
+    );
+    assert_eq!(listen[3].port(), None);
+
+    let wildcard = &listen[4];
+    assert_eq!(
+        wildcard.address,
+        ListenAddress::Inet {
+            host: None,
+            port: "8443".parse().unwrap(),
+        }
+    );
+    assert!(wildcard.default_server && wildcard.reuseport);
+    assert_eq!(wildcard.params, ["backlog=511"]);
+
+    assert_eq!(listen[5].port().unwrap().get(), 80);
+    assert_eq!(listen[5].address.to_string(), "[::1]:80");
+}
+
+#[test]
+fn listen_directives_render_as_written() {
+    let config = NginxConfig::parse(CONFIG).unwrap();
+    assert_eq!(config.to_string(), CONFIG);
+
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(json.contains(r#""address":"[::]:443""#), "{json}");
+    let config: NginxConfig = serde_json::from_str(&json).unwrap();
+    assert_eq!(config.to_string(), CONFIG);
+
+    let bytes = bincode::serialize(&config).unwrap();
+    let config: NginxConfig = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(config.to_string(), CONFIG);
+}
+
+#[test]
+fn changed_listens_keep_the_order_of_their_arguments() {
+    let mut config = NginxConfig::parse(CONFIG).unwrap();
+    let listen = &mut config.servers[0].listen;
+
+    listen[1].http2 = false;
+    listen[1].proxy_protocol = true;
+    listen[4].default_server = false;
+    listen[4].params.push("fastopen=256".to_string());
+    listen.remove(5);
+    listen.push(Listen {
+        ssl: true,
+        ..Listen::new("8444".parse().unwrap())
+    });
+
+    assert_eq!(
+        config.to_string(),
+        "server {
+    listen 80;
+    listen [::]:443 ssl default_server proxy_protocol;
+    listen 127.0.0.1:8080 proxy_protocol;
+    listen unix:/run/nginx/app.sock;
+    listen *:8443 reuseport backlog=511 fastopen=256;
+    listen 8444 ssl;
+    server_name example.org;
+}
+"
+    );
+}
+
+#[test]
+fn default_servers_are_per_port() {
+    let config = NginxConfig::parse(
+        "server { listen 80; listen 443 ssl default_server; server_name a.test; }
+         server { listen 80 default_server; listen 443 ssl; server_name b.test; }
+         server { server_name c.test; }",
+    )
+    .unwrap();
+
+    let server = |url: &str| {
+        let request: Request = url.parse().unwrap();
+        config.route(&request).unwrap().server.server_name[0].to_string()
+    };
+
+    assert_eq!(server("http://unknown.test/"), "b.test");
+    assert_eq!(server("https://unknown.test/"), "a.test");
+    // Without `listen`, nginx listens on port 80
+    assert_eq!(server("http://c.test/"), "c.test");
+}
+
+#[test]
+fn invalid_listens_are_rejected() {
+    for (input, column, error) in [
+        ("listen 0;", 8, "port must be between 1 and 65535"),
+        ("listen [::1:80;", 8, "invalid listen address `[::1`"),
+        ("listen unix:run.sock;", 8, "must be absolute"),
+        ("listen;", 1, "expected an address or a port"),
+    ] {
+        let config = format!("server {{\n{input}\n}}\n");
+        let e = NginxConfig::parse(&config).unwrap_err();
+        assert_eq!((e.line, e.column), (2, column), "{input}");
+        assert!(e.message.contains(error), "{input}: {e}");
+    }
+
+    let mut deployment = Deployment {
+        name: "app".to_string(),
+        nginx_cfgs: vec![NginxConfig::parse(CONFIG).unwrap()],
+        ..Default::default()
+    };
+    let listen = &mut deployment.nginx_cfgs[0].servers[0].listen;
+    listen[0].params.push("ssl".to_string());
+    listen[1].params.push("so;rcvbuf".to_string());
+
+    let e = deployment.validate().unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "Validation failed: nginx_cfgs[0].servers[0].listen[0].params[0]: `ssl` has a field of its own; \
+         nginx_cfgs[0].servers[0].listen[1].params[0]: invalid parameter `so;rcvbuf`"
+    );
+}
@@ -0,0 +1,190 @@
+//! Rendering a parsed vhost must reproduce the file, and changing a value must only
+//! change the directive it belongs to.
+
+mod common;
+
+use common::changed_lines;
+
+use std::path::PathBuf;
+
+use piosphere::deployment::nginx::{
+    ast::Directive, Listen, NginxConfig, NginxLocation, NginxServer,
+};
+
+fn corpus() -> Vec<(String, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/nginx");
+
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .expect("could not read the corpus")
+        .map(|entry| entry.expect("could not read the corpus").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let contents = std::fs::read_to_string(&path).expect("could not read config");
+            (name, contents)
+        })
+        .collect();
+
+    files.sort();
+    assert!(!files.is_empty(), "empty corpus in {}", dir.display());
+    files
+}
+
+fn parse(input: &str) -> NginxConfig {
+    NginxConfig::parse(input).unwrap_or_else(|e| panic!("{e}\n{input}"))
+}
+
+#[test]
+fn corpus_round_trips() {
+    for (name, contents) in corpus() {
+        assert_eq!(parse(&contents).to_string(), contents, "rendering {name}");
+    }
+}
+
+#[test]
+fn corpus_round_trips_through_json() {
+    for (name, contents) in corpus() {
+        let json = serde_json::to_string(&parse(&contents)).unwrap();
+        let config: NginxConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(config.to_string(), contents, "rendering {name}");
+    }
+}
+
+#[test]
+fn corpus_round_trips_through_bincode() {
+    for (name, contents) in corpus() {
+        let bytes = bincode::serialize(&parse(&contents)).unwrap();
+        let config: NginxConfig = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(config.to_string(), contents, "rendering {name}");
+    }
+}
+
+#[test]
+fn changing_listen_keeps_its_parameters() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/php_fpm.conf"
+    ))
+    .unwrap();
+
+    let mut config = parse(&input);
+    config.servers[0].listen[0].address = "9090".parse().unwrap();
+    let output = config.to_string();
+
+    assert_eq!(
+        changed_lines(&input, &output),
+        ["\tlisten 9090 default_server;"]
+    );
+    assert_eq!(output.lines().count(), input.lines().count());
+}
+
+#[test]
+fn changing_server_names_keeps_comments() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/upstream_pool.conf"
+    ))
+    .unwrap();
+
+    let mut config = parse(&input);
+    config.servers[0].server_name[1] = "api.vpn".parse().unwrap();
+    let output = config.to_string();
+
+    assert_eq!(
+        changed_lines(&input, &output),
+        ["    server_name api.vpn;      # for the VPN"]
+    );
+}
+
+#[test]
+fn changing_proxy_pass_keeps_its_position() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/reverse_proxy.conf"
+    ))
+    .unwrap();
+
+    let mut config = parse(&input);
+    config.servers[0].location[0].proxy_pass = Some("http://127.0.0.1:4000".parse().unwrap());
+    let output = config.to_string();
+
+    assert_eq!(
+        changed_lines(&input, &output),
+        ["        proxy_pass         http://127.0.0.1:4000;"]
+    );
+}
+
+#[test]
+fn added_directives_are_indented() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/comments.conf"
+    ))
+    .unwrap();
+
+    let mut config = parse(&input);
+    let server = &mut config.servers[0];
+    server
+        .directives
+        .push(Directive::new("client_max_body_size", &["1m"]));
+    server.location.push(NginxLocation::new());
+    let output = config.to_string();
+
+    assert!(output.starts_with("## Vhost for the docs site\n"));
+    assert!(output.ends_with("\n}\n\n# end of file\n"));
+    assert!(output.contains("\n  client_max_body_size 1m;\n  location / {\n    proxy_pass "));
+    assert_eq!(parse(&output).to_string(), output);
+}
+
+#[test]
+fn new_configs_use_the_default_layout() {
+    let server = NginxServer {
+        server_name: vec!["example.org".parse().unwrap()],
+        access_log: Some("/var/log/nginx/example.log".to_string()),
+        ..Default::default()
+    };
+    let redirect = NginxServer {
+        listen: vec![Listen::new("8080".parse().unwrap())],
+        server_name: vec!["www.example.org".parse().unwrap()],
+        directives: vec![Directive::new(
+            "return",
+            &["301", "http://example.org$request_uri"],
+        )],
+        ..Default::default()
+    };
+    let config = NginxConfig {
+        servers: vec![server, redirect],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        config.to_string(),
+        "server {\n  listen 80;\n  server_name example.org;\n  access_log /var/log/nginx/example.log;\n}\n\
+         server {\n  listen 8080;\n  server_name www.example.org;\n  return 301 http://example.org$request_uri;\n}\n"
+    );
+}
+
+#[test]
+fn every_server_block_is_mapped() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/redirect_www.conf"
+    ))
+    .unwrap();
+
+    let mut config = parse(&input);
+    let names: Vec<_> = config
+        .servers
+        .iter()
+        .map(|server| server.server_name[0].to_string())
+        .collect();
+    assert_eq!(names, ["www.example.com", "example.com", "example.com"]);
+    assert_eq!(config.global.len(), 1);
+
+    config.servers.remove(0);
+    let output = config.to_string();
+    // The comment above the block goes with it
+    assert!(!output.contains("# Redirect the www subdomain"));
+    assert!(!output.contains("www.example.com"));
+    assert_eq!(parse(&output).servers.len(), 2);
+}
@@ -0,0 +1,212 @@
+use piosphere::deployment::nginx::{
+    route::{Request, ServerMatch},
+    Modifier, NginxConfig, NginxLocation,
+};
+
+const CONFIG: &str = r#"
+server {
+    listen 80;
+    server_name example.org www.example.org;
+
+    location / { return 200 root; }
+    location = / { return 200 exact; }
+    location /static/ { root /srv; }
+    location ^~ /static/vendor/ { root /srv/vendor; }
+    location ~* \.(png|jpe?g)$ { expires 30d; }
+    location ~ ^/api/v[0-9]+/ { proxy_pass http://127.0.0.1:8000; }
+    location /api/ { proxy_pass http://127.0.0.1:9000; }
+    location "/with space/" { return 204; }
+    location @fallback { proxy_pass http://127.0.0.1:7000; }
+}
+
+server {
+    listen 80;
+    server_name *.example.org;
+    location / { return 301 https://example.org$request_uri; }
+}
+
+server {
+    listen 80;
+    server_name *.cdn.example.org ~^img[0-9]+\.example\.net$;
+    location / { root /srv/cdn; }
+}
+
+server {
+    listen 80 default_server;
+    server_name _;
+    return 444;
+}
+
+server {
+    listen 443 ssl;
+    server_name example.org;
+    location ~ (?<=/)secret { deny all; }
+    location / { proxy_pass http://127.0.0.1:8443; }
+}
+"#;
+
+fn config() -> NginxConfig {
+    NginxConfig::parse(CONFIG).unwrap()
+}
+
+fn request(url: &str) -> Request {
+    url.parse().unwrap()
+}
+
+/// The first server name and location path of the route.
+fn route(config: &NginxConfig, url: &str) -> (String, ServerMatch, Option<String>) {
+    let route = config
+        .route(&request(url))
+        .unwrap_or_else(|| panic!("no route for {url}"));
+
+    (
+        route.server.server_name[0].to_string(),
+        route.server_match,
+        route
+            .location
+            .map(|location| format!("{}{}", location.modifier, location.path)),
+    )
+}
+
+#[test]
+fn modifiers_are_parsed() {
+    let config = config();
+    let locations: Vec<_> = config.servers[0]
+        .location
+        .iter()
+        .map(|location| (location.modifier, location.path.as_str()))
+        .collect();
+
+    assert_eq!(
+        locations,
+        [
+            (Modifier::Prefix, "/"),
+            (Modifier::Exact, "/"),
+            (Modifier::Prefix, "/static/"),
+            (Modifier::PreferPrefix, "/static/vendor/"),
+            (Modifier::RegexCaseless, r"\.(png|jpe?g)$"),
+            (Modifier::Regex, "^/api/v[0-9]+/"),
+            (Modifier::Prefix, "/api/"),
+            (Modifier::Prefix, "/with space/"),
+            (Modifier::Prefix, "@fallback"),
+        ]
+    );
+    assert!(config.servers[0].location[8].is_named());
+}
+
+#[test]
+fn modifiers_can_be_attached_to_the_path() {
+    let input = "server { listen 80; location =/x {} location ~*\\.gif$ {} }";
+    let config = NginxConfig::parse(input).unwrap();
+    let location = &config.servers[0].location;
+
+    assert_eq!(
+        (location[0].modifier, location[0].path.as_str()),
+        (Modifier::Exact, "/x")
+    );
+    assert_eq!(
+        (location[1].modifier, location[1].path.as_str()),
+        (Modifier::RegexCaseless, "\\.gif$")
+    );
+    assert_eq!(config.to_string(), input);
+}
+
+#[test]
+fn changed_locations_are_rendered_with_their_modifier() {
+    let mut config = config();
+    let server = &mut config.servers[0];
+
+    server.location[2].modifier = Modifier::PreferPrefix;
+    server.location[7].path = "/with \"quotes\"/".to_string();
+
+    let mut location = NginxLocation::new();
+    location.modifier = Modifier::RegexCaseless;
+    location.path = r"\.php$".to_string();
+    location.directives.clear();
+    server.location.push(location);
+
+    let output = config.to_string();
+    assert!(output.contains("    location ^~ /static/ { root /srv; }\n"));
+    assert!(output.contains(r#"    location "/with \"quotes\"/" { return 204; }"#));
+    assert!(
+        output.contains("  location ~* \\.php$ {\n    proxy_pass http://localhost:42069/;\n  }")
+    );
+
+    let reparsed = NginxConfig::parse(&output).unwrap();
+    assert_eq!(reparsed.servers[0].location[7].path, "/with \"quotes\"/");
+    assert_eq!(reparsed.servers[0].location[9].path, r"\.php$");
+}
+
+#[test]
+fn locations_are_picked_like_nginx() {
+    let config = config();
+
+    for (url, location) in [
+        ("http://example.org/", "=/"),
+        ("http://example.org/index.html", "/"),
+        ("http://example.org/static/app.js", "/static/"),
+        ("http://example.org/static/logo.PNG", r"~*\.(png|jpe?g)$"),
+        (
+            "http://example.org/static/vendor/logo.png",
+            "^~/static/vendor/",
+        ),
+        ("http://example.org/api/v2/users?page=2", "~^/api/v[0-9]+/"),
+        ("http://example.org/api/health", "/api/"),
+        ("http://example.org/with%20space/x", "/with space/"),
+        ("http://example.org//static/../api//v1/x", "~^/api/v[0-9]+/"),
+    ] {
+        let (_, _, picked) = route(&config, url);
+        assert_eq!(picked.as_deref(), Some(location), "{url}");
+    }
+}
+
+#[test]
+fn servers_are_picked_like_nginx() {
+    let config = config();
+
+    for (url, name, by) in [
+        ("http://Example.ORG./", "example.org", ServerMatch::Exact),
+        ("http://www.example.org/", "example.org", ServerMatch::Exact),
+        (
+            "http://blog.example.org/",
+            "*.example.org",
+            ServerMatch::LeadingWildcard,
+        ),
+        (
+            "http://a.cdn.example.org/",
+            "*.cdn.example.org",
+            ServerMatch::LeadingWildcard,
+        ),
+        (
+            "http://img12.example.net/",
+            "*.cdn.example.org",
+            ServerMatch::Regex,
+        ),
+        ("http://unknown.test/", "_", ServerMatch::Default),
+        ("https://example.org/", "example.org", ServerMatch::Exact),
+    ] {
+        let (picked, picked_by, _) = route(&config, url);
+        assert_eq!((picked.as_str(), picked_by), (name, by), "{url}");
+    }
+
+    let route = config.route(&request("https://example.org/")).unwrap();
+    assert_eq!(route.server.listen[0].port().unwrap().get(), 443);
+    assert!(config.route(&request("http://example.org:8080/")).is_none());
+}
+
+#[test]
+fn unsupported_regexes_are_reported() {
+    let config = config();
+    let route = config
+        .route(&request("https://example.org/secret"))
+        .unwrap();
+
+    assert_eq!(route.location.unwrap().path, "/");
+    assert_eq!(route.unsupported, ["location ~ (?<=/)secret"]);
+}
+
+#[test]
+fn invalid_modifiers_are_rejected() {
+    let e = NginxConfig::parse("server { location ~~ /x {} }").unwrap_err();
+    assert!(e.to_string().contains("unknown modifier `~~`"), "{e}");
+}
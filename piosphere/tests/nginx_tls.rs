@@ -0,0 +1,388 @@
+use chrono::NaiveDate;
+use piosphere::deployment::{
+    nginx::{CertificateInfo, Hsts, Issuer, Listen, NginxConfig, NginxServer, Tls, TlsProtocol},
+    Deployment,
+};
+use rcgen::{date_time_ymd, CertificateParams, KeyPair};
+
+const CONFIG: &str = "server {
+    listen 443 ssl;
+    server_name example.org;
+
+    ssl_certificate     \"/etc/ssl/example.org/fullchain.pem\";
+    ssl_certificate_key /etc/ssl/example.org/privkey.pem;
+    ssl_protocols TLSv1.2 TLSv1.3;
+    ssl_stapling on; # needs a resolver
+    ssl_stapling_verify on;
+    add_header Strict-Transport-Security \"max-age=63072000; includeSubDomains\" always;
+    add_header X-Frame-Options DENY;
+}
+";
+
+fn tls_server() -> NginxServer {
+    NginxServer {
+        listen: vec![Listen {
+            ssl: true,
+            ..Listen::new("443".parse().unwrap())
+        }],
+        server_name: vec!["example.org".parse().unwrap()],
+        tls: Some(Tls {
+            redirect: true,
+            ..Tls::new(
+                "/etc/ssl/example.org/fullchain.pem",
+                "/etc/ssl/example.org/privkey.pem",
+            )
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn tls_directives_are_typed() {
+    let config = NginxConfig::parse(CONFIG).unwrap();
+    let server = &config.servers[0];
+    let tls = server.tls.as_ref().unwrap();
+
+    assert_eq!(tls.certificate, "/etc/ssl/example.org/fullchain.pem");
+    assert_eq!(tls.certificate_key, "/etc/ssl/example.org/privkey.pem");
+    assert_eq!(tls.protocols, [TlsProtocol::Tls1_2, TlsProtocol::Tls1_3]);
+    assert_eq!(tls.ciphers, None);
+    assert!(tls.stapling);
+    assert_eq!(
+        tls.hsts,
+        Some(Hsts {
+            max_age: 63072000,
+            include_subdomains: true,
+            preload: false,
+        })
+    );
+    assert!(!tls.redirect);
+
+    // Other headers are left as they are
+    assert_eq!(server.directives.len(), 1);
+    assert_eq!(config.to_string(), CONFIG);
+
+    let json = serde_json::to_string(&config).unwrap();
+    let config: NginxConfig = serde_json::from_str(&json).unwrap();
+    assert_eq!(config.to_string(), CONFIG);
+
+    let bytes = bincode::serialize(&config).unwrap();
+    let config: NginxConfig = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(config.to_string(), CONFIG);
+}
+
+#[test]
+fn changed_tls_settings_keep_their_position() {
+    let mut config = NginxConfig::parse(CONFIG).unwrap();
+    let tls = config.servers[0].tls.as_mut().unwrap();
+
+    tls.certificate_key = "/etc/ssl/example.org/key.pem".to_string();
+    tls.protocols.clear();
+    tls.ciphers = Some("HIGH:!aNULL:!MD5".to_string());
+    tls.stapling = false;
+    tls.hsts.as_mut().unwrap().preload = true;
+
+    assert_eq!(
+        config.to_string(),
+        "server {
+    listen 443 ssl;
+    server_name example.org;
+
+    ssl_certificate     \"/etc/ssl/example.org/fullchain.pem\";
+    ssl_certificate_key /etc/ssl/example.org/key.pem;
+    add_header Strict-Transport-Security \"max-age=63072000; includeSubDomains; preload\" always;
+  ssl_ciphers HIGH:!aNULL:!MD5;
+    add_header X-Frame-Options DENY;
+}
+"
+    );
+
+    config.servers[0].tls = None;
+    assert_eq!(
+        config.to_string(),
+        "server {
+    listen 443 ssl;
+    server_name example.org;
+    add_header X-Frame-Options DENY;
+}
+"
+    );
+}
+
+#[test]
+fn tls_directives_without_certificate_are_not_managed() {
+    let config =
+        NginxConfig::parse("server { listen 443 ssl; server_name a.test; ssl_protocols TLSv1.3; }")
+            .unwrap();
+
+    assert!(config.servers[0].tls.is_none());
+    assert_eq!(config.servers[0].directives[0].name, "ssl_protocols");
+
+    let e = NginxConfig::parse(
+        "server {\n  ssl_certificate /a.pem;\n  ssl_protocols TLSv1.2 SSLv3;\n}\n",
+    )
+    .unwrap_err();
+    assert_eq!((e.line, e.column), (3, 25));
+    assert!(e.message.contains("unsupported protocol `SSLv3`"), "{e}");
+}
+
+#[test]
+fn http_is_redirected_to_https() {
+    let mut config = NginxConfig {
+        servers: vec![tls_server()],
+        ..Default::default()
+    };
+
+    assert_eq!(config.add_https_redirects(), 1);
+    assert_eq!(config.add_https_redirects(), 0);
+
+    let expected = "server {
+  listen 443 ssl;
+  server_name example.org;
+  ssl_certificate /etc/ssl/example.org/fullchain.pem;
+  ssl_certificate_key /etc/ssl/example.org/privkey.pem;
+}
+server {
+  listen 80;
+  server_name example.org;
+  location / {
+    return 301 https://$host$request_uri;
+  }
+}
+";
+    assert_eq!(config.to_string(), expected);
+
+    let parsed = NginxConfig::parse(expected).unwrap();
+    assert!(parsed.servers[0].tls.as_ref().unwrap().redirect);
+
+    // The redirect keeps the port of the TLS server
+    let mut server = tls_server();
+    server.listen[0].address = "[::]:8443".parse().unwrap();
+    let mut config = NginxConfig {
+        servers: vec![server],
+        ..Default::default()
+    };
+    config.add_https_redirects();
+
+    let redirect = config.servers[1].to_string();
+    assert!(
+        redirect.contains("listen 80;\n  listen [::]:80;"),
+        "{redirect}"
+    );
+    assert!(
+        redirect.contains("return 301 https://$host:8443$request_uri;"),
+        "{redirect}"
+    );
+}
+
+#[test]
+fn names_already_served_over_http_are_not_redirected() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/redirect_www.conf"
+    ))
+    .unwrap();
+    let mut config = NginxConfig::parse(&input).unwrap();
+
+    // The location `/` of the second server redirects `example.com`
+    assert!(config.servers[2].tls.as_ref().unwrap().redirect);
+    assert_eq!(config.add_https_redirects(), 0);
+
+    let mut server = tls_server();
+    server.server_name = vec!["app.test".parse().unwrap()];
+    let mut config = NginxConfig::parse(
+        "server { listen 80; server_name app.test; location / { proxy_pass http://127.0.0.1:8000; } }",
+    )
+    .unwrap();
+    config.servers.push(server);
+
+    assert_eq!(config.add_https_redirects(), 0);
+    let parsed = NginxConfig::parse(&config.to_string()).unwrap();
+    assert!(!parsed.servers[1].tls.as_ref().unwrap().redirect);
+}
+
+#[test]
+fn invalid_tls_settings_are_rejected() {
+    let mut server = tls_server();
+    server.listen[0].ssl = false;
+    let tls = server.tls.as_mut().unwrap();
+    tls.certificate_key = String::new();
+    tls.ciphers = Some("HIGH; return 200".to_string());
+    tls.hsts = Some(Hsts {
+        max_age: 300,
+        include_subdomains: false,
+        preload: true,
+    });
+
+    let deployment = Deployment {
+        name: "app".to_string(),
+        nginx_cfgs: vec![NginxConfig {
+            servers: vec![server],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let e = deployment.validate().unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "Validation failed: nginx_cfgs[0].servers[0].tls: TLS needs a `listen` with `ssl`; \
+         nginx_cfgs[0].servers[0].tls.certificate_key: certificate_key is required; \
+         nginx_cfgs[0].servers[0].tls.ciphers: ';' is not allowed; \
+         nginx_cfgs[0].servers[0].tls.hsts.include_subdomains: preload requires including the subdomains; \
+         nginx_cfgs[0].servers[0].tls.hsts.max_age: preload requires a max age of at least 31536000 seconds"
+    );
+}
+
+#[test]
+fn acme_challenges_are_served_over_http() {
+    let mut server = tls_server();
+    let tls = server.tls.as_mut().unwrap();
+    tls.redirect = false;
+    tls.issuer = Some(Issuer::Acme);
+
+    let mut config = NginxConfig {
+        servers: vec![server],
+        ..Default::default()
+    };
+
+    // Names issued by ACME are always redirected
+    assert_eq!(config.add_https_redirects(), 1);
+    assert_eq!(
+        config.serve_acme_challenges("/opt/piosphere/acme/challenges"),
+        1
+    );
+    assert_eq!(
+        config.serve_acme_challenges("/opt/piosphere/acme/challenges"),
+        0
+    );
+
+    assert_eq!(
+        config.servers[1].to_string(),
+        "server {
+  listen 80;
+  server_name example.org;
+  location / {
+    return 301 https://$host$request_uri;
+  }
+  location ^~ /.well-known/acme-challenge/ {
+    alias /opt/piosphere/acme/challenges/;
+  }
+}"
+    );
+
+    // Names already served over HTTP get the challenges from their server
+    let mut config = NginxConfig::parse(
+        "server { listen 80; server_name example.org; location / { proxy_pass http://127.0.0.1:8000; } }",
+    )
+    .unwrap();
+    config.servers.push(tls_server());
+    config.servers[1].tls.as_mut().unwrap().issuer = Some(Issuer::Acme);
+
+    assert_eq!(config.add_https_redirects(), 0);
+    assert_eq!(config.serve_acme_challenges("/acme/"), 1);
+    assert_eq!(
+        config.servers[0].location[1].path,
+        "/.well-known/acme-challenge/"
+    );
+}
+
+#[test]
+fn acme_needs_names_to_prove() {
+    let mut server = tls_server();
+    server.server_name = vec!["*.example.org".parse().unwrap(), "_".parse().unwrap()];
+    server.tls.as_mut().unwrap().issuer = Some(Issuer::Acme);
+
+    let deployment = Deployment {
+        name: "app".to_string(),
+        nginx_cfgs: vec![NginxConfig {
+            servers: vec![server],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    let e = deployment.validate().unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "Validation failed: nginx_cfgs[0].servers[0].tls.issuer: \
+         `*.example.org` cannot be proven over HTTP, wildcards need a DNS challenge"
+    );
+
+    let mut deployment = deployment;
+    deployment.nginx_cfgs[0].servers[0].server_name = vec!["_".parse().unwrap()];
+    let e = deployment.validate().unwrap_err().to_string();
+    assert_eq!(
+        e,
+        "Validation failed: nginx_cfgs[0].servers[0].tls.issuer: \
+         a server name is needed to issue the certificate for"
+    );
+}
+
+/// Write a self-signed certificate for `example.org` valid during `year` and its key to `dir`.
+fn write_certificate(dir: &std::path::Path, year: i32) {
+    let mut params = CertificateParams::new(vec!["example.org".to_string()]).unwrap();
+    params.not_before = date_time_ymd(year, 1, 1);
+    params.not_after = date_time_ymd(year + 1, 1, 1);
+
+    let key = KeyPair::generate().unwrap();
+    let certificate = params.self_signed(&key).unwrap();
+
+    let dir = dir.join("etc/ssl/example.org");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("fullchain.pem"), certificate.pem()).unwrap();
+    std::fs::write(dir.join("privkey.pem"), key.serialize_pem()).unwrap();
+}
+
+#[test]
+fn certificates_are_checked_before_activation() {
+    let root = tempfile::tempdir().unwrap();
+    let config = NginxConfig {
+        servers: vec![tls_server()],
+        ..Default::default()
+    };
+    let resolve = |location: &str| root.path().join(location.trim_start_matches('/'));
+    let now = NaiveDate::from_ymd_opt(2030, 6, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+
+    let e = config
+        .check_certificates("nginx_cfg", resolve, now)
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Validation failed: \
+         nginx_cfg.servers[0].tls.certificate_key: `/etc/ssl/example.org/privkey.pem` does not exist; \
+         nginx_cfg.servers[0].tls.certificate: `/etc/ssl/example.org/fullchain.pem` does not exist"
+    );
+
+    write_certificate(root.path(), 2030);
+    config
+        .check_certificates("nginx_cfg", resolve, now)
+        .unwrap();
+
+    let info = CertificateInfo::read(&resolve("/etc/ssl/example.org/fullchain.pem")).unwrap();
+    assert_eq!(info.names, ["example.org"]);
+    assert_eq!(info.not_after.to_string(), "2031-01-01 00:00:00");
+
+    write_certificate(root.path(), 2028);
+    let e = config
+        .check_certificates("nginx_cfg", resolve, now)
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Validation failed: nginx_cfg.servers[0].tls.certificate: \
+         `/etc/ssl/example.org/fullchain.pem` expired on 2029-01-01 00:00:00"
+    );
+
+    std::fs::write(
+        resolve("/etc/ssl/example.org/fullchain.pem"),
+        "not a certificate",
+    )
+    .unwrap();
+    let e = config
+        .check_certificates("nginx_cfg", resolve, now)
+        .unwrap_err();
+    assert!(e.to_string().contains("is not a PEM certificate"), "{e}");
+}
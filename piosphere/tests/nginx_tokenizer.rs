@@ -0,0 +1,67 @@
+use piosphere::deployment::{nginx::ast::Document, parse::ParseError};
+
+fn error(input: &str) -> ParseError {
+    Document::parse(input).unwrap_err()
+}
+
+fn args(input: &str) -> Vec<String> {
+    let document = Document::parse(input).unwrap();
+    document.directives[0].args.clone()
+}
+
+#[test]
+fn unterminated_quotes_point_at_the_opening_quote() {
+    let e = error("return 200 \"ok;\n");
+    assert_eq!((e.line, e.column), (1, 12));
+    assert_eq!(e.message, "unterminated quoted string");
+    assert_eq!(e.expected.as_deref(), Some("a closing `\"`"));
+
+    let e = error("add_header X-Frame 'deny;\n}");
+    assert_eq!((e.line, e.column), (1, 20));
+    assert_eq!(e.expected.as_deref(), Some("a closing `'`"));
+
+    // A quote of the other kind does not close the string
+    let e = error("return 200 \"ok';");
+    assert_eq!(e.expected.as_deref(), Some("a closing `\"`"));
+
+    // Nor does an escaped one
+    let e = error("return 200 \"ok\\\";");
+    assert_eq!((e.line, e.column), (1, 12));
+}
+
+#[test]
+fn quoted_strings_keep_their_quotes_and_escapes() {
+    assert_eq!(
+        args("return 200 \"a \\\"b\\\" {c};\";"),
+        ["200", "\"a \\\"b\\\" {c};\""]
+    );
+    assert_eq!(args("add_header X 'it''s';"), ["X", "'it'", "'s'"]);
+}
+
+#[test]
+fn quotes_in_comments_are_ignored() {
+    let document = Document::parse("# don't \"quote\n listen 80; # it's {\n").unwrap();
+    assert_eq!(document.directives.len(), 1);
+    assert_eq!(document.directives[0].args, ["80"]);
+}
+
+#[test]
+fn unterminated_blocks_point_at_their_opening_brace() {
+    let e = error("http {\n  server {\n    listen 80;\n  }\n");
+    assert_eq!((e.line, e.column), (1, 6));
+    assert_eq!(e.message, "`http` block is missing its closing `}`");
+
+    let e = error("http {\n  server {\n    listen 80;\n");
+    assert_eq!((e.line, e.column), (2, 10));
+    assert_eq!(e.message, "`server` block is missing its closing `}`");
+
+    let e = error("{ listen 80; }");
+    assert_eq!((e.line, e.column), (1, 1));
+    assert_eq!(e.message, "unexpected `{`");
+}
+
+#[test]
+fn variables_keep_their_braces() {
+    assert_eq!(args("set $a ${b}c;"), ["$a", "${b}c"]);
+    assert_eq!(args("return 200 $a$b;"), ["200", "$a$b"]);
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::changed_lines;
+
+use piosphere::deployment::nginx::{
+    Balancing, NginxConfig, NginxLocation, NginxServer, NginxUpstream, UpstreamServer,
+};
+
+fn upstream_pool() -> (String, NginxConfig) {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/nginx/upstream_pool.conf"
+    ))
+    .unwrap();
+    let config = NginxConfig::parse(&input).unwrap();
+    (input, config)
+}
+
+#[test]
+fn upstream_blocks_are_mapped() {
+    let (_, config) = upstream_pool();
+
+    assert!(config.global.is_empty());
+    let [upstream] = &config.upstreams[..] else {
+        panic!("expected one upstream: {:?}", config.upstreams);
+    };
+
+    assert_eq!(upstream.name.as_str(), "api_pool");
+    assert_eq!(upstream.balancing, Balancing::LeastConn);
+    assert_eq!(upstream.keepalive, Some(32));
+
+    let first = &upstream.servers[0];
+    assert_eq!(first.address.as_str(), "10.0.0.11:9000");
+    assert_eq!(first.weight, Some(3));
+    assert_eq!(first.max_fails, Some(2));
+    assert_eq!(first.fail_timeout.as_ref().unwrap().as_str(), "10s");
+    assert!(!first.backup);
+    assert!(upstream.servers[2].backup);
+
+    let location = &config.servers[0].location[0];
+    let host = location.proxy_pass.as_ref().unwrap().host().unwrap();
+    assert_eq!(host, "api_pool");
+    assert!(config.upstream(host).is_some());
+}
+
+#[test]
+fn changing_a_server_only_changes_its_line() {
+    let (input, mut config) = upstream_pool();
+
+    let upstream = &mut config.upstreams[0];
+    upstream.servers[1].weight = Some(2);
+    upstream.servers[1].down = true;
+    let output = config.to_string();
+
+    assert_eq!(
+        changed_lines(&input, &output),
+        ["    server 10.0.0.12:9000 weight=2 down;"]
+    );
+}
+
+#[test]
+fn added_servers_follow_the_last_one() {
+    let (input, mut config) = upstream_pool();
+
+    let upstream = &mut config.upstreams[0];
+    upstream.balancing = Balancing::Hash {
+        key: "$request_uri".to_string(),
+        consistent: true,
+    };
+    upstream
+        .servers
+        .push(UpstreamServer::new("10.0.0.14:9000".parse().unwrap()));
+    let output = config.to_string();
+
+    assert_eq!(
+        changed_lines(&input, &output),
+        [
+            "    hash $request_uri consistent;",
+            "  server 10.0.0.14:9000;"
+        ]
+    );
+    assert!(output.contains("backup;\n  server 10.0.0.14:9000;\n    keepalive 32;"));
+}
+
+#[test]
+fn new_upstreams_come_first() {
+    let mut upstream = NginxUpstream::new(
+        "app".parse().unwrap(),
+        vec![
+            UpstreamServer {
+                weight: Some(5),
+                ..UpstreamServer::new("127.0.0.1:3000".parse().unwrap())
+            },
+            UpstreamServer::new("unix:/run/app.sock".parse().unwrap()),
+        ],
+    );
+    upstream.balancing = Balancing::IpHash;
+    upstream.keepalive = Some(16);
+
+    let mut location = NginxLocation::new();
+    location.proxy_to(&upstream);
+    location.directives.clear();
+
+    let server = NginxServer {
+        server_name: vec!["app.example.org".parse().unwrap()],
+        location: vec![location],
+        ..Default::default()
+    };
+    let config = NginxConfig {
+        servers: vec![server],
+        upstreams: vec![upstream],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        config.to_string(),
+        "upstream app {\n  ip_hash;\n  server 127.0.0.1:3000 weight=5;\n  server unix:/run/app.sock;\n  keepalive 16;\n}\n\
+         server {\n  listen 80;\n  server_name app.example.org;\n  location / {\n    proxy_pass http://app;\n  }\n}\n"
+    );
+}
+
+#[test]
+fn invalid_servers_are_rejected() {
+    for (input, error) in [
+        ("server 10.0.0.1:0;", "port must be between 1 and 65535"),
+        ("server 10.0.0.1 weight=heavy;", "`weight` must be a number"),
+        (
+            "server 10.0.0.1 fail_timeout=soon;",
+            "invalid duration `soon`",
+        ),
+    ] {
+        let config = format!("upstream app {{ {input} }}\nserver {{ listen 80; }}\n");
+        let e = NginxConfig::parse(&config).unwrap_err().to_string();
+        assert!(e.contains(error), "{input}: {e}");
+    }
+}
@@ -0,0 +1,106 @@
+use piosphere::deployment::{nginx::NginxConfig, parse::ParseError, systemd::SystemdConfig};
+
+fn nginx_error(input: &str) -> ParseError {
+    NginxConfig::parse(input).unwrap_err()
+}
+
+fn systemd_error(input: &str) -> ParseError {
+    SystemdConfig::parse(input).unwrap_err()
+}
+
+#[test]
+fn syntax_errors_point_at_the_token() {
+    let e = nginx_error("server {\n  listen 80;\n  server_name a.test\n}\n");
+    assert_eq!((e.line, e.column), (4, 1));
+    assert_eq!(e.message, "`server_name` is missing its terminating `;`");
+    assert_eq!(e.expected.as_deref(), Some("`;`"));
+    assert_eq!(e.snippet, "  |\n4 | }\n  | ^");
+
+    let e = nginx_error("server {\n  return 200 \"unterminated;\n}\n");
+    assert_eq!((e.line, e.column), (2, 14));
+    assert_eq!(e.expected.as_deref(), Some("a closing `\"`"));
+
+    let e = nginx_error("server { listen 80; }\n}\n");
+    assert_eq!((e.line, e.column), (2, 1));
+    assert_eq!(e.message, "unexpected `}`");
+
+    let e = nginx_error("server {\n  listen 80;\n  location / {\n    return 204;\n");
+    assert_eq!((e.line, e.column), (3, 14));
+    assert_eq!(e.message, "`location` block is missing its closing `}`");
+}
+
+#[test]
+fn missing_terminator_at_the_end_points_after_the_last_token() {
+    let e = nginx_error("server {\n  listen 80");
+    assert_eq!((e.line, e.column), (2, 12));
+    assert_eq!(e.snippet, "  |\n2 |   listen 80\n  |            ^");
+}
+
+#[test]
+fn invalid_values_point_at_the_argument() {
+    let e = nginx_error("server {\n\tlisten 80;\n\tserver_name a.test  b..test;\n}\n");
+    assert_eq!((e.line, e.column), (3, 22));
+    assert!(e.message.starts_with("Invalid `server_name`: "), "{e}");
+    assert_eq!(
+        e.snippet,
+        "  |\n3 | \tserver_name a.test  b..test;\n  | \t                    ^"
+    );
+
+    let e = nginx_error(
+        "server {\n  listen 80;\n  location / {\n    proxy_pass   localhost:8000;\n  }\n}\n",
+    );
+    assert_eq!((e.line, e.column), (4, 18));
+    assert!(e.message.starts_with("Invalid `proxy_pass`: "), "{e}");
+
+    let e = nginx_error("upstream app {\n  server 10.0.0.1 weight=heavy;\n}\n");
+    assert_eq!((e.line, e.column), (2, 10));
+}
+
+#[test]
+fn errors_render_with_their_file_and_snippet() {
+    let e = nginx_error("events {}\n").in_file("/etc/nginx/sites-enabled/app");
+
+    assert_eq!(
+        e.to_string(),
+        "/etc/nginx/sites-enabled/app:2:1: No `server` block found, expected a `server` block\n  |\n2 | \n  | ^"
+    );
+
+    let e = nginx_error("server {\n  listen 80;\n  ;\n}");
+    assert_eq!(
+        e.to_string(),
+        "line 3, column 3: unexpected `;`, expected a directive\n  |\n3 |   ;\n  |   ^"
+    );
+}
+
+#[test]
+fn systemd_lines_are_checked() {
+    let config = SystemdConfig::parse(
+        "# comment\n[Unit]\nDescription = app\n; another=comment\n\n[Service]\nExecStart=/bin/app\nEnvironment=PORT=8000\n",
+    )
+    .unwrap();
+    assert!(config.to_string().contains("Description=app\n"));
+    assert!(config.to_string().contains("Environment=PORT=8000\n"));
+
+    let e = systemd_error("[Unit]\nDescription=app\n[Timer]\nOnCalendar=daily\n");
+    assert_eq!((e.line, e.column), (3, 1));
+    assert_eq!(e.message, "unknown section `[Timer]`");
+    assert_eq!(
+        e.expected.as_deref(),
+        Some("`[Unit]`, `[Service]` or `[Install]`")
+    );
+
+    let e = systemd_error("[Service]\n  ExecStart /bin/app\n");
+    assert_eq!((e.line, e.column), (2, 21));
+    assert_eq!(e.message, "`ExecStart /bin/app` is not a parameter");
+
+    let e = systemd_error("ExecStart=/bin/app\n[Service]\n");
+    assert_eq!((e.line, e.column), (1, 1));
+    assert_eq!(
+        e.expected.as_deref(),
+        Some("a section header like `[Service]`")
+    );
+
+    let e = systemd_error("[Service\n");
+    assert_eq!((e.line, e.column), (1, 9));
+    assert_eq!(e.expected.as_deref(), Some("`]`"));
+}
@@ -0,0 +1,129 @@
+use piosphere::{
+    deployment::{
+        nginx::{NginxConfig, NginxLocation, NginxServer, StaticFiles},
+        systemd::SystemdConfig,
+        Deployment,
+    },
+    site::{SiteFile, SiteUpload},
+};
+use piosphere_testkit::TestServer;
+
+fn site() -> Deployment {
+    let server = NginxServer {
+        server_name: vec!["docs.test".parse().unwrap()],
+        location: vec![NginxLocation::serve(
+            "/",
+            StaticFiles::root("/var/www/docs"),
+        )],
+        ..Default::default()
+    };
+
+    Deployment::site(
+        "docs",
+        "docs deployment",
+        NginxConfig {
+            file_location: "/etc/nginx/sites-enabled/docs".to_string(),
+            servers: vec![server],
+            ..Default::default()
+        },
+    )
+}
+
+fn file(path: &str, contents: &str) -> SiteFile {
+    SiteFile {
+        path: path.to_string(),
+        contents: contents.as_bytes().to_vec(),
+    }
+}
+
+#[tokio::test]
+async fn static_sites_have_no_service() {
+    let root = tempfile::tempdir().unwrap();
+    let piosphere = TestServer::piosphere(root.path()).await;
+
+    let created = piosphere.create_deployment(site()).await.unwrap();
+
+    let vhost = std::fs::read_to_string(root.path().join("etc/nginx/sites-enabled/docs")).unwrap();
+    assert!(vhost.contains("root /var/www/docs;"), "{vhost}");
+    let units = std::fs::read_dir(root.path().join("etc/systemd/system")).unwrap();
+    assert_eq!(units.count(), 0);
+
+    let viewed = piosphere.view_deployment(&created.id).await.unwrap();
+    assert!(viewed.service_cfgs.is_empty());
+    let location = &viewed.nginx_cfgs[0].servers[0].location[0];
+    assert_eq!(location.files, Some(StaticFiles::root("/var/www/docs")));
+
+    let stats = piosphere.server_info().await.unwrap().db;
+    assert_eq!((stats.nginx_configs, stats.sysd_configs), (1, 0));
+
+    // A service can be added later
+    let mut updated = site();
+    updated.id = created.id.clone();
+    updated.service_cfgs = vec![SystemdConfig {
+        file_location: "/etc/systemd/system/docs.service".to_string(),
+        ..Default::default()
+    }];
+    piosphere.update_deployment(updated).await.unwrap();
+
+    let viewed = piosphere.view_deployment(&created.id).await.unwrap();
+    assert_eq!(viewed.service_cfgs.len(), 1);
+
+    let e = piosphere
+        .create_deployment(Deployment {
+            name: "empty".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Validation failed: nginx_cfgs: a vhost or a service is required"
+    );
+}
+
+#[tokio::test]
+async fn site_files_are_uploaded_to_served_directories() {
+    let root = tempfile::tempdir().unwrap();
+    let piosphere = TestServer::piosphere(root.path()).await;
+
+    let created = piosphere.create_deployment(site()).await.unwrap();
+
+    let written = piosphere
+        .upload_site(SiteUpload {
+            deployment_id: created.id.clone(),
+            dir: "/var/www/docs/".to_string(),
+            files: vec![
+                file("index.html", "<h1>Docs</h1>"),
+                file("assets/app.js", "console.log(1)"),
+            ],
+        })
+        .await
+        .unwrap();
+    assert_eq!(written, 2);
+
+    let docs = root.path().join("var/www/docs");
+    assert_eq!(
+        std::fs::read_to_string(docs.join("index.html")).unwrap(),
+        "<h1>Docs</h1>"
+    );
+    assert_eq!(
+        std::fs::read_to_string(docs.join("assets/app.js")).unwrap(),
+        "console.log(1)"
+    );
+
+    let e = piosphere
+        .upload_site(SiteUpload {
+            deployment_id: created.id,
+            dir: "/etc/nginx".to_string(),
+            files: vec![file("../passwd", ""), file("/etc/passwd", "")],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Validation failed: dir: `/etc/nginx` is not a directory a vhost of the deployment \
+         serves files from; \
+         files[0].path: `../passwd` must be a file relative to the directory, without `.` or `..`; \
+         files[1].path: `/etc/passwd` must be a file relative to the directory, without `.` or `..`"
+    );
+}
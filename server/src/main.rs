@@ -1,8 +1,5 @@
 use clap::Parser;
-use piosphere::{
-    db::PiosphereDatabase, socket::server::Server, PiosphereService, PITERIA_DB_FILE,
-    PITERIA_SOCKET,
-};
+use piosphere::{backend::DryRun, layer::RequestLog, Piosphere, PITERIA_DB_FILE, PITERIA_SOCKET};
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
@@ -12,43 +9,41 @@ use signal_hook::{
 async fn main() {
     let args = StartArgs::parse();
 
-    //let db = PiosphereDatabase::new(&args.db).await.unwrap(); // TODO
-    let db = PiosphereDatabase::new("piosphere.db").await.unwrap();
+    //let builder = Piosphere::builder().db(&args.db); // TODO
+    let mut builder = Piosphere::builder().db("piosphere.db").layer(RequestLog);
+
+    if args.dry_run {
+        builder = builder.service_manager(DryRun).nginx(DryRun);
+    }
 
     println!("Running migrations");
 
-    db.migrate().await.expect("error in migrations");
+    let piosphere = builder.build().await.expect("error in migrations");
 
     println!("Migrations successful");
 
-    let service = PiosphereService::new(db);
-
     println!("Starting server");
 
     let mut signals = Signals::new([SIGTERM, SIGINT]).unwrap();
 
-    let handle = Server::new(service, &args.socket);
+    let handle = piosphere
+        .serve(&args.socket)
+        .expect("could not listen on the socket");
 
-    let signals = tokio::spawn(async move {
-        for sig in signals.forever() {
-            println!("Received signal {:?}", sig);
-
-            if sig == SIGINT || sig == SIGTERM {
-                println!("Terminating server");
-                let result = handle.close().await;
-                return result;
-            }
-        }
-        unreachable!()
+    // Waiting for signals blocks, so it must not occupy one of the runtime workers
+    let signal = tokio::task::spawn_blocking(move || {
+        signals
+            .forever()
+            .find(|sig| *sig == SIGINT || *sig == SIGTERM)
     });
 
     println!("Server up and running");
 
-    // Should theoretically never happen since the signals task cannot panic
-    signals
-        .await
-        .expect("error while shutting down")
-        .expect("error while shutting down")
+    let sig = signal.await.expect("error while waiting for signals");
+    println!("Received signal {:?}", sig);
+
+    println!("Terminating server");
+    handle.close().await.expect("error while shutting down")
 }
 
 #[derive(Debug, Parser)]
@@ -58,4 +53,8 @@ struct StartArgs {
 
     #[arg(short, default_value=PITERIA_SOCKET)]
     socket: String,
+
+    /// Write config files without reloading systemd and nginx
+    #[arg(long)]
+    dry_run: bool,
 }
@@ -0,0 +1,73 @@
+use std::{
+    io,
+    os::{
+        fd::AsRawFd,
+        unix::{net::UnixListener, process::CommandExt},
+    },
+    process::{Command, Stdio},
+    sync::mpsc,
+    time::Duration,
+};
+
+use piosphere::socket::client::BlockingClient;
+
+/// The server is started the way systemd starts the service of a `.socket` unit,
+/// with the listening socket as fd 3 and `LISTEN_PID`/`LISTEN_FDS` set.
+#[test]
+fn uses_the_inherited_socket() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let socket = dir.path().join("activated.sock");
+    let listener = UnixListener::bind(&socket).unwrap();
+    let fd = listener.as_raw_fd();
+
+    // Only bound if the inherited socket is not picked up
+    let unused = dir.path().join("unused.sock");
+
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        // `exec` keeps the PID of the shell, which is the one the server has to see
+        .arg(r#"LISTEN_PID=$$ LISTEN_FDS=1 exec "$0" -s "$1" --dry-run"#)
+        .arg(env!("CARGO_BIN_EXE_server"))
+        .arg(&unused)
+        .current_dir(dir.path())
+        .stdout(Stdio::null());
+
+    // SAFETY: Only async-signal-safe calls between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            let res = if fd == 3 {
+                // `dup2` would leave the close-on-exec flag of the listener set
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, 3)
+            };
+            if res == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut server = command.spawn().unwrap();
+
+    // Connecting succeeds as soon as the socket is bound, the handshake waits for the server
+    let (tx, rx) = mpsc::channel();
+    let path = socket.display().to_string();
+    std::thread::spawn(move || {
+        let status = BlockingClient::new(&path).and_then(|mut client| client.server_info());
+        let _ = tx.send(status);
+    });
+    let status = rx.recv_timeout(Duration::from_secs(60));
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    let status = status
+        .expect("server did not respond")
+        .expect("error in request");
+
+    assert_eq!(status.socket, Some(socket.display().to_string()));
+    assert!(!unused.exists(), "the server bound its own socket");
+}
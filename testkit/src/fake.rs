@@ -0,0 +1,105 @@
+//! Backends recording the commands issued to them instead of running anything.
+
+use std::sync::{Arc, Mutex};
+
+use piosphere::{
+    backend::{NginxRunner, ServiceAction, ServiceManager},
+    error::PiosphereError,
+    PiosphereResult,
+};
+
+/// A command the service issued to one of its backends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `systemctl daemon-reload`
+    SystemdReload,
+
+    /// `systemctl <action> <unit>`
+    SystemdControl(ServiceAction, String),
+
+    /// `nginx -s reload`
+    NginxReload,
+}
+
+/// The commands issued so far, shared by the fakes and the [TestServer][crate::TestServer].
+#[derive(Debug, Clone, Default)]
+pub struct Commands {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    issued: Vec<Command>,
+
+    /// Commands failing with the message instead of being recorded.
+    failing: Vec<(Command, String)>,
+}
+
+impl Commands {
+    /// The commands issued so far, in order.
+    pub fn issued(&self) -> Vec<Command> {
+        self.lock().issued.clone()
+    }
+
+    /// The commands issued so far, in order, forgetting them.
+    pub fn take(&self) -> Vec<Command> {
+        std::mem::take(&mut self.lock().issued)
+    }
+
+    pub fn clear(&self) {
+        self.lock().issued.clear();
+    }
+
+    /// Make `command` fail with `message` until [succeed][Self::succeed] is called.
+    pub fn fail(&self, command: Command, message: &str) {
+        self.lock().failing.push((command, message.to_string()));
+    }
+
+    pub fn succeed(&self, command: Command) {
+        self.lock()
+            .failing
+            .retain(|(failing, _)| *failing != command);
+    }
+
+    fn issue(&self, command: Command) -> PiosphereResult<()> {
+        let mut state = self.lock();
+
+        if let Some((_, message)) = state
+            .failing
+            .iter()
+            .find(|(failing, _)| *failing == command)
+        {
+            return Err(PiosphereError::Backend(message.clone()));
+        }
+
+        state.issued.push(command);
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug)]
+pub struct FakeServiceManager(pub Commands);
+
+impl ServiceManager for FakeServiceManager {
+    fn reload(&self) -> PiosphereResult<()> {
+        self.0.issue(Command::SystemdReload)
+    }
+
+    fn control(&self, action: ServiceAction, unit: &str) -> PiosphereResult<()> {
+        self.0
+            .issue(Command::SystemdControl(action, unit.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub struct FakeNginx(pub Commands);
+
+impl NginxRunner for FakeNginx {
+    fn reload(&self) -> PiosphereResult<()> {
+        self.0.issue(Command::NginxReload)
+    }
+}
@@ -0,0 +1,217 @@
+//! Runs a piosphere server against a temporary directory for integration tests.
+//!
+//! The server uses an in-memory database and [fake backends][fake], all config files are
+//! written below [TestServer::root] and the socket is placed in the same directory.
+//!
+//! ```
+//! use piosphere_testkit::{fake::Command, TestServer};
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let server = TestServer::start().await;
+//!
+//! let deployment = server.deployment("app");
+//! server.client().create_deployment(deployment).await.unwrap();
+//!
+//! server.assert_file_contains("/etc/nginx/sites-enabled/app", "server_name app.test;");
+//! server.assert_commands(&[Command::SystemdReload, Command::NginxReload]);
+//!
+//! server.stop().await;
+//! # }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use fake::{Command, Commands, FakeNginx, FakeServiceManager};
+use piosphere::{
+    db::MEMORY,
+    deployment::{
+        nginx::{NginxConfig, NginxLocation, NginxServer},
+        systemd::SystemdConfig,
+        Deployment,
+    },
+    socket::{client::Client, server::Server},
+    Piosphere, PiosphereBuilder,
+};
+use tempfile::TempDir;
+
+pub mod fake;
+
+/// Directory of the vhosts created by [TestServer::deployment].
+pub const NGINX_DIR: &str = "/etc/nginx/sites-enabled";
+
+/// Directory of the units created by [TestServer::deployment].
+pub const SYSTEMD_DIR: &str = "/etc/systemd/system";
+
+pub struct TestServer {
+    /// Removed when the server is dropped.
+    dir: TempDir,
+    socket: String,
+    server: Server,
+    client: Client,
+    commands: Commands,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(|builder| builder).await
+    }
+
+    /// Start the server, letting `configure` add to the service, e.g. layers.
+    ///
+    /// The database, root and backends are set before `configure` is called.
+    pub async fn start_with(configure: impl FnOnce(PiosphereBuilder) -> PiosphereBuilder) -> Self {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let commands = Commands::default();
+
+        let piosphere = configure(Self::builder(dir.path(), &commands))
+            .build()
+            .await
+            .expect("could not build service");
+
+        let socket = dir.path().join("piosphere.sock").display().to_string();
+        let server = piosphere.serve(&socket).expect("could not serve");
+
+        let client = Client::new(&socket).await.expect("could not connect");
+
+        Self {
+            dir,
+            socket,
+            server,
+            client,
+            commands,
+        }
+    }
+
+    /// A service below `root` with an in-memory database, issuing its commands to fakes
+    /// recording them in `commands`. The directories of [NGINX_DIR] and [SYSTEMD_DIR] are
+    /// created, so services embedded in tests are set up like the one of the server.
+    pub fn builder(root: &Path, commands: &Commands) -> PiosphereBuilder {
+        for location in [NGINX_DIR, SYSTEMD_DIR] {
+            std::fs::create_dir_all(root.join(location.trim_start_matches('/')))
+                .expect("could not create config directories");
+        }
+
+        Piosphere::builder()
+            .db(MEMORY)
+            .root(root)
+            .service_manager(FakeServiceManager(commands.clone()))
+            .nginx(FakeNginx(commands.clone()))
+    }
+
+    /// A service set up by [builder][Self::builder], for tests which do not check the
+    /// issued commands.
+    pub async fn piosphere(root: &Path) -> Piosphere {
+        Self::builder(root, &Commands::default())
+            .build()
+            .await
+            .expect("could not build service")
+    }
+
+    /// The client connected when the server started.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Connect another client.
+    pub async fn connect(&self) -> Client {
+        Client::new(&self.socket).await.expect("could not connect")
+    }
+
+    pub fn socket(&self) -> &str {
+        &self.socket
+    }
+
+    /// The directory config file locations are relative to.
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Path of the file at the config file `location` of a deployment.
+    pub fn path(&self, location: &str) -> PathBuf {
+        self.root().join(location.trim_start_matches('/'))
+    }
+
+    /// A deployment named `name` serving `<name>.test`, with its files in [NGINX_DIR]
+    /// and [SYSTEMD_DIR].
+    pub fn deployment(&self, name: &str) -> Deployment {
+        let server = NginxServer {
+            server_name: vec![format!("{name}.test").parse().expect("invalid name")],
+            location: vec![NginxLocation::new()],
+            ..Default::default()
+        };
+
+        let nginx = NginxConfig {
+            file_location: format!("{NGINX_DIR}/{name}"),
+            servers: vec![server],
+            ..Default::default()
+        };
+
+        let sysd = SystemdConfig {
+            file_location: format!("{SYSTEMD_DIR}/{name}.service"),
+            ..Default::default()
+        };
+
+        Deployment::new(name, &format!("{name} deployment"), nginx, sysd)
+    }
+
+    /// A deployment named `name` only running a service, with its unit in [SYSTEMD_DIR].
+    pub fn worker(&self, name: &str) -> Deployment {
+        let sysd = SystemdConfig {
+            file_location: format!("{SYSTEMD_DIR}/{name}.service"),
+            ..Default::default()
+        };
+
+        Deployment::service(name, &format!("{name} deployment"), sysd)
+    }
+
+    /// Contents of the file at the config file `location`.
+    pub fn read_file(&self, location: &str) -> String {
+        let path = self.path(location);
+        std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("could not read {}: {e}", path.display()))
+    }
+
+    #[track_caller]
+    pub fn assert_file(&self, location: &str, expected: &str) {
+        assert_eq!(self.read_file(location), expected, "contents of {location}");
+    }
+
+    #[track_caller]
+    pub fn assert_file_contains(&self, location: &str, expected: &str) {
+        let contents = self.read_file(location);
+        assert!(
+            contents.contains(expected),
+            "{location} does not contain {expected:?}:\n{contents}"
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_no_file(&self, location: &str) {
+        assert!(!self.path(location).exists(), "{location} exists");
+    }
+
+    /// The commands issued by the service, see [Commands].
+    pub fn commands(&self) -> &Commands {
+        &self.commands
+    }
+
+    /// Assert the service issued exactly `expected` since the start or the last call,
+    /// and forget them.
+    #[track_caller]
+    pub fn assert_commands(&self, expected: &[Command]) {
+        assert_eq!(self.commands.take(), expected, "issued commands");
+    }
+
+    /// Close the client and shut the server down.
+    pub async fn stop(self) {
+        self.client
+            .close()
+            .await
+            .expect("error while closing client");
+        self.server
+            .close()
+            .await
+            .expect("error while shutting down server");
+    }
+}
@@ -0,0 +1,64 @@
+use piosphere::{backend::ServiceAction, error::PiosphereError};
+use piosphere_testkit::{fake::Command, TestServer};
+
+#[tokio::test]
+async fn deployments_are_written_and_reloaded() {
+    let server = TestServer::start().await;
+
+    let created = server
+        .client()
+        .create_deployment(server.deployment("app"))
+        .await
+        .unwrap();
+
+    server.assert_file_contains("/etc/nginx/sites-enabled/app", "server_name app.test;");
+    server.assert_file_contains("/etc/systemd/system/app.service", "[Service]");
+    server.assert_commands(&[Command::SystemdReload, Command::NginxReload]);
+
+    assert!(server
+        .client()
+        .delete_deployment(&created.id)
+        .await
+        .unwrap());
+
+    server.assert_no_file("/etc/nginx/sites-enabled/app");
+    server.assert_no_file("/etc/systemd/system/app.service");
+    server.assert_commands(&[
+        Command::SystemdControl(ServiceAction::Stop, "app.service".to_string()),
+        Command::SystemdReload,
+        Command::NginxReload,
+    ]);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn failing_commands_revert_the_deployment() {
+    let server = TestServer::start().await;
+    server
+        .commands()
+        .fail(Command::NginxReload, "invalid vhost");
+
+    let e = server
+        .client()
+        .create_deployment(server.deployment("app"))
+        .await
+        .unwrap_err();
+    assert!(matches!(e, PiosphereError::Backend(_)), "{e:?}");
+
+    server.assert_no_file("/etc/nginx/sites-enabled/app");
+    server.assert_no_file("/etc/systemd/system/app.service");
+    // Failing commands are not recorded, systemd reloads again after the revert
+    server.assert_commands(&[Command::SystemdReload, Command::SystemdReload]);
+
+    server.commands().succeed(Command::NginxReload);
+    server
+        .client()
+        .create_deployment(server.worker("worker"))
+        .await
+        .unwrap();
+    assert_eq!(server.client().overview().await.unwrap().len(), 1);
+    server.assert_commands(&[Command::SystemdReload]);
+
+    server.stop().await;
+}